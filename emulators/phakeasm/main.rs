@@ -0,0 +1,70 @@
+use argh::FromArgs;
+use std::fs;
+use std::process;
+
+use phakebit::asm;
+use phakebit::cpu::Variant;
+
+#[derive(FromArgs)]
+/// Assembler for 6502 source files
+struct CLIParams {
+    /// path of the source file
+    #[argh(positional)]
+    source_path: String,
+
+    /// path for the assembled binary
+    #[argh(option, short = 'o')]
+    output_path: String,
+
+    /// output format: raw (default) for the bytes only, or prg to prefix them
+    /// with the little endian load address
+    #[argh(option, short = 'f', default = "String::from(\"raw\")")]
+    format: String,
+
+    /// target CPU: 6502 (default), 6510, 2a03 or 65c02
+    #[argh(option, short = 'c', default = "String::from(\"6502\")")]
+    cpu: String,
+
+    /// print the symbol table
+    #[argh(switch, short = 's')]
+    symbols: bool,
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+pub fn main() {
+    let params: CLIParams = argh::from_env();
+    let variant = match params.cpu.to_lowercase().as_str() {
+        "6502" => Variant::MOS6502,
+        "6510" => Variant::MOS6510,
+        "2a03" => Variant::Ricoh2A03,
+        "65c02" => Variant::WDC65C02,
+        cpu => fail(format!("Unknown CPU {}", cpu)),
+    };
+
+    let source = fs::read_to_string(&params.source_path)
+        .unwrap_or_else(|e| fail(format!("Unable to read {}: {}", params.source_path, e)));
+    let program = asm::assemble(&source, variant)
+        .unwrap_or_else(|e| fail(format!("{}:{}", params.source_path, e)));
+
+    let output = match params.format.as_str() {
+        "raw" => program.bytes.clone(),
+        "prg" => {
+            let mut output = program.origin.to_le_bytes().to_vec();
+            output.extend(&program.bytes);
+            output
+        }
+        format => fail(format!("Unknown output format {}", format)),
+    };
+    fs::write(&params.output_path, output)
+        .unwrap_or_else(|e| fail(format!("Unable to write {}: {}", params.output_path, e)));
+
+    if params.symbols {
+        for (name, value) in &program.symbols {
+            println!("{:04X} {}", value, name);
+        }
+    }
+}
@@ -1,5 +1,4 @@
 mod emulator;
-mod memory;
 mod pia;
 mod terminal;
 
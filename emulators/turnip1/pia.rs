@@ -1,65 +1,76 @@
 use std::sync::mpsc;
 
-pub struct PIAChip {
-    kbd_data: u8,
-    kbd_control: u8,
-    display_data: u8,
-    display_control: u8,
+use phakebit::pia::{ControlLines, PiaPorts};
 
-    rx: mpsc::Receiver<u8>,
-    tx: mpsc::Sender<u8>,
+/// The Apple-1 keyboard on port A and display on port B of its PIA
+pub struct Apple1Ports {
+    kbd_rx: mpsc::Receiver<u8>,
+    dsp_tx: mpsc::Sender<u8>,
+
+    key: u8,
+    /// A key was strobed and hasn't been read yet
+    key_pending: bool,
+    /// Character on the display data lines
+    display: u8,
+    /// The display took a character and signals it is ready on CB1
+    display_ready: bool,
 }
 
-impl PIAChip {
-    pub fn new(rx: mpsc::Receiver<u8>, tx: mpsc::Sender<u8>) -> PIAChip {
-        PIAChip {
-            kbd_data: 0,
-            kbd_control: 0,
-            display_data: 0,
-            display_control: 0,
-            rx,
-            tx,
+impl Apple1Ports {
+    pub fn new(kbd_rx: mpsc::Receiver<u8>, dsp_tx: mpsc::Sender<u8>) -> Apple1Ports {
+        Apple1Ports {
+            kbd_rx,
+            dsp_tx,
+            key: 0,
+            key_pending: false,
+            display: 0,
+            display_ready: false,
         }
     }
-    pub fn read(&mut self, address: u16) -> u8 {
-        match address {
-            0xD010 => {
-                let data = self.kbd_data;
-                self.kbd_data = 0;
-                data
-            }
-            0xD011 => {
-                let mut c = 0;
-                if self.kbd_data != 0 {
-                    c = 0xFF;
-                } else {
-                    if let Ok(v) = self.rx.try_recv() {
-                        self.kbd_data = v;
-                        c = 0xFF;
-                    }
-                }
-                c
-            }
-            0xD012 => self.display_data,
-            0xD013 => self.display_control,
-            _ => 0,
+}
+
+impl PiaPorts for Apple1Ports {
+    fn read_a(&mut self) -> u8 {
+        self.key_pending = false;
+        self.key
+    }
+
+    /// PB7 low: the display is never busy
+    fn read_b(&mut self) -> u8 {
+        0x00
+    }
+
+    fn write_b(&mut self, value: u8) {
+        self.display = value;
+    }
+
+    /// The display takes the character on the CB2 write strobe
+    fn set_cb2(&mut self, level: bool) {
+        if !level {
+            self.dsp_tx.send(self.display).unwrap();
+            self.display_ready = true;
         }
     }
-    pub fn write(&mut self, address: u16, value: u8) {
-        match address {
-            0xD010 => self.kbd_data = value,
-            0xD011 => {
-                self.kbd_control = value;
-            }
-            0xD012 => {
-                self.display_data = value;
-                self.tx.send(value).unwrap();
-                self.display_data = 0;
-            }
-            0xD013 => {
-                self.display_control = value;
+
+    /// Both strobes idle high and pulse low, flagging the PIA on the rising
+    /// edge
+    fn update_lines(&mut self, lines: &mut ControlLines) {
+        // Keys are held until the PIA reads them, so pasted input isn't lost
+        if !lines.ca1 {
+            lines.ca1 = true;
+        } else if !self.key_pending {
+            if let Ok(key) = self.kbd_rx.try_recv() {
+                self.key = key;
+                self.key_pending = true;
+                lines.ca1 = false;
             }
-            _ => (),
+        }
+
+        if !lines.cb1 {
+            lines.cb1 = true;
+        } else if self.display_ready {
+            self.display_ready = false;
+            lines.cb1 = false;
         }
     }
 }
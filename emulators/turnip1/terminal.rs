@@ -18,7 +18,7 @@ impl Terminal {
 
         thread::spawn(move || loop {
             let mut c = rx.recv().unwrap();
-            c = c & 0x7F; // strip high bit
+            c &= 0x7F; // strip high bit
             if c == 0x0D {
                 // CR -> LF
                 c = 0x0A;
@@ -43,7 +43,7 @@ impl Terminal {
                         // LF -> CR
                         c = 0x0D;
                     }
-                    c = c | 0x80;
+                    c |= 0x80;
                     tx.send(c).unwrap();
                 }
             }
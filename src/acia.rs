@@ -0,0 +1,483 @@
+//! MOS 6551 Asynchronous Communications Interface Adapter. The data, status,
+//! command and control registers are mapped with `MappedDevice`, and the
+//! `Device` implementation moves characters at the programmed baud rate.
+//!
+//! The serial side is a `SerialPort`: `BufferSerial` keeps the data in memory
+//! for tests, and `StreamSerial` bridges to host streams like stdin/stdout,
+//! a TCP socket or a pseudo-terminal.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::device::Device;
+use crate::memory::MappedDevice;
+use crate::savestate::SaveStateError;
+
+/// Register offsets
+pub const DATA: u16 = 0x0;
+pub const STATUS: u16 = 0x1;
+pub const COMMAND: u16 = 0x2;
+pub const CONTROL: u16 = 0x3;
+
+/// Status register bits
+pub const STATUS_OVERRUN: u8 = 0x04;
+pub const STATUS_RDRF: u8 = 0x08;
+pub const STATUS_TDRE: u8 = 0x10;
+pub const STATUS_IRQ: u8 = 0x80;
+
+/// Baud rates selected by the low bits of the control register. Rate 0 is
+/// the 16x external clock, which is 115200 baud with the usual 1.8432 MHz
+/// crystal.
+const BAUD_RATES: [f64; 16] = [
+    115200.0, 50.0, 75.0, 109.92, 134.58, 150.0, 300.0, 600.0, 1200.0, 1800.0, 2400.0, 3600.0,
+    4800.0, 7200.0, 9600.0, 19200.0,
+];
+
+/// Length of the save state of an `Acia`
+const STATE_LEN: usize = 24;
+
+/// Serial side of an `Acia`
+pub trait SerialPort {
+    /// Next byte received from the other end, if one is waiting. Must not
+    /// block.
+    fn receive(&mut self) -> Option<u8>;
+    /// Send a byte to the other end
+    fn transmit(&mut self, byte: u8);
+}
+
+/// Serial port backed by memory buffers
+#[derive(Clone, Debug, Default)]
+pub struct BufferSerial {
+    /// Bytes to be received by the ACIA
+    pub input: VecDeque<u8>,
+    /// Bytes transmitted by the ACIA
+    pub output: Vec<u8>,
+}
+
+impl BufferSerial {
+    pub fn new() -> BufferSerial {
+        BufferSerial::default()
+    }
+
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+}
+
+impl SerialPort for BufferSerial {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        self.output.push(byte);
+    }
+}
+
+/// Serial port bridged to host streams. The reading side runs on a
+/// background thread so that receiving never blocks the emulation.
+pub struct StreamSerial {
+    rx: mpsc::Receiver<u8>,
+    writer: Box<dyn Write>,
+    error: Option<io::Error>,
+}
+
+impl StreamSerial {
+    pub fn new<R: Read + Send + 'static, W: Write + 'static>(reader: R, writer: W) -> StreamSerial {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = reader;
+            let mut buf = [0; 256];
+            loop {
+                let len = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => len,
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                };
+                if buf[..len].iter().any(|&byte| tx.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+        StreamSerial {
+            rx,
+            writer: Box::new(writer),
+            error: None,
+        }
+    }
+
+    /// Connect to stdin and stdout of the process
+    pub fn stdio() -> StreamSerial {
+        StreamSerial::new(io::stdin(), io::stdout())
+    }
+
+    /// Connect to a TCP socket
+    pub fn tcp(stream: TcpStream) -> io::Result<StreamSerial> {
+        let reader = stream.try_clone()?;
+        Ok(StreamSerial::new(reader, stream))
+    }
+
+    /// Connect to a device file such as a pseudo-terminal, e.g. one end of a
+    /// pair created with `socat -d -d pty,raw,echo=0 pty,raw,echo=0`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<StreamSerial> {
+        let file: File = OpenOptions::new().read(true).write(true).open(path)?;
+        let reader = file.try_clone()?;
+        Ok(StreamSerial::new(reader, file))
+    }
+
+    /// First error writing to the stream, after which output is dropped
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl SerialPort for StreamSerial {
+    fn receive(&mut self) -> Option<u8> {
+        self.rx.try_recv().ok()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(error) = self
+            .writer
+            .write_all(&[byte])
+            .and_then(|_| self.writer.flush())
+        {
+            self.error = Some(error);
+        }
+    }
+}
+
+/// MOS 6551 ACIA
+pub struct Acia<S: SerialPort> {
+    serial: S,
+    /// CPU clock frequency, for converting the baud rate to cycles
+    clock_hz: u64,
+    status: u8,
+    command: u8,
+    control: u8,
+    /// Received byte
+    rdr: u8,
+    /// Byte waiting for the transmitter
+    tdr: Option<u8>,
+    /// Byte being shifted out and the cycles left until it is sent
+    shifting: Option<(u8, u64)>,
+    /// Cycles until the receiver can take the next character
+    rx_timer: u64,
+}
+
+impl<S: SerialPort> Acia<S> {
+    /// ACIA connected to `serial`, timed for a CPU running at `clock_hz`
+    pub fn new(serial: S, clock_hz: u64) -> Acia<S> {
+        let mut acia = Acia {
+            serial,
+            clock_hz,
+            status: 0,
+            command: 0,
+            control: 0,
+            rdr: 0,
+            tdr: None,
+            shifting: None,
+            rx_timer: 0,
+        };
+        acia.reset();
+        acia
+    }
+
+    /// Hardware reset
+    pub fn reset(&mut self) {
+        self.status = STATUS_TDRE;
+        self.command = 0x02;
+        self.control = 0;
+        self.tdr = None;
+        self.shifting = None;
+        self.rx_timer = self.character_cycles();
+    }
+
+    pub fn serial(&self) -> &S {
+        &self.serial
+    }
+
+    pub fn serial_mut(&mut self) -> &mut S {
+        &mut self.serial
+    }
+
+    /// Cycles to transfer one character at the programmed baud rate and
+    /// frame format
+    pub fn character_cycles(&self) -> u64 {
+        let data_bits = 8 - ((self.control >> 5) & 0x03) as u64;
+        let parity_bits = ((self.command >> 5) & 0x01) as u64;
+        let stop_bits = if self.control & 0x80 != 0 { 2 } else { 1 };
+        let bits = 1 + data_bits + parity_bits + stop_bits;
+        let baud = BAUD_RATES[(self.control & 0x0F) as usize];
+        ((self.clock_hz as f64 * bits as f64 / baud).round() as u64).max(1)
+    }
+
+    fn data_mask(&self) -> u8 {
+        0xFF >> ((self.control >> 5) & 0x03)
+    }
+
+    /// DTR enables the receiver and interrupts
+    fn receiver_enabled(&self) -> bool {
+        self.command & 0x01 != 0
+    }
+
+    fn receive_irq_enabled(&self) -> bool {
+        self.receiver_enabled() && self.command & 0x02 == 0
+    }
+
+    fn transmit_irq_enabled(&self) -> bool {
+        self.receiver_enabled() && (self.command >> 2) & 0x03 == 0b01
+    }
+
+    fn echo(&self) -> bool {
+        self.command & 0x10 != 0
+    }
+
+    /// Move the transmit data register to the shift register
+    fn start_transmit(&mut self) {
+        if self.shifting.is_some() {
+            return;
+        }
+        if let Some(byte) = self.tdr.take() {
+            self.shifting = Some((byte, self.character_cycles()));
+            self.status |= STATUS_TDRE;
+            if self.transmit_irq_enabled() {
+                self.status |= STATUS_IRQ;
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        let Some(byte) = self.serial.receive() else {
+            return;
+        };
+        let byte = byte & self.data_mask();
+        if self.echo() {
+            self.serial.transmit(byte);
+        }
+        if self.status & STATUS_RDRF != 0 {
+            // The previous character wasn't read and the new one is lost
+            self.status |= STATUS_OVERRUN;
+            return;
+        }
+        self.rdr = byte;
+        self.status |= STATUS_RDRF;
+        if self.receive_irq_enabled() {
+            self.status |= STATUS_IRQ;
+        }
+    }
+
+    fn advance(&mut self, cycles: u64) {
+        let mut cycles = cycles;
+        while cycles > 0 {
+            let shift_left = self.shifting.map_or(u64::MAX, |(_, left)| left);
+            let rx_left = if self.receiver_enabled() {
+                self.rx_timer
+            } else {
+                u64::MAX
+            };
+            let step = cycles.min(shift_left).min(rx_left);
+            cycles -= step;
+
+            if let Some((byte, left)) = self.shifting {
+                if left == step {
+                    self.shifting = None;
+                    self.serial.transmit(byte);
+                    self.start_transmit();
+                } else {
+                    self.shifting = Some((byte, left - step));
+                }
+            }
+            if self.receiver_enabled() {
+                self.rx_timer -= step;
+                if self.rx_timer == 0 {
+                    self.rx_timer = self.character_cycles();
+                    self.receive();
+                }
+            }
+        }
+    }
+}
+
+impl<S: SerialPort> MappedDevice for Acia<S> {
+    fn read(&mut self, offset: u16) -> u8 {
+        let value = self.peek(offset);
+        match offset & 0x03 {
+            DATA => self.status &= !(STATUS_RDRF | STATUS_OVERRUN | 0x03),
+            STATUS => self.status &= !STATUS_IRQ,
+            _ => (),
+        }
+        value
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset & 0x03 {
+            DATA => {
+                self.tdr = Some(value & self.data_mask());
+                self.status &= !STATUS_TDRE;
+                self.start_transmit();
+            }
+            // Programmed reset
+            STATUS => {
+                self.command &= 0xE0;
+                self.status &= !STATUS_OVERRUN;
+            }
+            COMMAND => self.command = value,
+            CONTROL => {
+                self.control = value;
+                self.rx_timer = self.character_cycles();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        match offset & 0x03 {
+            DATA => self.rdr,
+            STATUS => self.status,
+            COMMAND => self.command,
+            CONTROL => self.control,
+            _ => unreachable!(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "6551 ACIA"
+    }
+
+    /// Registers and timing. Data on the serial side isn't saved.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![
+            self.status,
+            self.command,
+            self.control,
+            self.rdr,
+            self.tdr.is_some() as u8,
+            self.tdr.unwrap_or(0),
+            self.shifting.is_some() as u8,
+            self.shifting.map_or(0, |(byte, _)| byte),
+        ];
+        state.extend_from_slice(&self.shifting.map_or(0, |(_, left)| left).to_le_bytes());
+        state.extend_from_slice(&self.rx_timer.to_le_bytes());
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        if state.len() != STATE_LEN {
+            return Err(SaveStateError::Invalid(format!(
+                "expected {} bytes of ACIA state, got {}",
+                STATE_LEN,
+                state.len()
+            )));
+        }
+        let word = |index: usize| u64::from_le_bytes(state[index..index + 8].try_into().unwrap());
+        self.status = state[0];
+        self.command = state[1];
+        self.control = state[2];
+        self.rdr = state[3];
+        self.tdr = (state[4] != 0).then_some(state[5]);
+        self.shifting = (state[6] != 0).then_some((state[7], word(8)));
+        self.rx_timer = word(16);
+        Ok(())
+    }
+}
+
+impl<S: SerialPort> Device for Acia<S> {
+    fn tick(&mut self, cycles: u64) {
+        self.advance(cycles);
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        let shift = self.shifting.map(|(_, left)| left);
+        let rx = self.receiver_enabled().then_some(self.rx_timer);
+        shift.into_iter().chain(rx).min()
+    }
+
+    fn irq(&self) -> bool {
+        self.status & STATUS_IRQ != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1 MHz CPU, 9600 baud, 8N1
+    fn setup() -> Acia<BufferSerial> {
+        let mut acia = Acia::new(BufferSerial::new(), 1_000_000);
+        acia.write(CONTROL, 0x1E);
+        acia.write(COMMAND, 0x09);
+        acia
+    }
+
+    #[test]
+    fn test_transmit() {
+        let mut acia = setup();
+        // 10 bits at 9600 baud
+        assert_eq!(acia.character_cycles(), 1042);
+
+        acia.write(DATA, b'H');
+        // Moved straight to the shift register
+        assert_eq!(acia.peek(STATUS) & STATUS_TDRE, STATUS_TDRE);
+        acia.write(DATA, b'i');
+        assert_eq!(acia.peek(STATUS) & STATUS_TDRE, 0);
+
+        acia.tick(1041);
+        assert!(acia.serial().output.is_empty());
+        acia.tick(1);
+        assert_eq!(acia.serial().output, b"H");
+        assert_eq!(acia.peek(STATUS) & STATUS_TDRE, STATUS_TDRE);
+        acia.tick(1042);
+        assert_eq!(acia.serial().output, b"Hi");
+    }
+
+    #[test]
+    fn test_receive() {
+        let mut acia = setup();
+        acia.serial_mut().push_input(b"abc");
+        assert!(!acia.irq());
+
+        acia.tick(1042);
+        assert!(acia.irq());
+        assert_eq!(acia.read(STATUS), STATUS_IRQ | STATUS_TDRE | STATUS_RDRF);
+        assert!(!acia.irq());
+        assert_eq!(acia.read(DATA), b'a');
+        assert_eq!(acia.peek(STATUS) & STATUS_RDRF, 0);
+
+        // 'c' overruns the unread 'b'
+        acia.tick(2 * 1042);
+        assert_eq!(acia.read(STATUS) & STATUS_OVERRUN, STATUS_OVERRUN);
+        assert_eq!(acia.read(DATA), b'b');
+        assert_eq!(acia.peek(STATUS) & STATUS_OVERRUN, 0);
+
+        // Programmed reset drops DTR, which stops the receiver
+        acia.serial_mut().push_input(b"d");
+        acia.write(STATUS, 0);
+        acia.tick(5000);
+        assert_eq!(acia.peek(STATUS) & STATUS_RDRF, 0);
+        assert_eq!(acia.next_event(), None);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut acia = setup();
+        acia.write(DATA, b'x');
+        acia.tick(100);
+        let state = acia.save_state();
+
+        let mut restored = Acia::new(BufferSerial::new(), 1_000_000);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.peek(CONTROL), 0x1E);
+        restored.tick(942);
+        assert_eq!(restored.serial().output, b"x");
+    }
+}
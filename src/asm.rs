@@ -0,0 +1,859 @@
+//! Two-pass assembler for 6502 source text.
+//!
+//! The syntax is the usual one:
+//!
+//! ```text
+//! ; comment
+//! SCREEN = $0400          ; constant
+//!         .org $0600      ; set the address of the following code
+//! start:  LDX #0          ; label and immediate operand
+//! @loop:  LDA message,X   ; local label, only visible until the next label
+//!         BEQ @done
+//!         STA SCREEN,X
+//!         INX
+//!         BNE @loop
+//! @done:  JMP *
+//! message:
+//!         .byte "HELLO", 0
+//!         .word start, >message, <message + 1
+//! ```
+//!
+//! Numbers are decimal, `$` hexadecimal, `%` binary or `'c'` characters.
+//! Expressions can use `+ - * / % & | ^ << >>`, parentheses, unary `-` and
+//! `~`, and `<` and `>` for the low and high byte. `*` is the address of the
+//! current instruction. Operands that fit in a byte and are known on the
+//! first pass use zero page addressing.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::Variant;
+use crate::instruction;
+use crate::instruction::AddressingMode;
+use crate::instruction::Instruction;
+use crate::instruction::Operation;
+
+/// Result of assembling a program
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    /// Address of the first byte
+    pub origin: u16,
+    /// Assembled bytes, gaps left by `.org` are filled with zeroes
+    pub bytes: Vec<u8>,
+    /// Labels and constants. Local labels are named `label@local`.
+    pub symbols: BTreeMap<String, u16>,
+}
+
+/// Error in the source, with the 1-based line number
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assemble `source` for `variant`. Undocumented opcodes are not
+/// available.
+pub fn assemble(source: &str, variant: Variant) -> Result<Program, AsmError> {
+    let mut assembler = Assembler::new(variant);
+    assembler.pass(source, false)?;
+    assembler.pass(source, true)?;
+
+    Ok(Program {
+        origin: assembler.origin.unwrap_or(0),
+        bytes: assembler.output,
+        symbols: assembler
+            .symbols
+            .into_iter()
+            .map(|(name, value)| (name, value as u16))
+            .collect(),
+    })
+}
+
+struct Assembler {
+    instructions: Vec<Instruction>,
+    mnemonics: HashMap<String, Operation>,
+    symbols: BTreeMap<String, i64>,
+    /// Addressing modes chosen on the first pass, one per instruction
+    modes: Vec<AddressingMode>,
+    final_pass: bool,
+    pc: u32,
+    scope: String,
+    origin: Option<u16>,
+    output: Vec<u8>,
+}
+
+/// Errors inside a line are plain messages, the line number is added by
+/// `Assembler::pass`
+type LineResult<T> = Result<T, String>;
+
+impl Assembler {
+    fn new(variant: Variant) -> Assembler {
+        // Documented NMOS encodings first so they win over 65C02 aliases
+        let mut instructions: Vec<Instruction> = (0..=0xFF)
+            .filter_map(instruction::opcode_to_instruction)
+            .collect();
+        if variant == Variant::WDC65C02 {
+            instructions.extend((0..=0xFF).map(instruction::wdc65c02_opcode_to_instruction));
+        }
+        let mnemonics = instructions
+            .iter()
+            .map(|instruction| {
+                (
+                    format!("{:?}", instruction.operation),
+                    instruction.operation,
+                )
+            })
+            .collect();
+
+        Assembler {
+            instructions,
+            mnemonics,
+            symbols: BTreeMap::new(),
+            modes: Vec::new(),
+            final_pass: false,
+            pc: 0,
+            scope: String::new(),
+            origin: None,
+            output: Vec::new(),
+        }
+    }
+
+    fn pass(&mut self, source: &str, final_pass: bool) -> Result<(), AsmError> {
+        self.final_pass = final_pass;
+        self.pc = 0;
+        self.scope = String::new();
+        let mut instruction_index = 0;
+        for (number, line) in source.lines().enumerate() {
+            self.line(line, &mut instruction_index)
+                .map_err(|message| AsmError {
+                    line: number + 1,
+                    message,
+                })?;
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str, instruction_index: &mut usize) -> LineResult<()> {
+        let mut rest = strip_comment(line).trim();
+
+        // Label
+        let name_length = symbol_length(rest);
+        if name_length > 0 && rest[name_length..].starts_with(':') {
+            let name = &rest[..name_length];
+            if !name.starts_with('@') {
+                self.scope = name.to_string();
+            }
+            let value = self.pc as i64;
+            self.define(name, value)?;
+            rest = rest[name_length + 1..].trim();
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        // Constant
+        let name_length = symbol_length(rest);
+        if name_length > 0 && rest[name_length..].trim_start().starts_with('=') {
+            let name = &rest[..name_length];
+            let expression = rest[name_length..].trim_start()[1..].trim();
+            if let Some(value) = self.evaluate(expression)? {
+                self.define(name, value)?;
+            }
+            return Ok(());
+        }
+
+        if let Some(directive) = rest.strip_prefix('.') {
+            return self.directive(directive);
+        }
+
+        let (mnemonic, operand) = match rest.find(char::is_whitespace) {
+            Some(index) => (&rest[..index], rest[index..].trim()),
+            None => (rest, ""),
+        };
+        let operation = *self
+            .mnemonics
+            .get(&mnemonic.to_uppercase())
+            .ok_or_else(|| format!("Unknown instruction {}", mnemonic))?;
+        self.instruction(operation, operand, instruction_index)
+    }
+
+    fn directive(&mut self, directive: &str) -> LineResult<()> {
+        let (name, arguments) = match directive.find(char::is_whitespace) {
+            Some(index) => (&directive[..index], directive[index..].trim()),
+            None => (directive, ""),
+        };
+        match name.to_lowercase().as_str() {
+            "org" => {
+                let value = self
+                    .evaluate(arguments)?
+                    .ok_or("Address of .org must be known on the first pass")?;
+                self.pc = check_range(value, 0, 0xFFFF)? as u32;
+            }
+            "byte" => {
+                for argument in split_arguments(arguments) {
+                    if let Some(text) = argument.strip_prefix('"') {
+                        let text = text.strip_suffix('"').ok_or("Unterminated string")?;
+                        for byte in text.bytes() {
+                            self.emit(byte)?;
+                        }
+                    } else {
+                        let value = self.evaluate(argument)?.unwrap_or(0);
+                        self.emit(check_range(value, -0x80, 0xFF)? as u8)?;
+                    }
+                }
+            }
+            "word" => {
+                for argument in split_arguments(arguments) {
+                    let value = self.evaluate(argument)?.unwrap_or(0);
+                    let value = check_range(value, -0x8000, 0xFFFF)? as u16;
+                    self.emit_word(value)?;
+                }
+            }
+            _ => return Err(format!("Unknown directive .{}", name)),
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        operation: Operation,
+        operand: &str,
+        instruction_index: &mut usize,
+    ) -> LineResult<()> {
+        let (mode, values) = self.parse_operand(operation, operand)?;
+        let mode = if self.final_pass {
+            self.modes[*instruction_index]
+        } else {
+            self.modes.push(mode);
+            mode
+        };
+        *instruction_index += 1;
+
+        let opcode = self
+            .opcode(operation, mode)
+            .ok_or_else(|| format!("{:?} doesn't support {:?} addressing", operation, mode))?;
+        let address = self.pc as i64;
+        self.emit(opcode)?;
+
+        let value = values[0];
+        match mode {
+            AddressingMode::ACC | AddressingMode::IMPL => (),
+            AddressingMode::IMM => self.emit(check_range(value, -0x80, 0xFF)? as u8)?,
+            AddressingMode::ABS
+            | AddressingMode::ABSX
+            | AddressingMode::ABSY
+            | AddressingMode::IND
+            | AddressingMode::ABSXIND => self.emit_word(check_range(value, 0, 0xFFFF)? as u16)?,
+            AddressingMode::REL => {
+                self.emit(self.branch_offset(value, address + 2)?)?;
+            }
+            AddressingMode::ZPREL => {
+                self.emit(check_range(value, 0, 0xFF)? as u8)?;
+                self.emit(self.branch_offset(values[1], address + 3)?)?;
+            }
+            _ => self.emit(check_range(value, 0, 0xFF)? as u8)?,
+        }
+        Ok(())
+    }
+
+    /// Addressing mode and operand values. Unknown values are zero.
+    fn parse_operand(
+        &self,
+        operation: Operation,
+        operand: &str,
+    ) -> LineResult<(AddressingMode, [i64; 2])> {
+        if operand.is_empty() {
+            let mode = if self.opcode(operation, AddressingMode::IMPL).is_some() {
+                AddressingMode::IMPL
+            } else {
+                AddressingMode::ACC
+            };
+            return Ok((mode, [0, 0]));
+        }
+        if operand.eq_ignore_ascii_case("A") {
+            return Ok((AddressingMode::ACC, [0, 0]));
+        }
+        if let Some(expression) = operand.strip_prefix('#') {
+            let value = self.evaluate(expression)?;
+            return Ok((AddressingMode::IMM, [value.unwrap_or(0), 0]));
+        }
+        if self.opcode(operation, AddressingMode::ZPREL).is_some() {
+            let arguments = split_arguments(operand);
+            let [zero_page, target] = arguments[..] else {
+                return Err("Expected zero page address and branch target".to_string());
+            };
+            let zero_page = self.evaluate(zero_page)?.unwrap_or(0);
+            let target = self.evaluate(target)?.unwrap_or(0);
+            return Ok((AddressingMode::ZPREL, [zero_page, target]));
+        }
+
+        // Indirect modes, unless the parentheses are part of an expression
+        if operand.starts_with('(') {
+            let close = matching_paren(operand).ok_or("Unbalanced parentheses")?;
+            let inner = operand[1..close].trim();
+            let after = operand[close + 1..].trim();
+            let indexed_inner = index_suffix(inner);
+            let modes = match (
+                indexed_inner,
+                after.to_uppercase().replace(' ', "").as_str(),
+            ) {
+                (None, "") => Some((inner, [AddressingMode::IND, AddressingMode::ZPIND])),
+                (Some((inner, 'X')), "") => {
+                    Some((inner, [AddressingMode::ABSXIND, AddressingMode::XIND]))
+                }
+                (None, ",Y") => Some((inner, [AddressingMode::INDY, AddressingMode::INDY])),
+                _ => None,
+            };
+            if let Some((inner, modes)) = modes {
+                let value = self.evaluate(inner)?.unwrap_or(0);
+                let mode = modes
+                    .into_iter()
+                    .find(|mode| self.opcode(operation, *mode).is_some())
+                    .unwrap_or(modes[0]);
+                return Ok((mode, [value, 0]));
+            }
+        }
+
+        let (expression, modes) = match index_suffix(operand) {
+            Some((expression, 'X')) => (expression, [AddressingMode::ZPGX, AddressingMode::ABSX]),
+            Some((expression, _)) => (expression, [AddressingMode::ZPGY, AddressingMode::ABSY]),
+            None => {
+                if self.opcode(operation, AddressingMode::REL).is_some() {
+                    let value = self.evaluate(operand)?.unwrap_or(self.pc as i64);
+                    return Ok((AddressingMode::REL, [value, 0]));
+                }
+                (operand, [AddressingMode::ZPG, AddressingMode::ABS])
+            }
+        };
+        let value = self.evaluate(expression)?;
+        let [zero_page, absolute] = modes;
+        let has_zero_page = self.opcode(operation, zero_page).is_some();
+        let has_absolute = self.opcode(operation, absolute).is_some();
+        let mode = match value {
+            Some(0..=0xFF) if has_zero_page => zero_page,
+            _ if has_absolute => absolute,
+            _ => zero_page,
+        };
+        Ok((mode, [value.unwrap_or(0), 0]))
+    }
+
+    fn opcode(&self, operation: Operation, mode: AddressingMode) -> Option<u8> {
+        self.instructions
+            .iter()
+            .find(|instruction| instruction.operation == operation && instruction.mode == mode)
+            .map(|instruction| instruction.opcode)
+    }
+
+    fn branch_offset(&self, target: i64, next: i64) -> LineResult<u8> {
+        if !self.final_pass {
+            return Ok(0);
+        }
+        let offset = target - next;
+        if !(-128..=127).contains(&offset) {
+            return Err(format!("Branch target {:04X} is out of range", target));
+        }
+        Ok(offset as u8)
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> LineResult<()> {
+        let name = self.qualify(name);
+        if !self.final_pass && self.symbols.contains_key(&name) {
+            return Err(format!("Symbol {} is already defined", name));
+        }
+        check_range(value, -0x8000, 0xFFFF)?;
+        // A symbol moving between the passes means code assembled before its
+        // definition used the wrong value
+        let previous = self.symbols.insert(name.clone(), value & 0xFFFF);
+        if previous.is_some_and(|previous| previous != value & 0xFFFF) {
+            return Err(format!("Value of {} changed between passes", name));
+        }
+        Ok(())
+    }
+
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('@') {
+            format!("{}{}", self.scope, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Value of an expression, or `None` on the first pass when it refers to
+    /// a symbol that is not defined yet.
+    fn evaluate(&self, expression: &str) -> LineResult<Option<i64>> {
+        let mut parser = ExpressionParser {
+            assembler: self,
+            input: expression.as_bytes(),
+            position: 0,
+        };
+        let value = parser.expression()?;
+        parser.skip_whitespace();
+        if parser.position < parser.input.len() {
+            return Err(format!("Unexpected characters in {}", expression));
+        }
+        Ok(value)
+    }
+
+    fn emit(&mut self, byte: u8) -> LineResult<()> {
+        if self.pc > 0xFFFF {
+            return Err("Code past the end of memory".to_string());
+        }
+        let address = self.pc as u16;
+        self.pc += 1;
+        if !self.final_pass {
+            return Ok(());
+        }
+
+        let origin = *self.origin.get_or_insert(address);
+        if address < origin {
+            return Err(format!(
+                "Code at {:04X} is below the start {:04X}",
+                address, origin
+            ));
+        }
+        let index = (address - origin) as usize;
+        if index < self.output.len() {
+            return Err(format!("Code at {:04X} overlaps earlier code", address));
+        }
+        self.output.resize(index, 0);
+        self.output.push(byte);
+        Ok(())
+    }
+
+    fn emit_word(&mut self, value: u16) -> LineResult<()> {
+        self.emit((value & 0xFF) as u8)?;
+        self.emit((value >> 8) as u8)
+    }
+}
+
+/// Recursive descent parser for operand expressions
+struct ExpressionParser<'a> {
+    assembler: &'a Assembler,
+    input: &'a [u8],
+    position: usize,
+}
+
+const BINARY_OPERATORS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl ExpressionParser<'_> {
+    fn expression(&mut self) -> LineResult<Option<i64>> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> LineResult<Option<i64>> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        'operators: loop {
+            self.skip_whitespace();
+            for operator in BINARY_OPERATORS[level] {
+                if self.input[self.position..].starts_with(operator.as_bytes()) {
+                    self.position += operator.len();
+                    let right = self.binary(level + 1)?;
+                    left = match (left, right) {
+                        (Some(left), Some(right)) => Some(apply(operator, left, right)?),
+                        _ => None,
+                    };
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> LineResult<Option<i64>> {
+        self.skip_whitespace();
+        let operator = self.peek();
+        let operation: fn(i64) -> Option<i64> = match operator {
+            Some(b'-') => i64::checked_neg,
+            Some(b'~') => |value| Some(!value & 0xFFFF),
+            Some(b'<') => |value| Some(value & 0xFF),
+            Some(b'>') => |value| Some((value >> 8) & 0xFF),
+            _ => return self.primary(),
+        };
+        self.position += 1;
+        self.unary()?
+            .map(|value| operation(value).ok_or_else(overflow))
+            .transpose()
+    }
+
+    fn primary(&mut self) -> LineResult<Option<i64>> {
+        self.skip_whitespace();
+        let start = self.position;
+        match self.peek() {
+            Some(b'(') => {
+                self.position += 1;
+                let value = self.expression()?;
+                self.skip_whitespace();
+                if self.peek() != Some(b')') {
+                    return Err("Expected )".to_string());
+                }
+                self.position += 1;
+                Ok(value)
+            }
+            Some(b'*') => {
+                self.position += 1;
+                Ok(Some(self.assembler.pc as i64))
+            }
+            Some(b'$') => self.number(16, 1),
+            Some(b'%') => self.number(2, 1),
+            Some(b'0'..=b'9') => self.number(10, 0),
+            Some(b'\'') => {
+                let Some(&[b'\'', character, b'\'']) = self.input.get(start..start + 3) else {
+                    return Err("Invalid character literal".to_string());
+                };
+                self.position += 3;
+                Ok(Some(character as i64))
+            }
+            _ => {
+                let name = std::str::from_utf8(&self.input[start..]).unwrap_or("");
+                let length = symbol_length(name);
+                if length == 0 {
+                    return Err(format!("Expected a value at {}", name));
+                }
+                self.position += length;
+                let name = self.assembler.qualify(&name[..length]);
+                match self.assembler.symbols.get(&name) {
+                    Some(value) => Ok(Some(*value)),
+                    None if !self.assembler.final_pass => Ok(None),
+                    None => Err(format!("Undefined symbol {}", name)),
+                }
+            }
+        }
+    }
+
+    fn number(&mut self, radix: u32, prefix: usize) -> LineResult<Option<i64>> {
+        self.position += prefix;
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            self.position += 1;
+        }
+        let digits = std::str::from_utf8(&self.input[start..self.position]).unwrap_or("");
+        i64::from_str_radix(digits, radix)
+            .map(Some)
+            .map_err(|_| format!("Invalid number {}", digits))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+}
+
+fn apply(operator: &str, left: i64, right: i64) -> LineResult<i64> {
+    let result = match operator {
+        "|" => Some(left | right),
+        "^" => Some(left ^ right),
+        "&" => Some(left & right),
+        "<<" => Some(left << (right & 0x3F)),
+        ">>" => Some(left >> (right & 0x3F)),
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" | "%" if right == 0 => return Err("Division by zero".to_string()),
+        "/" => left.checked_div(right),
+        _ => left.checked_rem(right),
+    };
+    result.ok_or_else(overflow)
+}
+
+fn overflow() -> String {
+    "Arithmetic overflow".to_string()
+}
+
+fn check_range(value: i64, min: i64, max: i64) -> LineResult<i64> {
+    if value < min || value > max {
+        return Err(format!("Value {} is out of range", value));
+    }
+    Ok(value & 0xFFFF)
+}
+
+/// Length of the label or constant name at the start of `text`
+fn symbol_length(text: &str) -> usize {
+    text.char_indices()
+        .find(|(index, c)| {
+            let valid = c.is_ascii_alphabetic() || *c == '_' || (*index == 0 && *c == '@');
+            !(valid || (*index > 0 && c.is_ascii_digit()))
+        })
+        .map_or(text.len(), |(index, _)| index)
+}
+
+/// Remove a `;` comment, leaving string and character literals alone
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..index],
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Split on commas outside of parentheses and literals
+fn split_arguments(text: &str) -> Vec<&str> {
+    let mut arguments = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(open), _) if open == c => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                arguments.push(text[start..index].trim());
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    arguments.push(text[start..].trim());
+    arguments
+}
+
+/// Split `expression,X` or `expression,Y` into the expression and the index
+fn index_suffix(operand: &str) -> Option<(&str, char)> {
+    let arguments = split_arguments(operand);
+    let [expression, index] = arguments[..] else {
+        return None;
+    };
+    match index.to_ascii_uppercase().as_str() {
+        "X" => Some((expression, 'X')),
+        "Y" => Some((expression, 'Y')),
+        _ => None,
+    }
+}
+
+/// Index of the parenthesis closing the one `text` starts with
+fn matching_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AsmError};
+    use crate::cpu::Variant;
+    use crate::instrumentation::NoopSink;
+    use crate::memory::Memory;
+    use crate::run::{StopConditions, StopReason};
+    use crate::test_util::cpu_with_program;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source, Variant::MOS6502).unwrap().bytes
+    }
+
+    #[test]
+    fn test_simple_program() {
+        let source = "
+            .org $0600
+            LDX #$00
+            LDA #$0F
+            ORA #$F0
+            STA $00
+        loop:
+            JMP loop
+        ";
+        let program = assemble(source, Variant::MOS6502).unwrap();
+        assert_eq!(program.origin, 0x0600);
+        assert_eq!(
+            program.bytes,
+            [0xA2, 0x00, 0xA9, 0x0F, 0x09, 0xF0, 0x85, 0x00, 0x4C, 0x08, 0x06]
+        );
+        assert_eq!(program.symbols["loop"], 0x0608);
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let source = "
+            .org $1000
+            ASL
+            ROR A
+            LDA #<data
+            LDA $12
+            LDA $12,X
+            LDX $12,Y
+            LDA data
+            LDA data,X
+            LDA data,Y
+            JMP (data)
+            LDA ($12,X)
+            LDA ($12),Y
+            LDA (1 + 2) * 3
+            BNE *
+        data:
+        ";
+        assert_eq!(
+            bytes(source),
+            [
+                0x0A, 0x6A, 0xA9, 0x1E, 0xA5, 0x12, 0xB5, 0x12, 0xB6, 0x12, 0xAD, 0x1E, 0x10, 0xBD,
+                0x1E, 0x10, 0xB9, 0x1E, 0x10, 0x6C, 0x1E, 0x10, 0xA1, 0x12, 0xB1, 0x12, 0xA5, 0x09,
+                0xD0, 0xFE,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_65c02_addressing_modes() {
+        let source = "
+            .org $0200
+            LDA ($12)
+            JMP ($1234,X)
+        loop:
+            BBR3 $12,loop
+            STZ $12
+        ";
+        let program = assemble(source, Variant::WDC65C02).unwrap();
+        assert_eq!(
+            program.bytes,
+            [0xB2, 0x12, 0x7C, 0x34, 0x12, 0x3F, 0x12, 0xFD, 0x64, 0x12]
+        );
+    }
+
+    #[test]
+    fn test_symbols_and_directives() {
+        let source = "
+            ZP = $80
+            .org $0300
+        first:
+            LDA forward      ; forward reference, always absolute
+            STA ZP + 1
+        @loop:
+            DEX
+            BNE @loop
+        second:
+        @loop:
+            BEQ @loop
+            .byte \"AB\", 'c', -1, %101
+            .word first, >first
+            .org $0320
+        forward = $0010
+        ";
+        let program = assemble(source, Variant::MOS6502).unwrap();
+        let mut expected = vec![
+            0xAD, 0x10, 0x00, 0x85, 0x81, 0xCA, 0xD0, 0xFD, 0xF0, 0xFE, 0x41, 0x42, 0x63, 0xFF,
+            0x05, 0x00, 0x03, 0x03, 0x00,
+        ];
+        assert_eq!(program.bytes, expected);
+        assert_eq!(program.symbols["first@loop"], 0x0305);
+        assert_eq!(program.symbols["second@loop"], 0x0308);
+
+        // Gaps between .org blocks are filled
+        let program = assemble(&format!("{}\nNOP", source), Variant::MOS6502).unwrap();
+        expected.resize(0x20, 0);
+        expected.push(0xEA);
+        assert_eq!(program.bytes, expected);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| assemble(source, Variant::MOS6502).unwrap_err();
+        assert_eq!(
+            error("NOP\nFOO #1"),
+            AsmError {
+                line: 2,
+                message: "Unknown instruction FOO".to_string()
+            }
+        );
+        assert_eq!(error("LDA missing").message, "Undefined symbol missing");
+        assert_eq!(error("LDA #$100").message, "Value 256 is out of range");
+        assert_eq!(error("a:\na:").message, "Symbol a is already defined");
+        assert_eq!(
+            error("x = y\nx = 1\ny = 2"),
+            AsmError {
+                line: 1,
+                message: "Value of x changed between passes".to_string()
+            }
+        );
+        assert_eq!(
+            error("BNE far\n.org $0100\nfar:").message,
+            "Branch target 0100 is out of range"
+        );
+        assert_eq!(
+            error("LDA ($12)").message,
+            "LDA doesn't support IND addressing"
+        );
+        for overflow in [
+            ".word $7FFFFFFFFFFFFFFF+1",
+            ".word -$7FFFFFFFFFFFFFFF-2",
+            ".word $100000000*$100000000",
+            ".word (-$7FFFFFFFFFFFFFFF-1)/-1",
+            ".word (-$7FFFFFFFFFFFFFFF-1)%-1",
+            ".word -(-$7FFFFFFFFFFFFFFF-1)",
+        ] {
+            assert_eq!(error(overflow).message, "Arithmetic overflow");
+        }
+    }
+
+    #[test]
+    fn test_run_assembled() {
+        let source = "
+            .org $0600
+            LDX #5
+            LDA #0
+        loop:
+            CLC
+            ADC #3
+            DEX
+            BNE loop
+            STA result
+        done:
+            JMP done
+        result:
+            .byte 0
+        ";
+        let program = assemble(source, Variant::MOS6502).unwrap();
+        let mut cpu = cpu_with_program(&program.bytes, program.origin);
+
+        let reason = cpu.run_until(StopConditions::new().infinite_loop(), &mut NoopSink);
+        assert_eq!(
+            reason,
+            StopReason::InfiniteLoop {
+                pc: program.symbols["done"]
+            }
+        );
+        assert_eq!(
+            cpu.get_state().get_memory().peek(program.symbols["result"]),
+            15
+        );
+    }
+}
@@ -1,85 +1,616 @@
 //! Implementation of the instruction set
 
+use crate::debug::Break;
+use crate::debug::Breakpoints;
+use crate::debug::WatchKind;
+use crate::error::CpuError;
 use crate::instruction;
 use crate::instruction::AddressingMode;
 use crate::instruction::Operation;
 use crate::instrumentation::Trace;
+use crate::instrumentation::TraceSink;
+use crate::io_port::IoPort;
+use crate::memory::AccessKind;
 use crate::memory::Memory;
+use crate::rewind::Rewind;
+use crate::rewind::Snapshot;
+use crate::savestate::SaveState;
+use crate::savestate::SaveStateError;
+use crate::savestate::SAVE_STATE_VERSION;
+use crate::state::unfixed_address;
 use crate::state::CPUState;
+use crate::state::Registers;
 use crate::state::IRQ_VECTOR_ADDR;
+use crate::state::NMI_VECTOR_ADDR;
+use std::ops::RangeInclusive;
+
+/// Constant ORed into A by the unstable `ANE` and `LXA` instructions. It
+/// differs between chips, $EE is the most common value.
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+/// CPU model to emulate
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Variant {
+    /// Original NMOS 6502
+    MOS6502,
+    /// WDC W65C02S with the Rockwell bit instructions
+    WDC65C02,
+    /// Ricoh 2A03/2A07 of the NES: an NMOS 6502 whose D flag can be set but
+    /// is ignored by `ADC` and `SBC`
+    Ricoh2A03,
+    /// MOS 6510 of the C64: an NMOS 6502 with an I/O port at $0000 and $0001,
+    /// see `io_port`
+    MOS6510,
+}
 
 /// The CPU emulator
 pub struct CPU<T: Memory> {
     state: CPUState<T>,
+    variant: Variant,
+    /// Level of the IRQ input line
+    irq: bool,
+    /// Level of the NMI input line
+    nmi: bool,
+    /// Set on a falling edge of NMI until the interrupt is serviced
+    nmi_pending: bool,
+    /// Level of the RDY input line
+    rdy: bool,
+    /// Level of the SO input line
+    so: bool,
+    /// Cycles stolen by DMA, spent before the next instruction
+    stall: u64,
+    /// Instruction in progress in cycle-stepped mode
+    in_progress: Option<InProgress>,
+    /// Decode the undocumented NMOS opcodes
+    undocumented_opcodes: bool,
+    /// Set by `JAM` and `STP`, only cleared by reset
+    halted: bool,
+    /// Set by `WAI` until an interrupt is requested
+    waiting: bool,
+    breakpoints: Breakpoints<T>,
+    pub(crate) rewind: Option<Rewind>,
+}
+
+/// Instruction started by `CPU::tick()` that has not completed yet
+struct InProgress {
+    /// Registers before the instruction started
+    registers: Registers,
+    /// Interrupt to service before the instruction, decided on its first cycle
+    interrupt: Option<u16>,
+    /// State before the instruction for the rewind history
+    before: Snapshot,
 }
 
 /// Implementation of the instruction set.
 /// Instructions are executed against the `CPUState` struct.
 impl<T: Memory> CPU<T> {
     pub fn new(state: CPUState<T>) -> CPU<T> {
-        CPU { state: state }
+        CPU::with_variant(state, Variant::MOS6502)
+    }
+
+    pub fn with_variant(mut state: CPUState<T>, variant: Variant) -> CPU<T> {
+        state.set_io_port((variant == Variant::MOS6510).then(IoPort::new));
+        CPU {
+            state,
+            variant,
+            irq: false,
+            nmi: false,
+            nmi_pending: false,
+            rdy: true,
+            so: true,
+            stall: 0,
+            in_progress: None,
+            undocumented_opcodes: false,
+            halted: false,
+            waiting: false,
+            breakpoints: Breakpoints::new(),
+            rewind: None,
+        }
+    }
+
+    pub fn get_variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn get_state(&self) -> &CPUState<T> {
+        &self.state
     }
 
     pub fn get_mut_state(&mut self) -> &mut CPUState<T> {
         &mut self.state
     }
 
-    fn read_operand(&mut self, mode: AddressingMode) -> Option<u16> {
+    /// Enable or disable the undocumented NMOS opcodes (`LAX`, `SAX`, `DCP`,
+    /// `ISC`, `SLO`, `RLA`, `SRE`, `RRA`, `ANC`, `ALR`, `ARR`, `SBX`, the
+    /// multi-byte `NOP`s and the rest). They are disabled by default, in
+    /// which case they are treated as unknown opcodes. Has no effect on the
+    /// 65C02 which defines all opcodes.
+    pub fn set_undocumented_opcodes(&mut self, enabled: bool) {
+        self.undocumented_opcodes = enabled;
+    }
+
+    /// Whether a `JAM` or `STP` instruction has halted the CPU. A halted CPU
+    /// keeps executing the same instruction and ignores interrupts until it
+    /// is reset.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Whether a 65C02 `WAI` instruction is waiting for an interrupt
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    /// Reset the CPU. Clears a halt and pending interrupts and runs the reset
+    /// sequence of the `CPUState`. The 65C02 also clears D.
+    pub fn reset(&mut self) {
+        self.halted = false;
+        self.waiting = false;
+        self.nmi_pending = false;
+        self.in_progress = None;
+        self.state.reset();
+        if self.variant == Variant::WDC65C02 {
+            self.state.set_d(0);
+        }
+    }
+
+    /// Snapshot the CPU and memory. Breakpoints and watchpoints are not
+    /// part of the state. Fails in the middle of an instruction started with
+    /// `tick()`.
+    pub fn save_state(&self) -> Result<SaveState, SaveStateError> {
+        if self.in_progress.is_some() {
+            return Err(SaveStateError::InstructionInProgress);
+        }
+        Ok(SaveState {
+            version: SAVE_STATE_VERSION,
+            variant: self.variant,
+            registers: self.state.registers(),
+            irq: self.irq,
+            nmi: self.nmi,
+            nmi_pending: self.nmi_pending,
+            halted: self.halted,
+            waiting: self.waiting,
+            undocumented_opcodes: self.undocumented_opcodes,
+            rdy: self.rdy,
+            so: self.so,
+            stall: self.stall,
+            io_port: self
+                .state
+                .io_port()
+                .map(IoPort::save_state)
+                .unwrap_or_default(),
+            memory: self.state.get_memory().save_state(),
+        })
+    }
+
+    /// Restore a snapshot taken with `save_state()`. An instruction in
+    /// progress is abandoned.
+    pub fn load_state(&mut self, save_state: &SaveState) -> Result<(), SaveStateError> {
+        if save_state.version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(save_state.version));
+        }
+        let io_port = match save_state.variant {
+            Variant::MOS6510 => {
+                let mut io_port = IoPort::new();
+                io_port.load_state(&save_state.io_port)?;
+                Some(io_port)
+            }
+            _ => None,
+        };
+        // Last to fail, so a bad state leaves the CPU untouched
+        self.state.get_mut_memory().load_state(&save_state.memory)?;
+        self.state.set_io_port(io_port);
+        self.state.set_registers(save_state.registers);
+        self.variant = save_state.variant;
+        self.irq = save_state.irq;
+        self.nmi = save_state.nmi;
+        self.nmi_pending = save_state.nmi_pending;
+        self.halted = save_state.halted;
+        self.waiting = save_state.waiting;
+        self.undocumented_opcodes = save_state.undocumented_opcodes;
+        self.rdy = save_state.rdy;
+        self.so = save_state.so;
+        self.stall = save_state.stall;
+        self.in_progress = None;
+        Ok(())
+    }
+
+    /// Drive the IRQ input line. IRQ is level-triggered: the interrupt is
+    /// taken before every instruction for as long as the line is asserted and
+    /// the I flag is clear, so devices must deassert it once serviced.
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+    }
+
+    pub fn get_irq(&self) -> bool {
+        self.irq
+    }
+
+    /// Drive the NMI input line. NMI is edge-triggered: asserting the line
+    /// latches a single interrupt which is taken before the next instruction
+    /// regardless of the I flag. The line has to be deasserted before it can
+    /// trigger again.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi {
+            self.nmi_pending = true;
+        }
+        self.nmi = asserted;
+    }
+
+    pub fn get_nmi(&self) -> bool {
+        self.nmi
+    }
+
+    /// Drive the RDY input line. While it is low the CPU waits on its next
+    /// read cycle, the 65C02 also on write cycles. `tick()` advances a waiting
+    /// CPU one cycle per call and `execute()` keeps waiting until its cycle
+    /// budget is used up, both counting the wait in `CPUState.cycles`. As the
+    /// line can't change during a single instruction, `try_step()` fails with
+    /// `CpuError::NotReady` and `step()` panics.
+    pub fn set_rdy(&mut self, ready: bool) {
+        self.rdy = ready;
+    }
+
+    pub fn get_rdy(&self) -> bool {
+        self.rdy
+    }
+
+    /// Drive the SO input line. A falling edge sets the V flag.
+    pub fn set_so(&mut self, level: bool) {
+        if self.so && !level {
+            self.state.set_v(true);
+            if let Some(in_progress) = self.in_progress.as_mut() {
+                in_progress.registers.status |= 0b0100_0000;
+            }
+        }
+        self.so = level;
+    }
+
+    pub fn get_so(&self) -> bool {
+        self.so
+    }
+
+    /// Steal `cycles` cycles from the CPU, e.g. for a DMA transfer. They are
+    /// spent before the next instruction starts and counted in
+    /// `CPUState.cycles`, but not in the `Trace` of the instruction.
+    pub fn stall(&mut self, cycles: u64) {
+        self.stall += cycles;
+    }
+
+    /// Spend the stolen cycles now, unless an instruction is in progress.
+    /// Returns the number of cycles spent.
+    pub(crate) fn spend_stall(&mut self) -> u64 {
+        if self.in_progress.is_some() {
+            return 0;
+        }
+        let cycles = std::mem::take(&mut self.stall);
+        self.state.cycles += cycles;
+        cycles
+    }
+
+    /// Drop an unfinished instruction started with `tick()`, undoing the
+    /// memory writes it has done so far and going back to the state before
+    /// it. The writes are only known while rewinding is enabled. Returns
+    /// whether there was an instruction in progress.
+    pub(crate) fn abandon_instruction(&mut self) -> bool {
+        let Some(in_progress) = self.in_progress.take() else {
+            return false;
+        };
+        self.state.abandon_cycles();
+        for write in self.state.take_journal().iter().rev() {
+            self.state.get_mut_memory().poke(write.address, write.old);
+        }
+        self.restore_snapshot(&in_progress.before);
+        true
+    }
+
+    fn read_operand(&self, mode: AddressingMode) -> Option<u16> {
         match mode {
-            AddressingMode::REL => Some(self.state.read_byte(self.state.pc) as u16),
+            AddressingMode::REL => Some(self.state.peek_byte(self.state.pc) as u16),
             AddressingMode::ACC => None,
             AddressingMode::IMPL => None,
-            AddressingMode::IMM => Some(self.state.read_byte(self.state.pc) as u16),
-            AddressingMode::ZPG => Some(self.state.read_byte(self.state.pc) as u16),
-            AddressingMode::ZPGX => Some(self.state.read_byte(self.state.pc) as u16),
-            AddressingMode::ZPGY => Some(self.state.read_byte(self.state.pc) as u16),
-            AddressingMode::ABS => Some(self.state.read_word(self.state.pc)),
-            AddressingMode::ABSX => Some(self.state.read_word(self.state.pc)),
-            AddressingMode::ABSY => Some(self.state.read_word(self.state.pc)),
-            AddressingMode::IND => Some(self.state.read_byte(self.state.pc) as u16),
-            AddressingMode::XIND => Some(self.state.read_byte(self.state.pc) as u16),
-            AddressingMode::INDY => Some(self.state.read_byte(self.state.pc) as u16),
-        }
-    }
-
-    /// Execute the CPU for a given number of cycles.
-    /// Prints a trace of each instruction executed to stdout.
-    pub fn execute(&mut self, cycles: u64) {
+            AddressingMode::IMM => Some(self.state.peek_byte(self.state.pc) as u16),
+            AddressingMode::ZPG => Some(self.state.peek_byte(self.state.pc) as u16),
+            AddressingMode::ZPGX => Some(self.state.peek_byte(self.state.pc) as u16),
+            AddressingMode::ZPGY => Some(self.state.peek_byte(self.state.pc) as u16),
+            AddressingMode::ABS => Some(self.state.peek_word(self.state.pc)),
+            AddressingMode::ABSX => Some(self.state.peek_word(self.state.pc)),
+            AddressingMode::ABSY => Some(self.state.peek_word(self.state.pc)),
+            AddressingMode::IND => Some(self.state.peek_word(self.state.pc)),
+            AddressingMode::XIND => Some(self.state.peek_byte(self.state.pc) as u16),
+            AddressingMode::INDY => Some(self.state.peek_byte(self.state.pc) as u16),
+            AddressingMode::ZPIND => Some(self.state.peek_byte(self.state.pc) as u16),
+            AddressingMode::ABSXIND => Some(self.state.peek_word(self.state.pc)),
+            AddressingMode::ZPREL => Some(self.state.peek_word(self.state.pc)),
+        }
+    }
+
+    /// Break before executing the instruction at `pc`
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.add(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(pc);
+    }
+
+    /// Break before executing the instruction at `pc` while `bank` is mapped
+    /// there, see `Memory::bank()`
+    pub fn add_bank_breakpoint(&mut self, bank: u16, pc: u16) {
+        self.breakpoints.add_banked(bank, pc);
+    }
+
+    pub fn remove_bank_breakpoint(&mut self, bank: u16, pc: u16) {
+        self.breakpoints.remove_banked(bank, pc);
+    }
+
+    /// Break when `condition` holds for the state before an instruction, e.g.
+    /// on a register value or flag. Returns an id for removing it.
+    pub fn add_condition(&mut self, condition: impl Fn(&CPUState<T>) -> bool + 'static) -> usize {
+        self.breakpoints.add_condition(Box::new(condition))
+    }
+
+    pub fn remove_condition(&mut self, id: usize) {
+        self.breakpoints.remove_condition(id);
+    }
+
+    /// Break after an instruction that reads or writes an address in
+    /// `range`. Every bus access counts, including opcode fetches and dummy
+    /// reads. Returns an id for removing it.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> usize {
+        self.state.watchpoints.add(range, kind)
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) {
+        self.state.watchpoints.remove(id);
+    }
+
+    /// Check the breakpoints between instructions. A watchpoint hit is
+    /// reported once.
+    pub fn check_breakpoints(&mut self) -> Option<Break> {
+        if let Some(hit) = self.state.watchpoints.take_hit() {
+            return Some(Break::Watchpoint(hit));
+        }
+        self.breakpoints.check(&self.state)
+    }
+
+    /// Execute the CPU for a given number of cycles, or forever if `cycles`
+    /// is 0. The trace of each instruction executed is passed to `sink`.
+    /// While RDY is low the CPU waits one cycle at a time.
+    pub fn execute<S: TraceSink + ?Sized>(&mut self, cycles: u64, sink: &mut S) {
         while self.state.cycles < cycles || cycles == 0 {
-            let trace = self.step();
-            trace.print();
+            let trace = if self.rdy {
+                Some(self.step())
+            } else {
+                // The NMOS 6502 doesn't wait on writes, so an instruction can
+                // still complete
+                self.tick()
+            };
+            if let Some(trace) = trace {
+                sink.trace(&trace);
+            }
         }
     }
 
-    /// Execute the next instruction.
+    /// Execute the next instruction. Pending interrupts are serviced first, in
+    /// which case the returned `Trace` is of the first instruction of the
+    /// interrupt handler. An instruction started with `tick()` is run to
+    /// completion.
+    ///
+    /// Panics if the instruction can't be executed, see `try_step()`.
     pub fn step(&mut self) -> Trace {
+        self.run_step().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Like `step()`, but returns an error instead of panicking when the
+    /// instruction can't be executed or the CPU has been halted. PC is left
+    /// on the failed instruction, so the state can be inspected and fixed up
+    /// before continuing.
+    pub fn try_step(&mut self) -> Result<Trace, CpuError> {
+        if self.halted {
+            return Err(CpuError::Halted { pc: self.state.pc });
+        }
+        self.run_step()
+    }
+
+    fn run_step(&mut self) -> Result<Trace, CpuError> {
+        if !self.rdy {
+            return Err(CpuError::NotReady { pc: self.state.pc });
+        }
+        if self.in_progress.is_some() {
+            loop {
+                if let Some(trace) = self.try_tick()? {
+                    return Ok(trace);
+                }
+            }
+        }
+
+        let before = self.snapshot();
+        self.state.cycles += std::mem::take(&mut self.stall);
+        self.state.clear_journal();
+        let interrupt = self.poll_interrupts();
+        let result = self.run_instruction(interrupt);
+        self.record_step(before);
+        result
+    }
+
+    /// Advance the CPU by a single clock cycle, performing the one bus read or
+    /// write the 6502 does on that cycle. Returns the `Trace` of the
+    /// instruction when its last cycle has been executed.
+    ///
+    /// Registers are only updated when the instruction completes, apart from
+    /// `cycles` which counts every cycle. Interrupts are polled on the first
+    /// cycle of an instruction.
+    ///
+    /// Panics if the instruction can't be executed.
+    pub fn tick(&mut self) -> Option<Trace> {
+        self.try_tick().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    pub(crate) fn try_tick(&mut self) -> Result<Option<Trace>, CpuError> {
+        let in_progress = match self.in_progress.take() {
+            Some(in_progress) => in_progress,
+            None if self.stall > 0 => {
+                self.stall -= 1;
+                self.state.cycles += 1;
+                return Ok(None);
+            }
+            None => {
+                let before = self.snapshot();
+                self.state.clear_journal();
+                let interrupt = self.poll_interrupts();
+                InProgress {
+                    registers: self.state.registers(),
+                    interrupt,
+                    before,
+                }
+            }
+        };
+
+        self.state.set_registers(in_progress.registers);
+        self.state
+            .begin_cycle(self.rdy, self.variant == Variant::WDC65C02);
+        let result = self.run_instruction(in_progress.interrupt);
+        match (result, self.state.end_cycle()) {
+            (Ok(_), Some(cycles_done)) => {
+                self.state.set_registers(in_progress.registers);
+                self.state.cycles += cycles_done;
+                self.in_progress = Some(in_progress);
+                Ok(None)
+            }
+            (result, _) => {
+                self.record_step(in_progress.before);
+                result.map(Some)
+            }
+        }
+    }
+
+    /// Registers and interrupt state for rewinding
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            registers: self.state.registers(),
+            irq: self.irq,
+            nmi: self.nmi,
+            nmi_pending: self.nmi_pending,
+            halted: self.halted,
+            waiting: self.waiting,
+            rdy: self.rdy,
+            so: self.so,
+            stall: self.stall,
+            io_port: self.state.io_port().copied(),
+        }
+    }
+
+    pub(crate) fn restore_snapshot(&mut self, snapshot: &Snapshot) {
+        self.state.set_registers(snapshot.registers);
+        self.irq = snapshot.irq;
+        self.nmi = snapshot.nmi;
+        self.nmi_pending = snapshot.nmi_pending;
+        self.halted = snapshot.halted;
+        self.waiting = snapshot.waiting;
+        self.rdy = snapshot.rdy;
+        self.so = snapshot.so;
+        self.stall = snapshot.stall;
+        self.state.set_io_port(snapshot.io_port);
+    }
+
+    /// Add the instruction that just completed to the rewind history
+    fn record_step(&mut self, before: Snapshot) {
+        let Some(mut rewind) = self.rewind.take() else {
+            return;
+        };
+        let writes = self.state.take_journal();
+        rewind.record(before, self.snapshot(), writes);
+        if rewind.needs_checkpoint() {
+            if let Ok(save_state) = self.save_state() {
+                rewind.checkpoint(save_state);
+            }
+        }
+        self.rewind = Some(rewind);
+    }
+
+    fn decode(&self, opcode: u8) -> Option<instruction::Instruction> {
+        if self.variant == Variant::WDC65C02 {
+            return Some(instruction::wdc65c02_opcode_to_instruction(opcode));
+        }
+        if self.undocumented_opcodes {
+            if let Some(instruction) = instruction::undocumented_opcode_to_instruction(opcode) {
+                return Some(instruction);
+            }
+        }
+        instruction::opcode_to_instruction(opcode)
+    }
+
+    /// Service `interrupt` if any and execute the next instruction. On error
+    /// PC is left on the failed instruction.
+    fn run_instruction(&mut self, interrupt: Option<u16>) -> Result<Trace, CpuError> {
+        if let Some(vector) = interrupt {
+            self.interrupt(vector);
+        }
+
         let pc = self.state.pc;
-        let opcode = self.state.fetch_byte();
-        let instruction = instruction::opcode_to_instruction(opcode);
+        let bank = self.state.get_memory().bank(pc);
+        let start_cycles = self.state.cycles;
+        let opcode = self.state.fetch_opcode();
+        let Some(instruction) = self.decode(opcode) else {
+            self.state.pc = pc;
+            return Err(CpuError::UnknownOpcode { opcode, pc });
+        };
         let operand = self.read_operand(instruction.mode);
 
+        if let AddressingMode::IMPL | AddressingMode::ACC = instruction.mode {
+            // Single byte instructions read the following byte and ignore it,
+            // apart from the single cycle NOPs of the 65C02
+            if instruction.cycles > 1 {
+                self.state.dummy_read(self.state.pc);
+            }
+        }
+
+        if let Err(error) = self.dispatch(instruction) {
+            self.state.pc = pc;
+            return Err(error);
+        }
+        self.state.increment_cycles(instruction.cycles as u64);
+
+        let mut trace = Trace::new(
+            pc,
+            self.state.a,
+            self.state.x,
+            self.state.y,
+            self.state.sp,
+            self.state.status,
+            instruction,
+            operand,
+            (self.state.cycles - start_cycles) as u8,
+        );
+        trace.bank = bank;
+        Ok(trace)
+    }
+
+    fn dispatch(&mut self, instruction: instruction::Instruction) -> Result<(), CpuError> {
         match instruction.operation {
             Operation::BRK => self.brk(),
-            Operation::ADC => self.adc(instruction.mode),
-            Operation::LDX => self.ldx(instruction.mode),
-            Operation::LDA => self.lda(instruction.mode),
-            Operation::LDY => self.ldy(instruction.mode),
-            Operation::ASL => self.asl(instruction.mode),
-            Operation::ORA => self.ora(instruction.mode),
-            Operation::STA => self.sta(instruction.mode),
-            Operation::STX => self.stx(instruction.mode),
-            Operation::STY => self.sty(instruction.mode),
-            Operation::JMP => self.jmp(instruction.mode),
+            Operation::ADC => self.adc(instruction.mode)?,
+            Operation::LDX => self.ldx(instruction.mode)?,
+            Operation::LDA => self.lda(instruction.mode)?,
+            Operation::LDY => self.ldy(instruction.mode)?,
+            Operation::ASL => self.asl(instruction.mode)?,
+            Operation::ORA => self.ora(instruction.mode)?,
+            Operation::STA => self.sta(instruction.mode)?,
+            Operation::STX => self.stx(instruction.mode)?,
+            Operation::STY => self.sty(instruction.mode)?,
+            Operation::JMP => self.jmp(instruction.mode)?,
             Operation::DEY => self.dey(),
             Operation::DEX => self.dex(),
             Operation::INY => self.iny(),
             Operation::INX => self.inx(),
-            Operation::BPL => self.bpl(instruction.mode),
+            Operation::BPL => self.bpl(instruction.mode)?,
             Operation::PHA => self.pha(),
             Operation::PHP => self.php(),
-            Operation::CMP => self.cmp(instruction.mode),
-            Operation::BCS => self.bcs(instruction.mode),
-            Operation::BCC => self.bcc(instruction.mode),
+            Operation::CMP => self.cmp(instruction.mode)?,
+            Operation::BCS => self.bcs(instruction.mode)?,
+            Operation::BCC => self.bcc(instruction.mode)?,
             Operation::TXA => self.txa(),
             Operation::TYA => self.tya(),
             Operation::TXS => self.txs(),
@@ -93,72 +624,179 @@ impl<T: Memory> CPU<T> {
             Operation::CLV => self.clv(),
             Operation::CLD => self.cld(),
             Operation::SED => self.sed(),
-            Operation::SBC => self.sbc(instruction.mode),
-            Operation::JSR => self.jsr(instruction.mode),
+            Operation::SBC => self.sbc(instruction.mode)?,
+            Operation::JSR => self.jsr(),
             Operation::RTS => self.rts(),
-            Operation::ROL => self.rol(instruction.mode),
-            Operation::BNE => self.bne(instruction.mode),
+            Operation::ROL => self.rol(instruction.mode)?,
+            Operation::BNE => self.bne(instruction.mode)?,
             Operation::PLA => self.pla(),
             Operation::PLP => self.plp(),
-            Operation::AND => self.and(instruction.mode),
-            Operation::EOR => self.eor(instruction.mode),
-            Operation::LSR => self.lsr(instruction.mode),
-            Operation::ROR => self.ror(instruction.mode),
-            Operation::BMI => self.bmi(instruction.mode),
-            Operation::BVS => self.bvs(instruction.mode),
-            Operation::BVC => self.bvc(instruction.mode),
+            Operation::AND => self.and(instruction.mode)?,
+            Operation::EOR => self.eor(instruction.mode)?,
+            Operation::LSR => self.lsr(instruction.mode)?,
+            Operation::ROR => self.ror(instruction.mode)?,
+            Operation::BMI => self.bmi(instruction.mode)?,
+            Operation::BVS => self.bvs(instruction.mode)?,
+            Operation::BVC => self.bvc(instruction.mode)?,
             Operation::RTI => self.rti(),
-            Operation::NOP => self.nop(),
-            Operation::BEQ => self.beq(instruction.mode),
-            Operation::CPX => self.cpx(instruction.mode),
-            Operation::CPY => self.cpy(instruction.mode),
-            Operation::INC => self.inc(instruction.mode),
-            Operation::DEC => self.dec(instruction.mode),
-            Operation::BIT => self.bit(instruction.mode),
+            Operation::NOP => self.nop(instruction.mode, instruction.cycles)?,
+            Operation::BEQ => self.beq(instruction.mode)?,
+            Operation::CPX => self.cpx(instruction.mode)?,
+            Operation::CPY => self.cpy(instruction.mode)?,
+            Operation::INC => self.inc(instruction.mode)?,
+            Operation::DEC => self.dec(instruction.mode)?,
+            Operation::BIT => self.bit(instruction.mode)?,
+            Operation::ALR => self.alr(instruction.mode)?,
+            Operation::ANC => self.anc(instruction.mode)?,
+            Operation::ANE => self.ane(instruction.mode)?,
+            Operation::ARR => self.arr(instruction.mode)?,
+            Operation::DCP => self.dcp(instruction.mode)?,
+            Operation::ISC => self.isc(instruction.mode)?,
+            Operation::JAM => self.jam(),
+            Operation::LAS => self.las(instruction.mode)?,
+            Operation::LAX => self.lax(instruction.mode)?,
+            Operation::LXA => self.lxa(instruction.mode)?,
+            Operation::RLA => self.rla(instruction.mode)?,
+            Operation::RRA => self.rra(instruction.mode)?,
+            Operation::SAX => self.sax(instruction.mode)?,
+            Operation::SBX => self.sbx(instruction.mode)?,
+            Operation::SHA => self.sha(instruction.mode)?,
+            Operation::SHX => self.shx(instruction.mode)?,
+            Operation::SHY => self.shy(instruction.mode)?,
+            Operation::SLO => self.slo(instruction.mode)?,
+            Operation::SRE => self.sre(instruction.mode)?,
+            Operation::TAS => self.tas(instruction.mode)?,
+            Operation::BBR0 => self.bbr(0)?,
+            Operation::BBR1 => self.bbr(1)?,
+            Operation::BBR2 => self.bbr(2)?,
+            Operation::BBR3 => self.bbr(3)?,
+            Operation::BBR4 => self.bbr(4)?,
+            Operation::BBR5 => self.bbr(5)?,
+            Operation::BBR6 => self.bbr(6)?,
+            Operation::BBR7 => self.bbr(7)?,
+            Operation::BBS0 => self.bbs(0)?,
+            Operation::BBS1 => self.bbs(1)?,
+            Operation::BBS2 => self.bbs(2)?,
+            Operation::BBS3 => self.bbs(3)?,
+            Operation::BBS4 => self.bbs(4)?,
+            Operation::BBS5 => self.bbs(5)?,
+            Operation::BBS6 => self.bbs(6)?,
+            Operation::BBS7 => self.bbs(7)?,
+            Operation::BRA => self.bra(instruction.mode)?,
+            Operation::PHX => self.phx(),
+            Operation::PHY => self.phy(),
+            Operation::PLX => self.plx(),
+            Operation::PLY => self.ply(),
+            Operation::RMB0 => self.rmb(instruction.mode, 0)?,
+            Operation::RMB1 => self.rmb(instruction.mode, 1)?,
+            Operation::RMB2 => self.rmb(instruction.mode, 2)?,
+            Operation::RMB3 => self.rmb(instruction.mode, 3)?,
+            Operation::RMB4 => self.rmb(instruction.mode, 4)?,
+            Operation::RMB5 => self.rmb(instruction.mode, 5)?,
+            Operation::RMB6 => self.rmb(instruction.mode, 6)?,
+            Operation::RMB7 => self.rmb(instruction.mode, 7)?,
+            Operation::SMB0 => self.smb(instruction.mode, 0)?,
+            Operation::SMB1 => self.smb(instruction.mode, 1)?,
+            Operation::SMB2 => self.smb(instruction.mode, 2)?,
+            Operation::SMB3 => self.smb(instruction.mode, 3)?,
+            Operation::SMB4 => self.smb(instruction.mode, 4)?,
+            Operation::SMB5 => self.smb(instruction.mode, 5)?,
+            Operation::SMB6 => self.smb(instruction.mode, 6)?,
+            Operation::SMB7 => self.smb(instruction.mode, 7)?,
+            Operation::STP => self.stp(),
+            Operation::STZ => self.stz(instruction.mode)?,
+            Operation::TRB => self.trb(instruction.mode)?,
+            Operation::TSB => self.tsb(instruction.mode)?,
+            Operation::WAI => self.wai(),
         }
+        Ok(())
+    }
 
-        self.state.increment_cycles(instruction.cycles as u64);
+    /// Check the interrupt lines between instructions and return the vector
+    /// of the interrupt to take. NMI has priority over IRQ, and IRQ is masked
+    /// by the I flag.
+    fn poll_interrupts(&mut self) -> Option<u16> {
+        if self.halted {
+            return None;
+        }
+        if self.waiting && (self.nmi_pending || self.irq) {
+            // Continue after the WAI, servicing the interrupt unless masked
+            self.waiting = false;
+            self.state.pc = self.state.pc.wrapping_add(1);
+        }
 
-        Trace::new(
-            pc,
-            self.state.a,
-            self.state.x,
-            self.state.y,
-            self.state.sp,
-            self.state.status,
-            instruction,
-            operand,
-        )
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            Some(NMI_VECTOR_ADDR)
+        } else if self.irq && self.state.get_i() == 0 {
+            Some(IRQ_VECTOR_ADDR)
+        } else {
+            None
+        }
+    }
+
+    /// Hardware interrupt sequence. Same as `BRK` except that the pushed
+    /// status has the break flag clear and PC is not advanced.
+    fn interrupt(&mut self, vector: u16) {
+        // The opcode fetch and the following read are done but ignored
+        self.state.dummy_read(self.state.pc);
+        self.state.dummy_read(self.state.pc);
+        let return_address = self.state.pc;
+        self.state.push_word(return_address);
+        let mut status = self.state.status;
+        status &= 0b1110_1111;
+        status |= 0b0010_0000;
+        self.state.push_byte(status);
+        self.state.set_i(1);
+        if self.variant == Variant::WDC65C02 {
+            self.state.set_d(0);
+        }
+        self.state.pc = self.state.read_vector(vector);
+        self.state.increment_cycles(7);
     }
 
     fn brk(&mut self) {
-        self.state.pc += 1;
+        self.state.pc = self.state.pc.wrapping_add(1);
         let return_address = self.state.pc;
         self.state.push_word(return_address);
         let mut status = self.state.status;
         status |= 0b0011_0000;
         self.state.push_byte(status);
-        self.state.pc = self.state.read_word(IRQ_VECTOR_ADDR);
+        self.state.pc = self.state.read_vector(IRQ_VECTOR_ADDR);
         self.state.status |= 0b0000_0100; // set interrupt disable
+        if self.variant == Variant::WDC65C02 {
+            self.state.set_d(0);
+        }
     }
 
-    fn adc(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
-        let a = self.state.get_a();
-        let carry = self.state.get_c() as u8;
+    fn adc(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        self.decimal_penalty();
+        self.add_with_carry(operand);
+        Ok(())
+    }
 
-        let sum = if self.state.get_d() == 1 {
-            let mut result = a as u16 + operand as u16 + carry as u16;
-            if (a & 0x0F) + (operand & 0x0F) + carry > 0x09 {
-                result += 0x06;
-            }
-            if result > 0x99 {
-                result += 0x60;
-            }
-            result
-        } else {
-            a as u16 + operand as u16 + carry as u16
-        };
+    /// The 65C02 spends an extra cycle on `ADC` and `SBC` in decimal mode
+    fn decimal_penalty(&mut self) {
+        if self.variant == Variant::WDC65C02 && self.state.get_d() == 1 {
+            self.state.dummy_read(self.state.pc);
+            self.state.increment_cycles(1);
+        }
+    }
+
+    /// Whether `ADC` and `SBC` work in decimal. The 2A03 lacks decimal mode.
+    fn decimal_mode(&self) -> bool {
+        self.state.get_d() == 1 && self.variant != Variant::Ricoh2A03
+    }
+
+    fn add_with_carry(&mut self, operand: u8) {
+        if self.decimal_mode() {
+            self.add_decimal(operand);
+            return;
+        }
+
+        let a = self.state.get_a();
+        let sum = a as u16 + operand as u16 + self.state.get_c() as u16;
 
         self.state.set_c((sum > 0xFF) as u8);
         let result = (sum & 0xFF) as u8;
@@ -169,104 +807,223 @@ impl<T: Memory> CPU<T> {
         self.state.set_n(result);
     }
 
-    fn sbc(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
+    fn sbc(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        self.decimal_penalty();
+        self.subtract_with_carry(operand);
+        Ok(())
+    }
+
+    fn subtract_with_carry(&mut self, operand: u8) {
         let a = self.state.get_a();
-        let carry = self.state.get_c() as u8;
+        let carry = self.state.get_c();
+        let sum = a as u16 + (!operand) as u16 + carry as u16;
+        let binary = (sum & 0xFF) as u8;
 
-        let sum = if self.state.get_d() == 1 {
-            let mut result = a as u16 + (!operand) as u16 + carry as u16;
-            if (a & 0x0F) < (operand & 0x0F) + 1 - carry {
-                result -= 0x06;
-            }
-            if result < 0x100 {
-                result -= 0x60;
-            }
-            result
+        let result = if self.decimal_mode() {
+            self.subtract_decimal(a, operand, carry)
         } else {
-            a as u16 + (!operand) as u16 + carry as u16
+            binary
         };
 
+        // C and V come from the binary subtraction on both variants
         self.state.set_c((sum > 0xFF) as u8);
-        let result = (sum & 0xFF) as u8;
         self.state.set_a(result);
         self.state
-            .set_v(((a ^ result) & (!operand ^ result) & 0x80) != 0);
-        self.state.set_z(result);
-        self.state.set_n(result);
+            .set_v(((a ^ binary) & (!operand ^ binary) & 0x80) != 0);
+        if self.variant == Variant::WDC65C02 {
+            self.state.set_z(result);
+            self.state.set_n(result);
+        } else {
+            self.state.set_z(binary);
+            self.state.set_n(binary);
+        }
+    }
+
+    /// Decimal mode addition. The NMOS 6502 sets Z from the binary sum and
+    /// N and V from the sum before the high digit is adjusted; the 65C02
+    /// sets N and Z from the BCD result. Invalid BCD operands give the same
+    /// results as on real chips.
+    /// See: <http://www.6502.org/tutorials/decimal_mode.html>
+    fn add_decimal(&mut self, operand: u8) {
+        let a = self.state.get_a();
+        let carry = self.state.get_c();
+
+        let mut low = (a & 0x0F) as u16 + (operand & 0x0F) as u16 + carry as u16;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let high = (a & 0xF0) as u16 + (operand & 0xF0) as u16 + low;
+        let signed_sum = (a & 0xF0) as i8 as i16 + (operand & 0xF0) as i8 as i16 + low as i16;
+        let sum = if high >= 0xA0 { high + 0x60 } else { high };
+
+        let result = (sum & 0xFF) as u8;
+        self.state.set_a(result);
+        self.state.set_c((sum > 0xFF) as u8);
+        self.state.set_v(!(-128..=127).contains(&signed_sum));
+        if self.variant == Variant::WDC65C02 {
+            self.state.set_z(result);
+            self.state.set_n(result);
+        } else {
+            self.state
+                .set_z(a.wrapping_add(operand).wrapping_add(carry));
+            self.state.set_n(high as u8);
+        }
     }
 
-    fn ldx(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
+    /// Decimal mode subtraction result. The 65C02 adjusts both digits after
+    /// subtracting, the NMOS 6502 adjusts the low digit before subtracting
+    /// the high one.
+    fn subtract_decimal(&self, a: u8, operand: u8, carry: u8) -> u8 {
+        let borrow = 1 - carry as i16;
+        let mut low = (a & 0x0F) as i16 - (operand & 0x0F) as i16 - borrow;
+
+        let difference = if self.variant == Variant::WDC65C02 {
+            let mut difference = a as i16 - operand as i16 - borrow;
+            if difference < 0 {
+                difference -= 0x60;
+            }
+            if low < 0 {
+                difference -= 0x06;
+            }
+            difference
+        } else {
+            if low < 0 {
+                low = ((low - 0x06) & 0x0F) - 0x10;
+            }
+            let mut difference = (a & 0xF0) as i16 - (operand & 0xF0) as i16 + low;
+            if difference < 0 {
+                difference -= 0x60;
+            }
+            difference
+        };
+        (difference & 0xFF) as u8
+    }
+
+    fn ldx(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
         self.state.set_x(operand);
         self.state.set_z(self.state.x);
         self.state.set_n(self.state.x);
+        Ok(())
     }
 
-    fn ldy(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
+    fn ldy(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
         self.state.set_y(operand);
         self.state.set_z(self.state.y);
         self.state.set_n(self.state.y);
+        Ok(())
     }
 
-    fn lda(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
+    fn lda(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
         self.state.set_a(operand);
         self.state.set_z(self.state.a);
         self.state.set_n(self.state.a);
+        Ok(())
     }
 
-    fn ora(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
+    fn ora(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
         let a = self.state.get_a() | operand;
         self.state.set_a(a);
         self.state.set_z(a);
         self.state.set_n(a);
+        Ok(())
     }
 
-    fn asl(&mut self, mode: AddressingMode) {
+    fn asl(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
         match mode {
             AddressingMode::ACC => {
-                let c = (self.state.a & 0b1000_0000) >> 7;
-                let result = self.state.a << 1;
+                let result = self.shift_left(self.state.a);
                 self.state.set_a(result);
-                self.state.set_c(c);
-                self.state.set_z(result);
-                self.state.set_n(result);
             }
             _ => {
-                let address = self.state.resolve_address(mode);
-                let value = self.state.read_byte(address);
-                let c = (value & 0b1000_0000) >> 7;
-                let result = value << 1;
-                self.state.write_byte(address, result);
-                self.state.set_c(c);
-                self.state.set_z(result);
-                self.state.set_n(result);
+                let address = self.resolve_shift_address(mode)?;
+                self.modify_at(address, Self::shift_left);
             }
         }
+        Ok(())
+    }
+
+    fn shift_left(&mut self, value: u8) -> u8 {
+        let c = (value & 0b1000_0000) >> 7;
+        let result = value << 1;
+        self.state.set_c(c);
+        self.state.set_z(result);
+        self.state.set_n(result);
+        result
+    }
+
+    /// Read-modify-write of memory. Returns the result.
+    fn modify(
+        &mut self,
+        mode: AddressingMode,
+        operation: impl FnOnce(&mut Self, u8) -> u8,
+    ) -> Result<u8, CpuError> {
+        let address = self.state.resolve_address(mode)?;
+        Ok(self.modify_at(address, operation))
+    }
+
+    /// The NMOS 6502 writes the unmodified value back before writing the
+    /// result, the 65C02 reads it twice instead.
+    fn modify_at(&mut self, address: u16, operation: impl FnOnce(&mut Self, u8) -> u8) -> u8 {
+        let value = self.state.read_byte(address);
+        if self.variant == Variant::WDC65C02 {
+            self.state.dummy_read(address);
+        } else {
+            self.state.write_byte_as(address, value, AccessKind::Dummy);
+        }
+        let result = operation(self, value);
+        self.state.write_byte(address, result);
+        result
     }
 
-    fn sta(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
+    /// Effective address of a shift or rotate of memory. On the 65C02 these
+    /// only spend the extra indexing cycle when the index crosses a page.
+    fn resolve_shift_address(&mut self, mode: AddressingMode) -> Result<u16, CpuError> {
+        Ok(match (mode, self.variant) {
+            (AddressingMode::ABSX, Variant::WDC65C02) => {
+                let base = self.state.fetch_word();
+                let address = base.wrapping_add(self.state.x as u16);
+                self.state.page_cross_penalty(base, address);
+                address
+            }
+            _ => self.state.resolve_address(mode)?,
+        })
+    }
+
+    fn sta(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let address = self.state.resolve_address(mode)?;
         self.state.write_byte(address, self.state.a);
+        Ok(())
     }
 
-    fn stx(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
+    fn stx(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let address = self.state.resolve_address(mode)?;
         self.state.write_byte(address, self.state.x);
+        Ok(())
     }
 
-    fn sty(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
+    fn sty(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let address = self.state.resolve_address(mode)?;
         self.state.write_byte(address, self.state.y);
+        Ok(())
     }
 
-    fn jmp(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
+    fn jmp(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let address = match (mode, self.variant) {
+            (AddressingMode::IND, Variant::WDC65C02) => {
+                let indirect_address = self.state.fetch_word();
+                // The 65C02 takes an extra cycle to fix the page wrap bug
+                self.state.dummy_read(self.state.pc.wrapping_sub(1));
+                self.state.read_word(indirect_address)
+            }
+            _ => self.state.resolve_address(mode)?,
+        };
         self.state.set_pc(address);
-        self.state.increment_cycles(1);
+        Ok(())
     }
 
     fn dey(&mut self) {
@@ -293,12 +1050,29 @@ impl<T: Memory> CPU<T> {
         self.state.set_n(self.state.x);
     }
 
-    fn bpl(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        if self.state.get_n() == 0 {
+    /// Common implementation of the conditional branches. A taken branch
+    /// costs one extra cycle, and another one if the target is on a
+    /// different page than the next instruction.
+    fn branch(&mut self, mode: AddressingMode, condition: bool) -> Result<(), CpuError> {
+        let address = self.state.resolve_address(mode)?;
+        if condition {
+            // Dummy reads of the next opcode while PC is being updated
+            self.state.dummy_read(self.state.pc);
+            if address & 0xFF00 != self.state.pc & 0xFF00 {
+                let unfixed = (self.state.pc & 0xFF00) | (address & 0x00FF);
+                self.state.dummy_read(unfixed);
+                self.state.increment_cycles(1);
+            }
             self.state.set_pc(address);
             self.state.increment_cycles(1);
         }
+        Ok(())
+    }
+
+    fn bpl(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let condition = self.state.get_n() == 0;
+        self.branch(mode, condition)?;
+        Ok(())
     }
 
     fn pha(&mut self) {
@@ -311,27 +1085,29 @@ impl<T: Memory> CPU<T> {
         self.state.push_byte(status);
     }
 
-    fn cmp(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
-        let a = self.state.get_a();
-        let result = a.wrapping_sub(operand);
-        self.state.set_c(if a >= operand { 1 } else { 0 });
+    fn cmp(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        self.compare(self.state.get_a(), operand);
+        Ok(())
+    }
+
+    fn compare(&mut self, register: u8, operand: u8) {
+        let result = register.wrapping_sub(operand);
+        self.state.set_c(if register >= operand { 1 } else { 0 });
         self.state.set_z(result);
         self.state.set_n(result);
     }
 
-    fn bcs(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        if self.state.get_c() == 1 {
-            self.state.set_pc(address);
-        }
+    fn bcs(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let condition = self.state.get_c() == 1;
+        self.branch(mode, condition)?;
+        Ok(())
     }
 
-    fn bcc(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        if self.state.get_c() == 0 {
-            self.state.set_pc(address);
-        }
+    fn bcc(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let condition = self.state.get_c() == 0;
+        self.branch(mode, condition)?;
+        Ok(())
     }
 
     fn txa(&mut self) {
@@ -390,49 +1166,55 @@ impl<T: Memory> CPU<T> {
         self.state.set_d(1);
     }
 
-    fn jsr(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        let return_address = self.state.pc - 1;
+    fn jsr(&mut self) {
+        let low = self.state.fetch_byte() as u16;
+        self.state.read_stack();
+        // Return address is the address of the high byte of the operand,
+        // which is only fetched after the pushes
+        let return_address = self.state.pc;
         self.state.push_word(return_address);
-        self.state.set_pc(address);
+        let high = self.state.read_byte_as(self.state.pc, AccessKind::Operand) as u16;
+        self.state.set_pc((high << 8) | low);
     }
 
     fn rts(&mut self) {
+        self.state.read_stack();
         let return_address = self.state.pop_word();
-        self.state.set_pc(return_address + 1);
+        self.state.dummy_read(return_address);
+        self.state.set_pc(return_address.wrapping_add(1));
     }
 
-    fn rol(&mut self, mode: AddressingMode) {
+    fn rol(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
         match mode {
             AddressingMode::ACC => {
-                let c = (self.state.a & 0b1000_0000) >> 7;
-                let result = (self.state.a << 1) | self.state.get_c();
+                let result = self.rotate_left(self.state.a);
                 self.state.set_a(result);
-                self.state.set_c(c);
-                self.state.set_z(result);
-                self.state.set_n(result);
             }
             _ => {
-                let address = self.state.resolve_address(mode);
-                let value = self.state.read_byte(address);
-                let c = (value & 0b1000_0000) >> 7;
-                let result = (value << 1) | self.state.get_c();
-                self.state.write_byte(address, result);
-                self.state.set_c(c);
-                self.state.set_z(result);
-                self.state.set_n(result);
+                let address = self.resolve_shift_address(mode)?;
+                self.modify_at(address, Self::rotate_left);
             }
         }
+        Ok(())
     }
 
-    fn bne(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        if self.state.get_z() == 0 {
-            self.state.set_pc(address);
-        }
+    fn rotate_left(&mut self, value: u8) -> u8 {
+        let c = (value & 0b1000_0000) >> 7;
+        let result = (value << 1) | self.state.get_c();
+        self.state.set_c(c);
+        self.state.set_z(result);
+        self.state.set_n(result);
+        result
+    }
+
+    fn bne(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let condition = self.state.get_z() == 0;
+        self.branch(mode, condition)?;
+        Ok(())
     }
 
     fn pla(&mut self) {
+        self.state.read_stack();
         let value = self.state.pop_byte();
         self.state.set_a(value);
         self.state.set_z(self.state.a);
@@ -440,117 +1222,152 @@ impl<T: Memory> CPU<T> {
     }
 
     fn plp(&mut self) {
+        self.state.read_stack();
         self.state.status = self.state.pop_byte() & 0b1110_1111; // ignore break flag
     }
 
-    fn and(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
+    fn and(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
         let a = self.state.get_a() & operand;
         self.state.set_a(a);
         self.state.set_z(a);
         self.state.set_n(a);
+        Ok(())
     }
 
-    fn eor(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
+    fn eor(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
         let a = self.state.get_a() ^ operand;
         self.state.set_a(a);
         self.state.set_z(a);
         self.state.set_n(a);
+        Ok(())
     }
 
-    fn lsr(&mut self, mode: AddressingMode) {
+    fn lsr(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
         match mode {
             AddressingMode::ACC => {
-                let c = self.state.a & 0b0000_0001;
-                let result = self.state.a >> 1;
+                let result = self.shift_right(self.state.a);
                 self.state.set_a(result);
-                self.state.set_c(c);
-                self.state.set_z(result);
-                self.state.set_n(result);
             }
             _ => {
-                let address = self.state.resolve_address(mode);
-                let value = self.state.read_byte(address);
-                let c = value & 0b0000_0001;
-                let result = value >> 1;
-                self.state.write_byte(address, result);
-                self.state.set_c(c);
-                self.state.set_z(result);
-                self.state.set_n(result);
+                let address = self.resolve_shift_address(mode)?;
+                self.modify_at(address, Self::shift_right);
             }
         }
+        Ok(())
+    }
+
+    fn shift_right(&mut self, value: u8) -> u8 {
+        let c = value & 0b0000_0001;
+        let result = value >> 1;
+        self.state.set_c(c);
+        self.state.set_z(result);
+        self.state.set_n(result);
+        result
     }
 
-    fn ror(&mut self, mode: AddressingMode) {
+    fn ror(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
         match mode {
             AddressingMode::ACC => {
-                let c = self.state.a & 0b0000_0001;
-                let result = (self.state.a >> 1) | (self.state.get_c() << 7);
+                let result = self.rotate_right(self.state.a);
                 self.state.set_a(result);
-                self.state.set_c(c);
-                self.state.set_z(result);
-                self.state.set_n(result);
             }
             _ => {
-                let address = self.state.resolve_address(mode);
-                let value = self.state.read_byte(address);
-                let c = value & 0b0000_0001;
-                let result = (value >> 1) | (self.state.get_c() << 7);
-                self.state.write_byte(address, result);
-                self.state.set_c(c);
-                self.state.set_z(result);
-                self.state.set_n(result);
+                let address = self.resolve_shift_address(mode)?;
+                self.modify_at(address, Self::rotate_right);
             }
         }
+        Ok(())
     }
 
-    fn bmi(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        if self.state.get_n() == 1 {
-            self.state.set_pc(address);
-        }
+    fn rotate_right(&mut self, value: u8) -> u8 {
+        let c = value & 0b0000_0001;
+        let result = (value >> 1) | (self.state.get_c() << 7);
+        self.state.set_c(c);
+        self.state.set_z(result);
+        self.state.set_n(result);
+        result
     }
 
-    fn bvs(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        if self.state.get_v() == 1 {
-            self.state.set_pc(address);
-        }
+    fn bmi(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let condition = self.state.get_n() == 1;
+        self.branch(mode, condition)?;
+        Ok(())
     }
 
-    fn bvc(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        if self.state.get_v() == 0 {
-            self.state.set_pc(address);
-        }
+    fn bvs(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let condition = self.state.get_v() == 1;
+        self.branch(mode, condition)?;
+        Ok(())
+    }
+
+    fn bvc(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let condition = self.state.get_v() == 0;
+        self.branch(mode, condition)?;
+        Ok(())
     }
 
     fn rti(&mut self) {
+        self.state.read_stack();
         self.state.status = self.state.pop_byte() & 0b1110_1111; // ignore break flag
         let address = self.state.pop_word();
         self.state.set_pc(address);
-        self.state.increment_cycles(1);
     }
 
-    fn nop(&mut self) {}
+    fn nop(&mut self, mode: AddressingMode, cycles: u8) -> Result<(), CpuError> {
+        // Undocumented NOPs with an operand still read it
+        if let AddressingMode::IMPL = mode {
+            return Ok(());
+        }
+        self.state.fetch_operand(mode)?;
+        if let AddressingMode::ABS = mode {
+            // The 65C02 $5C NOP keeps the bus busy for another four cycles
+            for _ in 4..cycles {
+                self.state.dummy_read(0xFFFF);
+            }
+        }
+        Ok(())
+    }
 
-    fn inc(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        let value = self.state.read_byte(address);
-        let result = value.wrapping_add(1);
-        self.state.write_byte(address, result);
+    fn inc(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        match mode {
+            AddressingMode::ACC => {
+                let result = self.increment(self.state.a);
+                self.state.set_a(result);
+            }
+            _ => {
+                self.modify(mode, Self::increment)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn increment(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
         self.state.set_z(result);
         self.state.set_n(result);
+        result
     }
 
-    fn dec(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        let value = self.state.read_byte(address);
+    fn dec(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        match mode {
+            AddressingMode::ACC => {
+                let result = self.decrement(self.state.a);
+                self.state.set_a(result);
+            }
+            _ => {
+                self.modify(mode, Self::decrement)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decrement(&mut self, value: u8) -> u8 {
         let result = value.wrapping_sub(1);
-        self.state.write_byte(address, result);
         self.state.set_z(result);
         self.state.set_n(result);
+        result
     }
 
     fn tsx(&mut self) {
@@ -559,45 +1376,352 @@ impl<T: Memory> CPU<T> {
         self.state.set_n(self.state.x);
     }
 
-    fn bit(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
+    fn bit(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
         let a = self.state.get_a();
         let result = a & operand;
         self.state.set_z(result);
+        // BIT #imm of the 65C02 only affects Z
+        if let AddressingMode::IMM = mode {
+            return Ok(());
+        }
         self.state.set_n(operand);
         self.state.set_v((operand & 0b0100_0000) != 0);
+        Ok(())
     }
 
-    fn beq(&mut self, mode: AddressingMode) {
-        let address = self.state.resolve_address(mode);
-        if self.state.get_z() == 1 {
-            self.state.set_pc(address);
-        }
+    fn beq(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let condition = self.state.get_z() == 1;
+        self.branch(mode, condition)?;
+        Ok(())
     }
 
-    fn cpx(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
-        let x = self.state.get_x();
-        let result = x.wrapping_sub(operand);
-        self.state.set_c(if x >= operand { 1 } else { 0 });
-        self.state.set_z(result);
-        self.state.set_n(result);
+    fn cpx(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        self.compare(self.state.get_x(), operand);
+        Ok(())
+    }
+
+    fn cpy(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        self.compare(self.state.get_y(), operand);
+        Ok(())
+    }
+
+    // Undocumented NMOS instructions
+
+    fn alr(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        let result = self.shift_right(self.state.a & operand);
+        self.state.set_a(result);
+        Ok(())
+    }
+
+    fn anc(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        let a = self.state.get_a() & operand;
+        self.state.set_a(a);
+        self.state.set_z(a);
+        self.state.set_n(a);
+        self.state.set_c(a & 0b1000_0000);
+        Ok(())
+    }
+
+    fn ane(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        let a = (self.state.a | UNSTABLE_MAGIC) & self.state.x & operand;
+        self.state.set_a(a);
+        self.state.set_z(a);
+        self.state.set_n(a);
+        Ok(())
     }
 
-    fn cpy(&mut self, mode: AddressingMode) {
-        let operand = self.state.fetch_operand(mode);
-        let y = self.state.get_y();
-        let result = y.wrapping_sub(operand);
-        self.state.set_c(if y >= operand { 1 } else { 0 });
+    fn arr(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        let value = self.state.a & operand;
+        let carry = self.state.get_c();
+        let mut result = (value >> 1) | (carry << 7);
         self.state.set_z(result);
-        self.state.set_n(result);
+
+        if self.decimal_mode() {
+            // The decimal adjust works on the AND result, but N and V are
+            // taken from the rotate before adjusting
+            self.state.set_n(carry << 7);
+            self.state.set_v(((value ^ result) & 0b0100_0000) != 0);
+            if (value & 0x0F) + (value & 0x01) > 0x05 {
+                result = (result & 0xF0) | (result.wrapping_add(0x06) & 0x0F);
+            }
+            let c = (value >> 4) + ((value >> 4) & 0x01) > 0x05;
+            if c {
+                result = result.wrapping_add(0x60);
+            }
+            self.state.set_c(c as u8);
+        } else {
+            self.state.set_n(result);
+            self.state.set_c(result & 0b0100_0000);
+            self.state
+                .set_v(((result >> 6) ^ (result >> 5)) & 0x01 != 0);
+        }
+        self.state.set_a(result);
+        Ok(())
+    }
+
+    fn dcp(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let value = self.modify(mode, |_, value| value.wrapping_sub(1))?;
+        self.compare(self.state.get_a(), value);
+        Ok(())
+    }
+
+    fn isc(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let value = self.modify(mode, |_, value| value.wrapping_add(1))?;
+        self.subtract_with_carry(value);
+        Ok(())
+    }
+
+    fn jam(&mut self) {
+        self.halted = true;
+        // Stay on the JAM opcode
+        self.state.pc = self.state.pc.wrapping_sub(1);
+    }
+
+    fn las(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        let value = operand & self.state.sp;
+        self.state.sp = value;
+        self.state.set_x(value);
+        self.state.set_a(value);
+        self.state.set_z(value);
+        self.state.set_n(value);
+        Ok(())
+    }
+
+    fn lax(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        self.state.set_a(operand);
+        self.state.set_x(operand);
+        self.state.set_z(operand);
+        self.state.set_n(operand);
+        Ok(())
+    }
+
+    fn lxa(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        let value = (self.state.a | UNSTABLE_MAGIC) & operand;
+        self.state.set_a(value);
+        self.state.set_x(value);
+        self.state.set_z(value);
+        self.state.set_n(value);
+        Ok(())
+    }
+
+    fn rla(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let value = self.modify(mode, Self::rotate_left)?;
+        let a = self.state.get_a() & value;
+        self.state.set_a(a);
+        self.state.set_z(a);
+        self.state.set_n(a);
+        Ok(())
+    }
+
+    fn rra(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let value = self.modify(mode, Self::rotate_right)?;
+        self.add_with_carry(value);
+        Ok(())
+    }
+
+    fn sax(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let address = self.state.resolve_address(mode)?;
+        self.state.write_byte(address, self.state.a & self.state.x);
+        Ok(())
+    }
+
+    fn sbx(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let operand = self.state.fetch_operand(mode)?;
+        let value = self.state.a & self.state.x;
+        self.compare(value, operand);
+        self.state.set_x(value.wrapping_sub(operand));
+        Ok(())
+    }
+
+    fn sha(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let value = self.state.a & self.state.x;
+        self.store_high_and(mode, value)?;
+        Ok(())
+    }
+
+    fn shx(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        self.store_high_and(mode, self.state.x)?;
+        Ok(())
+    }
+
+    fn shy(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        self.store_high_and(mode, self.state.y)?;
+        Ok(())
+    }
+
+    fn slo(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let value = self.modify(mode, Self::shift_left)?;
+        let a = self.state.get_a() | value;
+        self.state.set_a(a);
+        self.state.set_z(a);
+        self.state.set_n(a);
+        Ok(())
+    }
+
+    fn sre(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let value = self.modify(mode, Self::shift_right)?;
+        let a = self.state.get_a() ^ value;
+        self.state.set_a(a);
+        self.state.set_z(a);
+        self.state.set_n(a);
+        Ok(())
+    }
+
+    fn tas(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        self.state.sp = self.state.a & self.state.x;
+        self.store_high_and(mode, self.state.sp)?;
+        Ok(())
+    }
+
+    /// Store used by `SHA`, `SHX`, `SHY` and `TAS`. The value is ANDed with
+    /// the high byte of the base address plus one, and when the index crosses
+    /// a page the result also replaces the high byte of the address.
+    fn store_high_and(&mut self, mode: AddressingMode, value: u8) -> Result<(), CpuError> {
+        let (base, address) = match mode {
+            AddressingMode::ABSX => {
+                let base = self.state.fetch_word();
+                (base, base.wrapping_add(self.state.x as u16))
+            }
+            AddressingMode::ABSY => {
+                let base = self.state.fetch_word();
+                (base, base.wrapping_add(self.state.y as u16))
+            }
+            AddressingMode::INDY => {
+                let zero_page_address = self.state.fetch_byte();
+                let base = self.state.read_zero_page_word(zero_page_address);
+                (base, base.wrapping_add(self.state.y as u16))
+            }
+            _ => return Err(CpuError::UnsupportedAddressingMode { mode }),
+        };
+        self.state.dummy_read(unfixed_address(base, address));
+
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let address = if base & 0xFF00 != address & 0xFF00 {
+            ((value as u16) << 8) | (address & 0x00FF)
+        } else {
+            address
+        };
+        self.state.write_byte(address, value);
+        Ok(())
+    }
+
+    // 65C02 instructions
+
+    fn bbr(&mut self, bit: u8) -> Result<(), CpuError> {
+        self.branch_on_bit(bit, false)?;
+        Ok(())
+    }
+
+    fn bbs(&mut self, bit: u8) -> Result<(), CpuError> {
+        self.branch_on_bit(bit, true)?;
+        Ok(())
+    }
+
+    fn branch_on_bit(&mut self, bit: u8, set: bool) -> Result<(), CpuError> {
+        let zero_page_address = self.state.fetch_byte() as u16;
+        let value = self.state.read_byte(zero_page_address);
+        self.state.dummy_read(zero_page_address);
+        let condition = (value >> bit) & 0x01 == set as u8;
+        self.branch(AddressingMode::REL, condition)?;
+        Ok(())
+    }
+
+    fn bra(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        self.branch(mode, true)?;
+        Ok(())
+    }
+
+    fn phx(&mut self) {
+        self.state.push_byte(self.state.x);
+    }
+
+    fn phy(&mut self) {
+        self.state.push_byte(self.state.y);
+    }
+
+    fn plx(&mut self) {
+        self.state.read_stack();
+        let value = self.state.pop_byte();
+        self.state.set_x(value);
+        self.state.set_z(value);
+        self.state.set_n(value);
+    }
+
+    fn ply(&mut self) {
+        self.state.read_stack();
+        let value = self.state.pop_byte();
+        self.state.set_y(value);
+        self.state.set_z(value);
+        self.state.set_n(value);
+    }
+
+    fn rmb(&mut self, mode: AddressingMode, bit: u8) -> Result<(), CpuError> {
+        self.modify(mode, |_, value| value & !(1 << bit))?;
+        Ok(())
+    }
+
+    fn smb(&mut self, mode: AddressingMode, bit: u8) -> Result<(), CpuError> {
+        self.modify(mode, |_, value| value | (1 << bit))?;
+        Ok(())
+    }
+
+    fn stp(&mut self) {
+        self.halted = true;
+        self.state.dummy_read(self.state.pc);
+        // Stay on the STP opcode
+        self.state.pc = self.state.pc.wrapping_sub(1);
+    }
+
+    fn stz(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let address = self.state.resolve_address(mode)?;
+        self.state.write_byte(address, 0);
+        Ok(())
+    }
+
+    fn trb(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let a = self.state.a;
+        self.modify(mode, |cpu, value| {
+            cpu.state.set_z(value & a);
+            value & !a
+        })?;
+        Ok(())
+    }
+
+    fn tsb(&mut self, mode: AddressingMode) -> Result<(), CpuError> {
+        let a = self.state.a;
+        self.modify(mode, |cpu, value| {
+            cpu.state.set_z(value & a);
+            value | a
+        })?;
+        Ok(())
+    }
+
+    fn wai(&mut self) {
+        self.waiting = true;
+        self.state.dummy_read(self.state.pc);
+        // Stay on the WAI opcode until an interrupt is requested
+        self.state.pc = self.state.pc.wrapping_sub(1);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{instrumentation::Trace, memory::Memory, memory::PlainMemory, state};
-    use circular_buffer::CircularBuffer;
+    use crate::{
+        asm, error::CpuError, instruction, instrumentation::NoopSink,
+        instrumentation::RingBufferSink, instrumentation::Trace, memory::AccessKind,
+        memory::Memory, memory::PlainMemory, run::StopConditions, run::StopReason, state,
+        test_util::cpu_with_program, test_util::cpu_with_variant,
+    };
     use std::fs;
 
     #[test]
@@ -608,19 +1732,614 @@ mod tests {
 
         let mut memory = PlainMemory::new();
         for (i, byte) in program.iter().enumerate() {
-            memory.set(0x0600 + i as u16, *byte);
+            memory.poke(0x0600 + i as u16, *byte);
         }
 
-        memory.set(state::RESET_VECTOR_ADDR, 0x00);
-        memory.set(state::RESET_VECTOR_ADDR + 1, 0x06);
+        memory.poke(state::RESET_VECTOR_ADDR, 0x00);
+        memory.poke(state::RESET_VECTOR_ADDR + 1, 0x06);
 
         let mut cpu_state = super::CPUState::new(memory);
         cpu_state.reset();
 
         let mut cpu = super::CPU::new(cpu_state);
-        cpu.execute(1000);
+        let mut sink = RingBufferSink::new(2);
+        cpu.execute(1000, &mut sink);
 
         assert_eq!(cpu.state.a, 0xFF);
+        let pcs: Vec<u16> = sink.traces().map(|trace| trace.pc).collect();
+        assert_eq!(pcs, [0x0608, 0x0608]);
+    }
+
+    #[test]
+    fn test_irq() {
+        // CLI, NOP, NOP
+        let mut cpu = cpu_with_program(&[0x58, 0xEA, 0xEA], 0x0600);
+        cpu.state.write_word(state::IRQ_VECTOR_ADDR, 0x0700);
+        cpu.state.write_byte(0x0700, 0xEA);
+
+        cpu.step();
+        cpu.set_irq(true);
+        let trace = cpu.step();
+
+        assert_eq!(trace.pc, 0x0700);
+        assert_eq!(cpu.state.get_i(), 1);
+        // Reset, CLI, interrupt, NOP
+        assert_eq!(cpu.state.cycles, 7 + 2 + 7 + 2);
+        let status = cpu.state.pop_byte();
+        assert_eq!(status & 0b0011_0000, 0b0010_0000);
+        assert_eq!(cpu.state.pop_word(), 0x0601);
+    }
+
+    #[test]
+    fn test_irq_masked() {
+        // SEI, NOP
+        let mut cpu = cpu_with_program(&[0x78, 0xEA], 0x0600);
+        cpu.state.write_word(state::IRQ_VECTOR_ADDR, 0x0700);
+
+        cpu.step();
+        cpu.set_irq(true);
+        let trace = cpu.step();
+
+        assert_eq!(trace.pc, 0x0601);
+        assert_eq!(cpu.state.sp, 0xFD);
+    }
+
+    #[test]
+    fn test_nmi_is_edge_triggered() {
+        // SEI, NOP, NOP, NOP
+        let mut cpu = cpu_with_program(&[0x78, 0xEA, 0xEA, 0xEA], 0x0600);
+        cpu.state.write_word(state::NMI_VECTOR_ADDR, 0x0700);
+        // NOP, RTI
+        cpu.state.write_byte(0x0700, 0xEA);
+        cpu.state.write_byte(0x0701, 0x40);
+
+        cpu.step();
+        cpu.set_nmi(true);
+        assert_eq!(cpu.step().pc, 0x0700);
+        assert_eq!(cpu.step().pc, 0x0701);
+        // line is still asserted, but no new edge
+        assert_eq!(cpu.step().pc, 0x0601);
+
+        cpu.set_nmi(false);
+        cpu.set_nmi(true);
+        assert_eq!(cpu.step().pc, 0x0700);
+    }
+
+    #[test]
+    fn test_rdy() {
+        // LDA #$01, STA $0200
+        for (cpu, write_waits) in [
+            (
+                cpu_with_program(&[0xA9, 0x01, 0x8D, 0x00, 0x02], 0x0600),
+                false,
+            ),
+            (
+                cpu_with_variant(
+                    &[0xA9, 0x01, 0x8D, 0x00, 0x02],
+                    0x0600,
+                    super::Variant::WDC65C02,
+                ),
+                true,
+            ),
+        ] {
+            let mut cpu = cpu;
+            cpu.set_rdy(false);
+            assert_eq!(
+                cpu.try_step().unwrap_err(),
+                CpuError::NotReady { pc: 0x0600 }
+            );
+            // Waits on the opcode fetch
+            let start = cpu.state.cycles;
+            assert!(cpu.tick().is_none());
+            assert!(cpu.tick().is_none());
+            assert_eq!(cpu.state.cycles, start + 2);
+            cpu.set_rdy(true);
+            cpu.step();
+            assert_eq!(cpu.state.cycles, start + 4);
+
+            // Pull RDY low before the write cycle of STA
+            for _ in 0..3 {
+                assert!(cpu.tick().is_none());
+            }
+            cpu.set_rdy(false);
+            let trace = cpu.tick();
+            assert_eq!(trace.is_none(), write_waits);
+            assert_eq!(cpu.state.peek_byte(0x0200), !write_waits as u8);
+            cpu.set_rdy(true);
+            if write_waits {
+                assert_eq!(cpu.tick().unwrap().cycles, 4);
+            }
+            assert_eq!(cpu.state.cycles, start + 8 + write_waits as u64);
+        }
+    }
+
+    #[test]
+    fn test_execute_not_ready() {
+        // LDA #$01, STA $0200
+        let mut cpu = cpu_with_program(&[0xA9, 0x01, 0x8D, 0x00, 0x02], 0x0600);
+        let start = cpu.state.cycles;
+        cpu.set_rdy(false);
+        let mut traces = 0;
+        cpu.execute(start + 10, &mut |_: &Trace| traces += 1);
+        assert_eq!(traces, 0);
+        assert_eq!(cpu.state.cycles, start + 10);
+        assert_eq!(cpu.state.pc, 0x0600);
+
+        // The wait cycles come on top of the instructions' own
+        cpu.set_rdy(true);
+        cpu.execute(start + 16, &mut |_: &Trace| traces += 1);
+        assert_eq!(traces, 2);
+        assert_eq!(cpu.state.cycles, start + 16);
+        assert_eq!(cpu.state.peek_byte(0x0200), 0x01);
+    }
+
+    #[test]
+    fn test_so() {
+        // CLV, NOP
+        let mut cpu = cpu_with_program(&[0xB8, 0xEA], 0x0600);
+        cpu.set_so(false);
+        assert_eq!(cpu.state.get_v(), 1);
+        cpu.step();
+        // No new falling edge
+        cpu.set_so(false);
+        assert_eq!(cpu.state.get_v(), 0);
+        cpu.set_so(true);
+        cpu.set_so(false);
+        assert_eq!(cpu.state.get_v(), 1);
+
+        // Taking effect in the middle of an instruction
+        cpu.state.set_v(false);
+        cpu.tick();
+        cpu.set_so(true);
+        cpu.set_so(false);
+        cpu.tick();
+        assert_eq!(cpu.state.get_v(), 1);
+    }
+
+    #[test]
+    fn test_stall() {
+        // NOP, NOP
+        let mut cpu = cpu_with_program(&[0xEA, 0xEA], 0x0600);
+        let start = cpu.state.cycles;
+        cpu.stall(3);
+        assert_eq!(cpu.step().cycles, 2);
+        assert_eq!(cpu.state.cycles, start + 5);
+
+        cpu.stall(2);
+        let mut ticks = 1;
+        while cpu.tick().is_none() {
+            ticks += 1;
+        }
+        assert_eq!(ticks, 4);
+        assert_eq!(cpu.state.cycles, start + 9);
+    }
+
+    #[test]
+    fn test_page_cross_penalty() {
+        // LDX #$01, LDA $06FF,X, LDA $0600,X, STA $06FF,X
+        let program = [
+            0xA2, 0x01, 0xBD, 0xFF, 0x06, 0xBD, 0x00, 0x06, 0x9D, 0xFF, 0x06,
+        ];
+        let mut cpu = cpu_with_program(&program, 0x0600);
+
+        assert_eq!(cpu.step().cycles, 2);
+        assert_eq!(cpu.step().cycles, 5);
+        assert_eq!(cpu.step().cycles, 4);
+        // stores always take the fixed count
+        assert_eq!(cpu.step().cycles, 5);
+        assert_eq!(cpu.state.cycles, 7 + 16);
+    }
+
+    #[test]
+    fn test_branch_penalty() {
+        // SEC, BCC +2 (not taken), BCS +2 (taken)
+        let program = [0x38, 0x90, 0x02, 0xB0, 0x02];
+        let mut cpu = cpu_with_program(&program, 0x0600);
+        assert_eq!(cpu.step().cycles, 2);
+        assert_eq!(cpu.step().cycles, 2);
+        assert_eq!(cpu.step().cycles, 3);
+        assert_eq!(cpu.state.pc, 0x0607);
+
+        // SEC, BCS +2 branching from $06FE to $0700
+        let program = [0x38, 0xB0, 0x02];
+        let mut cpu = cpu_with_program(&program, 0x06FB);
+        cpu.step();
+        assert_eq!(cpu.step().cycles, 4);
+        assert_eq!(cpu.state.pc, 0x0700);
+    }
+
+    #[test]
+    fn test_tick_read_modify_write() {
+        struct WriteLog {
+            memory: PlainMemory,
+            writes: Vec<(u16, u8)>,
+        }
+
+        impl Memory for WriteLog {
+            fn read(&mut self, address: u16, kind: AccessKind) -> u8 {
+                self.memory.read(address, kind)
+            }
+
+            fn write(&mut self, address: u16, value: u8, kind: AccessKind) {
+                self.writes.push((address, value));
+                self.memory.write(address, value, kind);
+            }
+
+            fn peek(&self, address: u16) -> u8 {
+                self.memory.peek(address)
+            }
+        }
+
+        let mut memory = WriteLog {
+            memory: PlainMemory::new(),
+            writes: Vec::new(),
+        };
+        // LDX #$05, INC $10,X
+        for (i, byte) in [0xA2, 0x05, 0xF6, 0x10].iter().enumerate() {
+            memory.memory.poke(0x0600 + i as u16, *byte);
+        }
+        memory.memory.poke(0x0015, 0x41);
+        memory.memory.poke(state::RESET_VECTOR_ADDR + 1, 0x06);
+
+        let mut cpu_state = super::CPUState::new(memory);
+        cpu_state.reset();
+        let mut cpu = super::CPU::new(cpu_state);
+        cpu.step();
+
+        for cycle in 1..6 {
+            assert!(cpu.tick().is_none());
+            assert_eq!(cpu.state.cycles, 7 + 2 + cycle);
+            assert_eq!(cpu.state.x, 0x05);
+            assert_eq!(cpu.state.pc, 0x0602);
+        }
+        assert_eq!(cpu.state.get_memory().writes, vec![(0x0015, 0x41)]);
+
+        let trace = cpu.tick().expect("instruction should complete");
+        assert_eq!(trace.cycles, 6);
+        assert_eq!(cpu.state.pc, 0x0604);
+        assert_eq!(
+            cpu.state.get_memory().writes,
+            vec![(0x0015, 0x41), (0x0015, 0x42)]
+        );
+    }
+
+    #[test]
+    fn test_access_kinds() {
+        struct KindLog {
+            memory: PlainMemory,
+            accesses: Vec<(u16, AccessKind)>,
+        }
+
+        impl Memory for KindLog {
+            fn read(&mut self, address: u16, kind: AccessKind) -> u8 {
+                self.accesses.push((address, kind));
+                self.memory.read(address, kind)
+            }
+
+            fn write(&mut self, address: u16, value: u8, kind: AccessKind) {
+                self.accesses.push((address, kind));
+                self.memory.write(address, value, kind);
+            }
+
+            fn peek(&self, address: u16) -> u8 {
+                self.memory.peek(address)
+            }
+        }
+
+        let mut memory = KindLog {
+            memory: PlainMemory::new(),
+            accesses: Vec::new(),
+        };
+        // JSR $0610
+        for (i, byte) in [0x20, 0x10, 0x06].iter().enumerate() {
+            memory.memory.poke(0x0600 + i as u16, *byte);
+        }
+        memory.memory.poke(state::RESET_VECTOR_ADDR + 1, 0x06);
+
+        let mut cpu_state = super::CPUState::new(memory);
+        cpu_state.reset();
+        let mut cpu = super::CPU::new(cpu_state);
+        cpu.step();
+
+        assert_eq!(
+            cpu.state.get_memory().accesses,
+            vec![
+                // Reset sequence
+                (0x0000, AccessKind::Dummy),
+                (0x0000, AccessKind::Dummy),
+                (0x0100, AccessKind::Dummy),
+                (0x01FF, AccessKind::Dummy),
+                (0x01FE, AccessKind::Dummy),
+                (0xFFFC, AccessKind::Vector),
+                (0xFFFD, AccessKind::Vector),
+                // JSR
+                (0x0600, AccessKind::Opcode),
+                (0x0601, AccessKind::Operand),
+                (0x01FD, AccessKind::Dummy),
+                (0x01FD, AccessKind::Stack),
+                (0x01FC, AccessKind::Stack),
+                (0x0602, AccessKind::Operand),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tick_cycles() {
+        let program = fs::read("./fixtures/6502_functional_test.bin").expect("should be there");
+        let mut cpu = cpu_with_program(&program, 0x0000);
+        cpu.state.pc = 0x0400;
+
+        for _ in 0..200_000 {
+            let mut ticks = 1;
+            let trace = loop {
+                match cpu.tick() {
+                    Some(trace) => break trace,
+                    None => ticks += 1,
+                }
+            };
+            assert_eq!(ticks, trace.cycles, "at {:04X}", trace.pc);
+        }
+    }
+
+    #[test]
+    fn test_undocumented_opcodes() {
+        // LAX $10, SAX $11, DCP $12, ISC $13, SLO $14, SBX #$01
+        let program = [
+            0xA7, 0x10, 0x87, 0x11, 0xC7, 0x12, 0xE7, 0x13, 0x07, 0x14, 0xCB, 0x01,
+        ];
+        let mut cpu = cpu_with_program(&program, 0x0600);
+        cpu.set_undocumented_opcodes(true);
+        cpu.state.write_byte(0x10, 0x3C);
+        cpu.state.write_byte(0x12, 0x3D);
+        cpu.state.write_byte(0x13, 0x0F);
+        cpu.state.write_byte(0x14, 0x81);
+
+        cpu.step();
+        assert_eq!((cpu.state.a, cpu.state.x), (0x3C, 0x3C));
+        cpu.step();
+        assert_eq!(cpu.state.read_byte(0x11), 0x3C);
+        cpu.step();
+        assert_eq!(cpu.state.read_byte(0x12), 0x3C);
+        assert_eq!(cpu.state.get_z(), 1);
+        assert_eq!(cpu.state.get_c(), 1);
+        cpu.step();
+        assert_eq!(cpu.state.read_byte(0x13), 0x10);
+        assert_eq!(cpu.state.a, 0x2C);
+        cpu.step();
+        assert_eq!(cpu.state.read_byte(0x14), 0x02);
+        assert_eq!(cpu.state.a, 0x2E);
+        assert_eq!(cpu.state.get_c(), 1);
+        cpu.step();
+        assert_eq!(cpu.state.x, 0x2B);
+        assert_eq!(cpu.state.a, 0x2E);
+        assert_eq!(cpu.state.get_c(), 1);
+    }
+
+    #[test]
+    fn test_undocumented_cycles() {
+        for opcode in 0..=0xFF {
+            let Some(instruction) = instruction::undocumented_opcode_to_instruction(opcode) else {
+                continue;
+            };
+            for index in [0x00, 0x90] {
+                let mut cpu = cpu_with_program(&[opcode, 0x80, 0x12], 0x0600);
+                cpu.set_undocumented_opcodes(true);
+                cpu.state.write_word(0x0080, 0x1280);
+                cpu.state.x = index;
+                cpu.state.y = index;
+
+                let mut ticks = 1;
+                let trace = loop {
+                    match cpu.tick() {
+                        Some(trace) => break trace,
+                        None => ticks += 1,
+                    }
+                };
+                assert_eq!(ticks, trace.cycles, "opcode {:02X}", opcode);
+                assert!(trace.cycles - instruction.cycles <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_jam() {
+        // SEI, JAM
+        let mut cpu = cpu_with_program(&[0x78, 0x02], 0x0600);
+        cpu.set_undocumented_opcodes(true);
+        cpu.step();
+        cpu.step();
+        assert!(cpu.is_halted());
+        cpu.set_nmi(true);
+        assert_eq!(cpu.step().pc, 0x0601);
+        assert_eq!(cpu.state.pc, 0x0601);
+
+        cpu.reset();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.state.pc, 0x0600);
+    }
+
+    #[test]
+    fn test_try_step_errors() {
+        // NOP, JAM
+        let mut cpu = cpu_with_program(&[0xEA, 0x02], 0x0600);
+        cpu.try_step().unwrap();
+        assert_eq!(
+            cpu.try_step().unwrap_err(),
+            CpuError::UnknownOpcode {
+                opcode: 0x02,
+                pc: 0x0601
+            }
+        );
+        assert_eq!(cpu.state.pc, 0x0601);
+
+        cpu.set_undocumented_opcodes(true);
+        cpu.try_step().unwrap();
+        assert_eq!(cpu.try_step().unwrap_err(), CpuError::Halted { pc: 0x0601 });
+    }
+
+    #[test]
+    fn test_65c02_instructions() {
+        // LDA #$0F, TSB $10, STZ $11, INC A, PHX, PLY, BBS1 $10,+1, BRK, BRA -2
+        let mut cpu = cpu_with_variant(
+            &[
+                0xA9, 0x0F, 0x04, 0x10, 0x64, 0x11, 0x1A, 0xDA, 0x7A, 0x9F, 0x10, 0x01, 0x00, 0x80,
+                0xFE,
+            ],
+            0x0600,
+            super::Variant::WDC65C02,
+        );
+        cpu.state.write_byte(0x0010, 0xF0);
+        cpu.state.write_byte(0x0011, 0xFF);
+        cpu.state.x = 0x42;
+
+        for _ in 0..7 {
+            cpu.step();
+        }
+        assert_eq!(cpu.state.read_byte(0x0010), 0xFF);
+        assert_eq!(cpu.state.get_z(), 0);
+        assert_eq!(cpu.state.read_byte(0x0011), 0x00);
+        assert_eq!(cpu.state.a, 0x10);
+        assert_eq!(cpu.state.y, 0x42);
+        assert_eq!(cpu.state.pc, 0x060D);
+
+        let trace = cpu.step();
+        assert_eq!(trace.pc, 0x060D);
+        assert_eq!(cpu.state.pc, 0x060D);
+    }
+
+    #[test]
+    fn test_65c02_decimal() {
+        // SED, CLC, LDA #$99, ADC #$01
+        let mut cpu = cpu_with_variant(
+            &[0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01],
+            0x0600,
+            super::Variant::WDC65C02,
+        );
+        for _ in 0..3 {
+            cpu.step();
+        }
+        let trace = cpu.step();
+        assert_eq!(cpu.state.a, 0x00);
+        assert_eq!(cpu.state.get_c(), 1);
+        assert_eq!(cpu.state.get_z(), 1);
+        assert_eq!(trace.cycles, 3);
+    }
+
+    #[test]
+    fn test_65c02_cycles() {
+        for opcode in 0..=0xFF {
+            let instruction = instruction::wdc65c02_opcode_to_instruction(opcode);
+            for (index, decimal) in [(0x00, false), (0x90, true)] {
+                let mut cpu =
+                    cpu_with_variant(&[opcode, 0x80, 0x12], 0x0600, super::Variant::WDC65C02);
+                cpu.state.write_word(0x0080, 0x1280);
+                cpu.state.x = index;
+                cpu.state.y = index;
+                cpu.state.set_d(decimal as u8);
+
+                let mut ticks = 1;
+                let trace = loop {
+                    match cpu.tick() {
+                        Some(trace) => break trace,
+                        None => ticks += 1,
+                    }
+                };
+                assert_eq!(ticks, trace.cycles, "opcode {:02X}", opcode);
+                assert!(trace.cycles - instruction.cycles <= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wai() {
+        // WAI, NOP
+        let mut cpu = cpu_with_variant(&[0xCB, 0xEA], 0x0600, super::Variant::WDC65C02);
+        cpu.step();
+        assert_eq!(cpu.step().pc, 0x0600);
+
+        // Masked interrupt resumes after the WAI
+        cpu.set_irq(true);
+        let trace = cpu.step();
+        assert_eq!(trace.pc, 0x0601);
+        assert_eq!(cpu.state.pc, 0x0602);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap() {
+        // JMP ($10FF)
+        for (cpu, target) in [
+            (cpu_with_program(&[0x6C, 0xFF, 0x10], 0x0600), 0x1234),
+            (
+                cpu_with_variant(&[0x6C, 0xFF, 0x10], 0x0600, super::Variant::WDC65C02),
+                0x5634,
+            ),
+        ] {
+            let mut cpu = cpu;
+            cpu.state.write_byte(0x10FF, 0x34);
+            cpu.state.write_byte(0x1000, 0x12);
+            cpu.state.write_byte(0x1100, 0x56);
+            cpu.step();
+            assert_eq!(cpu.state.pc, target);
+        }
+    }
+
+    #[test]
+    fn test_zero_page_wrap() {
+        // LDA ($FF),Y, LDA ($FE,X), LDA $FF,X, LDX $FF,Y
+        let mut cpu = cpu_with_program(&[0xB1, 0xFF, 0xA1, 0xFE, 0xB5, 0xFF, 0xB6, 0xFF], 0x0600);
+        cpu.state.write_byte(0x00FF, 0x00);
+        cpu.state.write_byte(0x0000, 0x20);
+        cpu.state.write_byte(0x0100, 0x30);
+        cpu.state.write_byte(0x2001, 0xAA);
+        cpu.state.write_byte(0x2000, 0xBB);
+        cpu.state.x = 0x01;
+        cpu.state.y = 0x01;
+
+        cpu.step();
+        assert_eq!(cpu.state.a, 0xAA);
+        cpu.step();
+        assert_eq!(cpu.state.a, 0xBB);
+        cpu.step();
+        assert_eq!(cpu.state.a, 0x20);
+        cpu.step();
+        assert_eq!(cpu.state.x, 0x20);
+    }
+
+    #[test]
+    fn test_address_space_wrap() {
+        // NOP at $FFFF
+        let mut cpu = cpu_with_program(&[], 0x0600);
+        cpu.state.write_byte(0xFFFF, 0xEA);
+        cpu.state.set_pc(0xFFFF);
+        cpu.step();
+        assert_eq!(cpu.state.pc, 0x0000);
+
+        // LDA #$42 with the operand at $0000, LDA $FFFF,X
+        cpu.state.write_byte(0xFFFF, 0xA9);
+        cpu.state.write_byte(0x0000, 0x42);
+        cpu.state.write_byte(0x0001, 0xBD);
+        cpu.state.write_byte(0x0002, 0xFF);
+        cpu.state.write_byte(0x0003, 0xFF);
+        cpu.state.set_pc(0xFFFF);
+        cpu.step();
+        assert_eq!(cpu.state.a, 0x42);
+        assert_eq!(cpu.state.pc, 0x0001);
+        cpu.state.x = 0x02;
+        let trace = cpu.step();
+        assert_eq!(cpu.state.a, 0xBD);
+        assert_eq!(trace.cycles, 5);
+
+        // Words at $FFFF take their high byte from $0000
+        assert_eq!(cpu.state.read_word(0xFFFF), 0x42A9);
+        cpu.state.write_word(0xFFFF, 0x1234);
+        assert_eq!(cpu.state.peek_byte(0x0000), 0x12);
+
+        // RTS to $0000
+        cpu.state.write_byte(0x0600, 0x60);
+        cpu.state.push_word(0xFFFF);
+        cpu.state.set_pc(0x0600);
+        cpu.step();
+        assert_eq!(cpu.state.pc, 0x0000);
     }
 
     #[test]
@@ -630,41 +2349,109 @@ mod tests {
 
         let mut memory = PlainMemory::new();
         for (i, byte) in program.iter().enumerate() {
-            memory.set(0x0000 + i as u16, *byte);
+            memory.poke(i as u16, *byte);
         }
 
-        memory.set(state::RESET_VECTOR_ADDR, 0x00);
-        memory.set(state::RESET_VECTOR_ADDR + 1, 0x04);
+        memory.poke(state::RESET_VECTOR_ADDR, 0x00);
+        memory.poke(state::RESET_VECTOR_ADDR + 1, 0x04);
 
         let mut cpu_state = super::CPUState::new(memory);
         cpu_state.reset();
 
         let mut cpu = super::CPU::new(cpu_state);
 
-        let mut buffer = CircularBuffer::<100, Trace>::new();
-        let mut stuck = false;
-        loop {
-            let trace = cpu.step();
-            if buffer.back().is_some() {
-                let last_pc = buffer.back().unwrap().pc;
-                stuck = trace.pc == last_pc;
-            }
+        let mut buffer = RingBufferSink::new(100);
+        let reason = cpu.run_until(StopConditions::new().infinite_loop(), &mut buffer);
 
-            buffer.push_back(trace);
-            if stuck {
-                break;
+        // 0x3469 is the last instruction in the test suite
+        if reason != (StopReason::InfiniteLoop { pc: 0x3469 }) {
+            for trace in buffer.traces() {
+                trace.print();
             }
+            panic!("stopped with {:?}", reason);
         }
+    }
 
-        if stuck {
-            for trace in buffer.iter() {
-                trace.print();
-            }
+    #[test]
+    fn run_decimal_test() {
+        // http://www.6502.org/tutorials/decimal_mode.html#B
+        let source = fs::read_to_string("./fixtures/6502_decimal_test.s").expect("should be there");
+        let wdc65c02 = source
+            .replace("JSR A6502", "JSR A65C02")
+            .replace("JSR S6502", "JSR S65C02");
+
+        for (variant, source) in [
+            (super::Variant::MOS6502, source),
+            (super::Variant::WDC65C02, wdc65c02),
+        ] {
+            let program = asm::assemble(&source, variant).unwrap();
+            let mut cpu = cpu_with_variant(&program.bytes, program.origin, variant);
+
+            let reason = cpu.run_until(StopConditions::new().infinite_loop(), &mut NoopSink);
+            assert_eq!(
+                reason,
+                StopReason::InfiniteLoop {
+                    pc: program.symbols["DONE"]
+                }
+            );
+            let state = cpu.get_state();
+            assert_eq!(
+                state.peek_byte(program.symbols["ERROR"]),
+                0,
+                "{:?} failed for N1 = {:02X}, N2 = {:02X}, C = {}",
+                variant,
+                state.peek_byte(program.symbols["N1"]),
+                state.peek_byte(program.symbols["N2"]),
+                cpu.state.y
+            );
         }
+    }
 
-        // 0x3469 is the last instruction in the test suite
-        if cpu.state.pc != 0x3469 {
-            assert!(!stuck);
+    #[test]
+    fn test_2a03_decimal() {
+        // SED, CLC, LDA #$09, ADC #$01, SBC #$01
+        let mut cpu = cpu_with_variant(
+            &[0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0xE9, 0x01],
+            0x0600,
+            super::Variant::Ricoh2A03,
+        );
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert_eq!(cpu.state.get_d(), 1);
+        assert_eq!(cpu.state.a, 0x0A);
+        cpu.step();
+        assert_eq!(cpu.state.a, 0x08);
+    }
+
+    #[test]
+    fn test_6510_io_port() {
+        // LDA #$2F, STA $00, LDA #$E5, STA $01, LDA $01, LDX $00
+        let mut cpu = cpu_with_variant(
+            &[
+                0xA9, 0x2F, 0x85, 0x00, 0xA9, 0xE5, 0x85, 0x01, 0xA5, 0x01, 0xA6, 0x00,
+            ],
+            0x0600,
+            super::Variant::MOS6510,
+        );
+        cpu.state
+            .update_io_port(|io_port| io_port.set_inputs(0b0001_0000));
+        for _ in 0..6 {
+            cpu.step();
         }
+        // Bit 4 is an input and the floating bits 6 and 7 were never driven
+        assert_eq!(cpu.state.a, 0b0011_0101);
+        assert_eq!(cpu.state.x, 0x2F);
+        assert_eq!(cpu.state.io_port().unwrap().pins(), 0b0011_0101);
+        // The writes also reach the memory underneath
+        assert_eq!(cpu.state.get_memory().peek(0x0001), 0xE5);
+
+        // Reset makes every pin an input
+        cpu.reset();
+        assert_eq!(cpu.state.peek_byte(0x0000), 0x00);
+        assert_eq!(cpu.state.io_port().unwrap().pins(), 0b0001_0000);
+
+        let cpu = cpu_with_program(&[], 0x0600);
+        assert!(cpu.state.io_port().is_none());
     }
 }
@@ -0,0 +1,271 @@
+//! Breakpoints and watchpoints. They don't modify memory, the CPU checks
+//! them itself between instructions in `CPU::run_until()`.
+
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use crate::memory::Memory;
+use crate::state::CPUState;
+
+/// Memory accesses a watchpoint triggers on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// A bus access that triggered a watchpoint
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Id returned by `CPU::add_watchpoint()`
+    pub id: usize,
+    pub address: u16,
+    /// Value read or written
+    pub value: u8,
+    pub write: bool,
+}
+
+/// Why the CPU stopped on a breakpoint
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Break {
+    /// Execution breakpoint at the address of the next instruction
+    Breakpoint(u16),
+    /// Execution breakpoint at an address in a specific bank
+    BankBreakpoint { bank: u16, pc: u16 },
+    /// The last instruction accessed a watched address
+    Watchpoint(WatchHit),
+    /// The conditional breakpoint with the id returned by
+    /// `CPU::add_condition()` holds
+    Condition(usize),
+}
+
+struct Watchpoint {
+    id: usize,
+    range: RangeInclusive<u16>,
+    kind: WatchKind,
+}
+
+/// Watchpoints are checked by `CPUState` on every bus access. The first hit
+/// is kept until taken.
+#[derive(Default)]
+pub(crate) struct Watchpoints {
+    list: Vec<Watchpoint>,
+    next_id: usize,
+    hit: Option<WatchHit>,
+}
+
+impl Watchpoints {
+    pub(crate) fn add(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.list.push(Watchpoint { id, range, kind });
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) {
+        self.list.retain(|watchpoint| watchpoint.id != id);
+    }
+
+    pub(crate) fn check(&mut self, address: u16, value: u8, write: bool) {
+        if self.list.is_empty() || self.hit.is_some() {
+            return;
+        }
+        let watchpoint = self.list.iter().find(|watchpoint| {
+            let kind_matches = match watchpoint.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::ReadWrite => true,
+            };
+            kind_matches && watchpoint.range.contains(&address)
+        });
+        if let Some(watchpoint) = watchpoint {
+            self.hit = Some(WatchHit {
+                id: watchpoint.id,
+                address,
+                value,
+                write,
+            });
+        }
+    }
+
+    pub(crate) fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}
+
+type Condition<T> = Box<dyn Fn(&CPUState<T>) -> bool>;
+
+/// Execution and conditional breakpoints held by the CPU
+pub(crate) struct Breakpoints<T: Memory> {
+    addresses: BTreeSet<u16>,
+    banked: BTreeSet<(u16, u16)>,
+    conditions: Vec<(usize, Condition<T>)>,
+    next_id: usize,
+}
+
+impl<T: Memory> Breakpoints<T> {
+    pub(crate) fn new() -> Breakpoints<T> {
+        Breakpoints {
+            addresses: BTreeSet::new(),
+            banked: BTreeSet::new(),
+            conditions: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub(crate) fn add(&mut self, pc: u16) {
+        self.addresses.insert(pc);
+    }
+
+    pub(crate) fn remove(&mut self, pc: u16) {
+        self.addresses.remove(&pc);
+    }
+
+    pub(crate) fn add_banked(&mut self, bank: u16, pc: u16) {
+        self.banked.insert((bank, pc));
+    }
+
+    pub(crate) fn remove_banked(&mut self, bank: u16, pc: u16) {
+        self.banked.remove(&(bank, pc));
+    }
+
+    pub(crate) fn add_condition(&mut self, condition: Condition<T>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.conditions.push((id, condition));
+        id
+    }
+
+    pub(crate) fn remove_condition(&mut self, id: usize) {
+        self.conditions
+            .retain(|(condition_id, _)| *condition_id != id);
+    }
+
+    pub(crate) fn check(&self, state: &CPUState<T>) -> Option<Break> {
+        if self.addresses.contains(&state.pc) {
+            return Some(Break::Breakpoint(state.pc));
+        }
+        if !self.banked.is_empty() {
+            if let Some(bank) = state.get_memory().bank(state.pc) {
+                if self.banked.contains(&(bank, state.pc)) {
+                    return Some(Break::BankBreakpoint { bank, pc: state.pc });
+                }
+            }
+        }
+        self.conditions
+            .iter()
+            .find(|(_, condition)| condition(state))
+            .map(|(id, _)| Break::Condition(*id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Break, WatchHit, WatchKind};
+    use crate::cpu::CPU;
+    use crate::instrumentation::NoopSink;
+    use crate::memory::MemoryMap;
+    use crate::run::{StopConditions, StopReason};
+    use crate::state::{CPUState, RESET_VECTOR_ADDR};
+    use crate::test_util::cpu_with_program;
+
+    // LDX #$00, INX, STX $10, LDA $20, JMP $0602
+    const PROGRAM: [u8; 11] = [
+        0xA2, 0x00, 0xE8, 0x86, 0x10, 0xA5, 0x20, 0x4C, 0x02, 0x06, 0x00,
+    ];
+
+    #[test]
+    fn test_breakpoint() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        cpu.add_breakpoint(0x0605);
+        let reason = cpu.run_until(StopConditions::new(), &mut NoopSink);
+        assert_eq!(reason, StopReason::Break(Break::Breakpoint(0x0605)));
+        assert_eq!(cpu.get_state().pc, 0x0605);
+
+        // Continuing executes the instruction at the breakpoint
+        let reason = cpu.run_until(StopConditions::new(), &mut NoopSink);
+        assert_eq!(reason, StopReason::Break(Break::Breakpoint(0x0605)));
+        assert_eq!(cpu.get_state().x, 2);
+
+        cpu.remove_breakpoint(0x0605);
+        let reason = cpu.run_until(StopConditions::new().instructions(4), &mut NoopSink);
+        assert_eq!(reason, StopReason::Instructions);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        let write = cpu.add_watchpoint(0x0010..=0x0010, WatchKind::Write);
+        let read = cpu.add_watchpoint(0x0018..=0x0020, WatchKind::Read);
+
+        let reason = cpu.run_until(StopConditions::new(), &mut NoopSink);
+        let hit = WatchHit {
+            id: write,
+            address: 0x0010,
+            value: 1,
+            write: true,
+        };
+        assert_eq!(reason, StopReason::Break(Break::Watchpoint(hit)));
+        assert_eq!(cpu.get_state().pc, 0x0605);
+
+        let reason = cpu.run_until(StopConditions::new(), &mut NoopSink);
+        let hit = WatchHit {
+            id: read,
+            address: 0x0020,
+            value: 0,
+            write: false,
+        };
+        assert_eq!(reason, StopReason::Break(Break::Watchpoint(hit)));
+
+        cpu.remove_watchpoint(write);
+        cpu.remove_watchpoint(read);
+        let reason = cpu.run_until(StopConditions::new().instructions(8), &mut NoopSink);
+        assert_eq!(reason, StopReason::Instructions);
+    }
+
+    #[test]
+    fn test_condition() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        let id = cpu.add_condition(|state| state.x == 3 && state.get_z() == 0);
+        let reason = cpu.run_until(StopConditions::new(), &mut NoopSink);
+        assert_eq!(reason, StopReason::Break(Break::Condition(id)));
+        assert_eq!(cpu.get_state().pc, 0x0603);
+    }
+
+    #[test]
+    fn test_bank_breakpoint() {
+        // Bank 1 holds INX, JMP $8000 at $8000
+        let mut banks = vec![0xEA; 0x200];
+        banks[0x100..0x104].copy_from_slice(&[0xE8, 0x4C, 0x00, 0x80]);
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000..=0x7FFF)
+            .banked_rom(0x8000..=0x80FF, &banks)
+            .bank_register(0x9000, 0x8000)
+            .ram(0xFF00..=0xFFFF)
+            .build()
+            .unwrap();
+        // LDA #$01, STA $9000, JMP $8000
+        memory.load(0x0600, &[0xA9, 0x01, 0x8D, 0x00, 0x90, 0x4C, 0x00, 0x80]);
+        let mut cpu_state = CPUState::new(memory);
+        cpu_state.write_word(RESET_VECTOR_ADDR, 0x0600);
+        cpu_state.reset();
+        let mut cpu = CPU::new(cpu_state);
+
+        cpu.add_bank_breakpoint(0, 0x8000);
+        cpu.add_bank_breakpoint(1, 0x8000);
+        let reason = cpu.run_until(StopConditions::new(), &mut NoopSink);
+        assert_eq!(
+            reason,
+            StopReason::Break(Break::BankBreakpoint {
+                bank: 1,
+                pc: 0x8000
+            })
+        );
+
+        let trace = cpu.step();
+        assert_eq!(trace.bank, Some(1));
+        assert_eq!(trace.to_string().get(..7), Some("01:8000"));
+        assert_eq!(cpu.get_state().x, 1);
+    }
+}
@@ -0,0 +1,327 @@
+//! Clocked devices and the `Scheduler` running them alongside the CPU.
+//!
+//! A device that is also memory-mapped is shared between the memory and the
+//! scheduler through an `Rc<RefCell<..>>`: map a clone of it with
+//! `MemoryMapBuilder::device()` and attach it with `Scheduler::attach()`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::CPU;
+use crate::error::CpuError;
+use crate::instrumentation::Trace;
+use crate::instrumentation::TraceSink;
+use crate::memory::Memory;
+
+/// Longest instruction, including an interrupt sequence before it
+const MAX_STEP_CYCLES: u64 = 7 + 8;
+
+/// A peripheral with its own notion of time, like a timer chip or a serial
+/// port.
+pub trait Device {
+    /// Advance the device by `cycles` CPU cycles.
+    fn tick(&mut self, cycles: u64);
+
+    /// Cycles until the device changes its interrupt outputs on its own,
+    /// e.g. when a timer runs out. `None` if nothing is pending. Near an
+    /// event the scheduler runs the CPU one cycle at a time.
+    fn next_event(&self) -> Option<u64> {
+        None
+    }
+
+    /// Whether the device asserts the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Whether the device asserts the NMI line
+    fn nmi(&self) -> bool {
+        false
+    }
+
+    /// Level the device drives on RDY. A device pulls it low to pause the CPU
+    /// on its next read cycle, e.g. while it uses the bus.
+    fn rdy(&self) -> bool {
+        true
+    }
+
+    /// Level the device drives on SO. A falling edge sets the V flag.
+    fn so(&self) -> bool {
+        true
+    }
+
+    /// Cycles the device has stolen from the CPU since it was last asked,
+    /// e.g. for a sprite DMA transfer. The CPU spends them before its next
+    /// instruction.
+    fn take_stall(&mut self) -> u64 {
+        0
+    }
+}
+
+/// Runs the CPU and the attached devices in cycle order. Before every
+/// instruction the devices are caught up to the CPU's cycle count and drive
+/// the IRQ and NMI lines, which are the OR of all device outputs, and the RDY
+/// and SO lines, which any device can pull low. Cycles stolen by a device are
+/// spent and the devices caught up again before the instruction runs.
+pub struct Scheduler<T: Memory> {
+    cpu: CPU<T>,
+    devices: Vec<Rc<RefCell<dyn Device>>>,
+    /// Cycle count the devices have been ticked to
+    time: u64,
+}
+
+impl<T: Memory> Scheduler<T> {
+    pub fn new(cpu: CPU<T>) -> Scheduler<T> {
+        let time = cpu.get_state().cycles;
+        Scheduler {
+            cpu,
+            devices: Vec::new(),
+            time,
+        }
+    }
+
+    /// Attach a device. It is ticked from the current cycle on.
+    pub fn attach<D: Device + 'static>(&mut self, device: Rc<RefCell<D>>) {
+        self.devices.push(device);
+    }
+
+    pub fn cpu(&self) -> &CPU<T> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU<T> {
+        &mut self.cpu
+    }
+
+    pub fn into_cpu(self) -> CPU<T> {
+        self.cpu
+    }
+
+    /// Execute one instruction, ticking the devices along with it.
+    pub fn step<S: TraceSink + ?Sized>(&mut self, sink: &mut S) -> Result<Trace, CpuError> {
+        self.sync();
+        // Catch the devices up with a DMA transfer before the CPU goes on,
+        // so it sees interrupts raised and registers changed during it
+        while self.cpu.spend_stall() > 0 {
+            self.sync();
+        }
+        if self.cpu.is_halted() {
+            return Err(CpuError::Halted {
+                pc: self.cpu.get_state().pc,
+            });
+        }
+
+        let trace = if self.event_due() || !self.cpu.get_rdy() {
+            // Tick the devices on every bus cycle, so the event and any
+            // register accesses around it happen on the right cycle, and
+            // a device holding RDY low can release it
+            loop {
+                let result = self.cpu.try_tick();
+                self.sync();
+                if let Some(trace) = result? {
+                    break trace;
+                }
+            }
+        } else {
+            let result = self.cpu.try_step();
+            self.sync();
+            result?
+        };
+        sink.trace(&trace);
+        Ok(trace)
+    }
+
+    /// Run until at least `cycles` more cycles have passed.
+    pub fn run<S: TraceSink + ?Sized>(
+        &mut self,
+        cycles: u64,
+        sink: &mut S,
+    ) -> Result<(), CpuError> {
+        let end = self.cpu.get_state().cycles + cycles;
+        while self.cpu.get_state().cycles < end {
+            self.step(sink)?;
+        }
+        Ok(())
+    }
+
+    /// Catch the devices up with the CPU and update the interrupt lines.
+    fn sync(&mut self) {
+        let now = self.cpu.get_state().cycles;
+        let elapsed = now.saturating_sub(self.time);
+        self.time = now;
+
+        let mut irq = false;
+        let mut nmi = false;
+        let mut rdy = true;
+        let mut so = true;
+        for device in &self.devices {
+            let mut device = device.borrow_mut();
+            if elapsed > 0 {
+                device.tick(elapsed);
+            }
+            irq |= device.irq();
+            nmi |= device.nmi();
+            rdy &= device.rdy();
+            so &= device.so();
+            self.cpu.stall(device.take_stall());
+        }
+        self.cpu.set_irq(irq);
+        self.cpu.set_nmi(nmi);
+        self.cpu.set_rdy(rdy);
+        self.cpu.set_so(so);
+    }
+
+    /// Whether a device event may fall within the next instruction
+    fn event_due(&self) -> bool {
+        self.devices
+            .iter()
+            .filter_map(|device| device.borrow().next_event())
+            .any(|cycles| cycles <= MAX_STEP_CYCLES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Device, Scheduler};
+    use crate::cpu::CPU;
+    use crate::instrumentation::NoopSink;
+    use crate::memory::{MappedDevice, MemoryMap};
+    use crate::state::{CPUState, IRQ_VECTOR_ADDR, RESET_VECTOR_ADDR};
+    use crate::test_util::cpu_with_program;
+
+    /// Raises IRQ once `remaining` cycles have passed
+    struct Timer {
+        remaining: u64,
+    }
+
+    impl Device for Timer {
+        fn tick(&mut self, cycles: u64) {
+            self.remaining = self.remaining.saturating_sub(cycles);
+        }
+
+        fn next_event(&self) -> Option<u64> {
+            (self.remaining > 0).then_some(self.remaining)
+        }
+
+        fn irq(&self) -> bool {
+            self.remaining == 0
+        }
+    }
+
+    /// Register holding the number of cycles the device has seen
+    struct Clock {
+        time: u64,
+    }
+
+    impl Device for Clock {
+        fn tick(&mut self, cycles: u64) {
+            self.time += cycles;
+        }
+
+        fn next_event(&self) -> Option<u64> {
+            Some(1)
+        }
+    }
+
+    impl MappedDevice for Clock {
+        fn read(&mut self, _offset: u16) -> u8 {
+            self.time as u8
+        }
+
+        fn write(&mut self, _offset: u16, _value: u8) {}
+
+        fn peek(&self, _offset: u16) -> u8 {
+            self.time as u8
+        }
+    }
+
+    /// Steals `pending` cycles for a DMA transfer
+    struct Dma {
+        pending: u64,
+    }
+
+    impl Device for Dma {
+        fn tick(&mut self, _cycles: u64) {}
+
+        fn next_event(&self) -> Option<u64> {
+            None
+        }
+
+        fn take_stall(&mut self) -> u64 {
+            std::mem::take(&mut self.pending)
+        }
+    }
+
+    #[test]
+    fn test_irq_timing() {
+        // CLI, JMP $0601
+        let mut cpu = cpu_with_program(&[0x58, 0x4C, 0x01, 0x06], 0x0600);
+        cpu.get_mut_state().write_word(IRQ_VECTOR_ADDR, 0x0700);
+
+        let mut scheduler = Scheduler::new(cpu);
+        scheduler.attach(Rc::new(RefCell::new(Timer { remaining: 100 })));
+
+        let start = loop {
+            let cycles = scheduler.cpu().get_state().cycles;
+            let trace = scheduler.step(&mut NoopSink).unwrap();
+            if trace.pc == 0x0700 {
+                break cycles;
+            }
+        };
+        // The timer starts after the 7 cycles of reset. First instruction
+        // boundary after it ran out: CLI takes two cycles and every JMP three
+        assert_eq!(start, 108);
+    }
+
+    #[test]
+    fn test_cycle_accurate_access() {
+        let clock = Rc::new(RefCell::new(Clock { time: 0 }));
+        let mut memory = MemoryMap::builder()
+            .ram(0x0000..=0xCFFF)
+            .device(0xD000..=0xD000, clock.clone())
+            .ram(0xD001..=0xFFFF)
+            .build()
+            .unwrap();
+        // NOP, LDA $D000
+        memory.load(0x0600, &[0xEA, 0xAD, 0x00, 0xD0]);
+        let mut cpu_state = CPUState::new(memory);
+        cpu_state.write_word(RESET_VECTOR_ADDR, 0x0600);
+        cpu_state.reset();
+
+        let mut scheduler = Scheduler::new(CPU::new(cpu_state));
+        scheduler.attach(clock.clone());
+        scheduler.run(6, &mut NoopSink).unwrap();
+
+        // The read is on the fourth cycle of LDA, after the two of NOP
+        assert_eq!(scheduler.cpu().get_state().a, 5);
+        assert_eq!(clock.borrow().time, 6);
+    }
+
+    #[test]
+    fn test_dma_stall() {
+        // NOP, NOP
+        let mut scheduler = Scheduler::new(cpu_with_program(&[0xEA, 0xEA], 0x0600));
+        scheduler.attach(Rc::new(RefCell::new(Dma { pending: 513 })));
+        scheduler.step(&mut NoopSink).unwrap();
+        assert_eq!(scheduler.cpu().get_state().cycles, 7 + 513 + 2);
+
+        // The stall is only taken once
+        scheduler.step(&mut NoopSink).unwrap();
+        assert_eq!(scheduler.cpu().get_state().cycles, 7 + 513 + 4);
+
+        // An IRQ raised during the transfer is taken right after it
+        let mut cpu = cpu_with_program(&[0xEA, 0xEA], 0x0600);
+        cpu.get_mut_state().set_i(0);
+        cpu.get_mut_state().write_word(IRQ_VECTOR_ADDR, 0x0700);
+        cpu.get_mut_state().write_byte(0x0700, 0xEA);
+        let mut scheduler = Scheduler::new(cpu);
+        scheduler.attach(Rc::new(RefCell::new(Dma { pending: 513 })));
+        scheduler.attach(Rc::new(RefCell::new(Timer { remaining: 100 })));
+        let trace = scheduler.step(&mut NoopSink).unwrap();
+        assert_eq!(trace.pc, 0x0700);
+        assert_eq!(scheduler.cpu().get_state().cycles, 7 + 513 + 7 + 2);
+    }
+}
@@ -0,0 +1,226 @@
+//! Disassembler for reading code straight from memory, whether it has been
+//! executed or not.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::cpu::Variant;
+use crate::instruction;
+use crate::instruction::AddressingMode;
+use crate::instruction::Instruction;
+use crate::instruction::Operation;
+use crate::memory::Memory;
+
+/// A disassembled instruction
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub address: u16,
+    /// Opcode and operand bytes
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+    pub mnemonic: String,
+    /// Operand in assembler syntax, empty for implied instructions
+    pub operand: String,
+    /// Destination of branches and absolute `JMP` and `JSR`
+    pub target: Option<u16>,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:04X} {:<8}  {}",
+            self.address,
+            bytes.join(" "),
+            self.mnemonic
+        )?;
+        if !self.operand.is_empty() {
+            write!(f, " {}", self.operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of operand bytes following the opcode
+pub fn operand_length(mode: AddressingMode) -> u16 {
+    match mode {
+        AddressingMode::ACC | AddressingMode::IMPL => 0,
+        AddressingMode::IMM
+        | AddressingMode::REL
+        | AddressingMode::ZPG
+        | AddressingMode::ZPGX
+        | AddressingMode::ZPGY
+        | AddressingMode::XIND
+        | AddressingMode::INDY
+        | AddressingMode::ZPIND => 1,
+        AddressingMode::ABS
+        | AddressingMode::ABSX
+        | AddressingMode::ABSY
+        | AddressingMode::IND
+        | AddressingMode::ABSXIND
+        | AddressingMode::ZPREL => 2,
+    }
+}
+
+/// Decode an opcode of `variant`. Undocumented NMOS opcodes are decoded too,
+/// whether the CPU has them enabled or not.
+pub fn decode(opcode: u8, variant: Variant) -> Instruction {
+    match variant {
+        Variant::WDC65C02 => instruction::wdc65c02_opcode_to_instruction(opcode),
+        _ => instruction::opcode_to_instruction(opcode)
+            .or_else(|| instruction::undocumented_opcode_to_instruction(opcode))
+            .expect("every NMOS opcode is documented or undocumented"),
+    }
+}
+
+/// Disassemble the instruction at `address`
+pub fn disassemble_one<T: Memory>(memory: &T, address: u16, variant: Variant) -> Line {
+    let opcode = memory.peek(address);
+    let instruction = decode(opcode, variant);
+    let length = operand_length(instruction.mode);
+    let bytes: Vec<u8> = (0..=length)
+        .map(|offset| memory.peek(address.wrapping_add(offset)))
+        .collect();
+    let operand = match length {
+        0 => None,
+        1 => Some(bytes[1] as u16),
+        _ => Some((bytes[2] as u16) << 8 | bytes[1] as u16),
+    };
+
+    Line {
+        address,
+        bytes,
+        instruction,
+        mnemonic: format_operation(instruction.operation),
+        operand: operand.map_or(String::new(), |operand| {
+            format_operand(operand, address, instruction.mode)
+        }),
+        target: operand.and_then(|operand| branch_target(instruction, operand, address)),
+    }
+}
+
+/// Disassemble the instructions starting in `range`. The last one may extend
+/// past the end of the range.
+pub fn disassemble<T: Memory>(
+    memory: &T,
+    range: RangeInclusive<u16>,
+    variant: Variant,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = *range.start() as u32;
+    while address <= *range.end() as u32 {
+        let line = disassemble_one(memory, address as u16, variant);
+        address += line.bytes.len() as u32;
+        lines.push(line);
+    }
+    lines
+}
+
+fn branch_target(instruction: Instruction, operand: u16, address: u16) -> Option<u16> {
+    match (instruction.operation, instruction.mode) {
+        (_, AddressingMode::REL) => {
+            Some(address.wrapping_add(2).wrapping_add(operand as i8 as u16))
+        }
+        (_, AddressingMode::ZPREL) => Some(
+            address
+                .wrapping_add(3)
+                .wrapping_add((operand >> 8) as i8 as u16),
+        ),
+        (Operation::JMP | Operation::JSR, AddressingMode::ABS) => Some(operand),
+        _ => None,
+    }
+}
+
+/// Mnemonic of an operation
+pub fn format_operation(operation: Operation) -> String {
+    format!("{:?}", operation)
+}
+
+/// Operand in assembler syntax. `pc` is the address of the instruction,
+/// needed for resolving relative branches.
+pub fn format_operand(operand: u16, pc: u16, mode: AddressingMode) -> String {
+    match mode {
+        AddressingMode::ACC => "A".to_string(),
+        AddressingMode::ABS => format!("${:04X}", operand),
+        AddressingMode::ABSX => format!("${:04X},X", operand),
+        AddressingMode::ABSY => format!("${:04X},Y", operand),
+        AddressingMode::IMM => format!("#${:02X}", operand),
+        AddressingMode::IMPL => "".to_string(),
+        AddressingMode::IND => format!("(${:04X})", operand),
+        AddressingMode::XIND => format!("(${:02X},X)", operand),
+        AddressingMode::INDY => format!("(${:02X}),Y", operand),
+        AddressingMode::REL => {
+            let signed_operand = operand as i8;
+            format!(
+                "${:04X}",
+                // 2 comes from having read the opcode and operand bytes
+                pc.wrapping_add(signed_operand as u16).wrapping_add(2)
+            )
+        }
+        AddressingMode::ZPG => format!("${:02X}", operand),
+        AddressingMode::ZPGX => format!("${:02X},X", operand),
+        AddressingMode::ZPGY => format!("${:02X},Y", operand),
+        AddressingMode::ZPIND => format!("(${:02X})", operand),
+        AddressingMode::ABSXIND => format!("(${:04X},X)", operand),
+        AddressingMode::ZPREL => {
+            let signed_offset = (operand >> 8) as i8;
+            format!(
+                "${:02X},${:04X}",
+                operand & 0xFF,
+                // 3 comes from having read the opcode and both operand bytes
+                pc.wrapping_add(signed_offset as u16).wrapping_add(3)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, disassemble_one};
+    use crate::cpu::Variant;
+    use crate::memory::{Memory, PlainMemory};
+
+    fn memory(program: &[u8]) -> PlainMemory {
+        let mut memory = PlainMemory::new();
+        for (i, byte) in program.iter().enumerate() {
+            memory.poke(0x0600 + i as u16, *byte);
+        }
+        memory
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // LDA #$0F, STA $1234,X, BNE -7, JSR $0600, LAX $10
+        let memory = memory(&[
+            0xA9, 0x0F, 0x9D, 0x34, 0x12, 0xD0, 0xF9, 0x20, 0x00, 0x06, 0xA7, 0x10,
+        ]);
+        let lines = disassemble(&memory, 0x0600..=0x060A, Variant::MOS6502);
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(
+            text,
+            [
+                "0600 A9 0F     LDA #$0F",
+                "0602 9D 34 12  STA $1234,X",
+                "0605 D0 F9     BNE $0600",
+                "0607 20 00 06  JSR $0600",
+                "060A A7 10     LAX $10",
+            ]
+        );
+        assert_eq!(lines[0].target, None);
+        assert_eq!(lines[2].target, Some(0x0600));
+        assert_eq!(lines[3].target, Some(0x0600));
+    }
+
+    #[test]
+    fn test_disassemble_65c02() {
+        // BBS7 $12,+2
+        let memory = memory(&[0xFF, 0x12, 0x02]);
+        let line = disassemble_one(&memory, 0x0600, Variant::WDC65C02);
+        assert_eq!(line.to_string(), "0600 FF 12 02  BBS7 $12,$0605");
+        assert_eq!(line.target, Some(0x0605));
+
+        let line = disassemble_one(&memory, 0x0600, Variant::MOS6502);
+        assert_eq!(line.mnemonic, "ISC");
+    }
+}
@@ -0,0 +1,36 @@
+//! Errors returned by the CPU
+
+use std::fmt;
+
+use crate::instruction::AddressingMode;
+
+/// Error raised when the CPU can't execute the next instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    /// The opcode at `pc` is not defined for the emulated CPU
+    UnknownOpcode { opcode: u8, pc: u16 },
+    /// An instruction was used with an addressing mode it doesn't support
+    UnsupportedAddressingMode { mode: AddressingMode },
+    /// The CPU has been halted by `JAM` or `STP` at `pc` and needs a reset
+    Halted { pc: u16 },
+    /// RDY is held low, so the CPU at `pc` can only be advanced a cycle at a
+    /// time with `CPU::tick()`
+    NotReady { pc: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { opcode, pc } => {
+                write!(f, "Unknown opcode {:02X} at {:04X}", opcode, pc)
+            }
+            CpuError::UnsupportedAddressingMode { mode } => {
+                write!(f, "Unsupported addressing mode: {:?}", mode)
+            }
+            CpuError::Halted { pc } => write!(f, "CPU halted at {:04X}", pc),
+            CpuError::NotReady { pc } => write!(f, "CPU waiting for RDY at {:04X}", pc),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
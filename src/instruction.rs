@@ -1,6 +1,6 @@
 //! Description of the instruction set of MOS 6502 CPU
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 /// Addressing mode for the instruction
 /// See: <https://www.masswerk.at/6502/6502_instruction_set.html#description>
 pub enum AddressingMode {
@@ -26,6 +26,10 @@ pub enum AddressingMode {
     XIND,
     /// Indirect with Y register offset
     INDY,
+    /// Zero page indirect (65C02)
+    ZPIND,
+    /// Absolute indirect with X register offset (65C02 `JMP`)
+    ABSXIND,
 
     /// Relative to PC
     REL,
@@ -36,10 +40,13 @@ pub enum AddressingMode {
     ZPGX,
     /// Zero page address with Y register offset
     ZPGY,
+    /// Zero page address followed by an offset relative to PC (65C02 `BBR`
+    /// and `BBS`)
+    ZPREL,
 }
 
 /// Operation for the instruction
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Operation {
     ADC,
     AND,
@@ -97,936 +104,2160 @@ pub enum Operation {
     TXA,
     TXS,
     TYA,
+
+    // Undocumented NMOS instructions
+    /// AND immediate, then LSR A
+    ALR,
+    /// AND immediate, copying N to C
+    ANC,
+    /// Unstable: A = (A | magic) & X & immediate
+    ANE,
+    /// AND immediate, then ROR A with odd flags
+    ARR,
+    /// DEC memory, then CMP
+    DCP,
+    /// INC memory, then SBC
+    ISC,
+    /// Halts the CPU until reset
+    JAM,
+    /// A, X and SP = memory & SP
+    LAS,
+    /// LDA and LDX
+    LAX,
+    /// Unstable: A = X = (A | magic) & immediate
+    LXA,
+    /// ROL memory, then AND
+    RLA,
+    /// ROR memory, then ADC
+    RRA,
+    /// Store A & X
+    SAX,
+    /// X = (A & X) - immediate
+    SBX,
+    /// Unstable: store A & X & (high byte of address + 1)
+    SHA,
+    /// Unstable: store X & (high byte of address + 1)
+    SHX,
+    /// Unstable: store Y & (high byte of address + 1)
+    SHY,
+    /// ASL memory, then ORA
+    SLO,
+    /// LSR memory, then EOR
+    SRE,
+    /// Unstable: SP = A & X, then SHA
+    TAS,
+
+    // 65C02 instructions
+    BBR0,
+    BBR1,
+    BBR2,
+    BBR3,
+    BBR4,
+    BBR5,
+    BBR6,
+    BBR7,
+    BBS0,
+    BBS1,
+    BBS2,
+    BBS3,
+    BBS4,
+    BBS5,
+    BBS6,
+    BBS7,
+    BRA,
+    PHX,
+    PHY,
+    PLX,
+    PLY,
+    RMB0,
+    RMB1,
+    RMB2,
+    RMB3,
+    RMB4,
+    RMB5,
+    RMB6,
+    RMB7,
+    SMB0,
+    SMB1,
+    SMB2,
+    SMB3,
+    SMB4,
+    SMB5,
+    SMB6,
+    SMB7,
+    STP,
+    STZ,
+    TRB,
+    TSB,
+    WAI,
 }
 
 /// Describes an instruction i.e. operation with its addressing mode
+#[derive(Copy, Clone, Debug)]
 pub struct Instruction {
     pub opcode: u8,
     pub operation: Operation,
     pub mode: AddressingMode,
+    /// Base cycle count, not including page crossing or branch penalties
     pub cycles: u8,
 }
 
-/// Maps an opcode to an instruction
-pub fn opcode_to_instruction(opcode: u8) -> Instruction {
+/// Maps an opcode to an instruction. Returns `None` for the undocumented
+/// opcodes.
+pub fn opcode_to_instruction(opcode: u8) -> Option<Instruction> {
     match opcode {
-        0x00 => Instruction {
+        0x00 => Some(Instruction {
             opcode: 0x00,
             operation: Operation::BRK,
             mode: AddressingMode::IMPL,
             cycles: 7,
-        },
-        0x01 => Instruction {
+        }),
+        0x01 => Some(Instruction {
             opcode: 0x01,
             operation: Operation::ORA,
             mode: AddressingMode::XIND,
             cycles: 6,
-        },
-        0x05 => Instruction {
+        }),
+        0x05 => Some(Instruction {
             opcode: 0x05,
             operation: Operation::ORA,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0x06 => Instruction {
+        }),
+        0x06 => Some(Instruction {
             opcode: 0x06,
             operation: Operation::ASL,
             mode: AddressingMode::ZPG,
             cycles: 5,
-        },
-        0x08 => Instruction {
+        }),
+        0x08 => Some(Instruction {
             opcode: 0x08,
             operation: Operation::PHP,
             mode: AddressingMode::IMPL,
             cycles: 3,
-        },
-        0x09 => Instruction {
+        }),
+        0x09 => Some(Instruction {
             opcode: 0x09,
             operation: Operation::ORA,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0x0A => Instruction {
+        }),
+        0x0A => Some(Instruction {
             opcode: 0x0A,
             operation: Operation::ASL,
             mode: AddressingMode::ACC,
             cycles: 2,
-        },
-        0x0D => Instruction {
+        }),
+        0x0D => Some(Instruction {
             opcode: 0x0D,
             operation: Operation::ORA,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0x0E => Instruction {
+        }),
+        0x0E => Some(Instruction {
             opcode: 0x0E,
             operation: Operation::ASL,
             mode: AddressingMode::ABS,
             cycles: 6,
-        },
-        0x10 => Instruction {
+        }),
+        0x10 => Some(Instruction {
             opcode: 0x10,
             operation: Operation::BPL,
             mode: AddressingMode::REL,
             cycles: 2,
-        },
-        0x11 => Instruction {
+        }),
+        0x11 => Some(Instruction {
             opcode: 0x11,
             operation: Operation::ORA,
             mode: AddressingMode::INDY,
             cycles: 5,
-        },
-        0x15 => Instruction {
+        }),
+        0x15 => Some(Instruction {
             opcode: 0x15,
             operation: Operation::ORA,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0x16 => Instruction {
+        }),
+        0x16 => Some(Instruction {
             opcode: 0x16,
             operation: Operation::ASL,
             mode: AddressingMode::ZPGX,
             cycles: 6,
-        },
-        0x18 => Instruction {
+        }),
+        0x18 => Some(Instruction {
             opcode: 0x18,
             operation: Operation::CLC,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0x19 => Instruction {
+        }),
+        0x19 => Some(Instruction {
             opcode: 0x19,
             operation: Operation::ORA,
             mode: AddressingMode::ABSY,
             cycles: 4,
-        },
-        0x1D => Instruction {
+        }),
+        0x1D => Some(Instruction {
             opcode: 0x1D,
             operation: Operation::ORA,
             mode: AddressingMode::ABSX,
             cycles: 4,
-        },
-        0x1E => Instruction {
+        }),
+        0x1E => Some(Instruction {
             opcode: 0x1E,
             operation: Operation::ASL,
             mode: AddressingMode::ABSX,
             cycles: 7,
-        },
-        0x20 => Instruction {
+        }),
+        0x20 => Some(Instruction {
             opcode: 0x20,
             operation: Operation::JSR,
             mode: AddressingMode::ABS,
             cycles: 6,
-        },
-        0x21 => Instruction {
+        }),
+        0x21 => Some(Instruction {
             opcode: 0x21,
             operation: Operation::AND,
             mode: AddressingMode::XIND,
             cycles: 6,
-        },
-        0x24 => Instruction {
+        }),
+        0x24 => Some(Instruction {
             opcode: 0x24,
             operation: Operation::BIT,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0x25 => Instruction {
+        }),
+        0x25 => Some(Instruction {
             opcode: 0x25,
             operation: Operation::AND,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0x26 => Instruction {
+        }),
+        0x26 => Some(Instruction {
             opcode: 0x26,
             operation: Operation::ROL,
             mode: AddressingMode::ZPG,
             cycles: 5,
-        },
-        0x28 => Instruction {
+        }),
+        0x28 => Some(Instruction {
             opcode: 0x28,
             operation: Operation::PLP,
             mode: AddressingMode::IMPL,
             cycles: 4,
-        },
-        0x29 => Instruction {
+        }),
+        0x29 => Some(Instruction {
             opcode: 0x29,
             operation: Operation::AND,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0x2A => Instruction {
+        }),
+        0x2A => Some(Instruction {
             opcode: 0x2A,
             operation: Operation::ROL,
             mode: AddressingMode::ACC,
             cycles: 2,
-        },
-        0x2C => Instruction {
+        }),
+        0x2C => Some(Instruction {
             opcode: 0x2C,
             operation: Operation::BIT,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0x2D => Instruction {
+        }),
+        0x2D => Some(Instruction {
             opcode: 0x2D,
             operation: Operation::AND,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0x2E => Instruction {
+        }),
+        0x2E => Some(Instruction {
             opcode: 0x2E,
             operation: Operation::ROL,
             mode: AddressingMode::ABS,
             cycles: 6,
-        },
-        0x30 => Instruction {
+        }),
+        0x30 => Some(Instruction {
             opcode: 0x30,
             operation: Operation::BMI,
             mode: AddressingMode::REL,
             cycles: 2,
-        },
-        0x31 => Instruction {
+        }),
+        0x31 => Some(Instruction {
             opcode: 0x31,
             operation: Operation::AND,
             mode: AddressingMode::INDY,
             cycles: 5,
-        },
-        0x35 => Instruction {
+        }),
+        0x35 => Some(Instruction {
             opcode: 0x35,
             operation: Operation::AND,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0x36 => Instruction {
+        }),
+        0x36 => Some(Instruction {
             opcode: 0x36,
             operation: Operation::ROL,
             mode: AddressingMode::ZPGX,
             cycles: 6,
-        },
+        }),
 
-        0x38 => Instruction {
+        0x38 => Some(Instruction {
             opcode: 0x38,
             operation: Operation::SEC,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0x39 => Instruction {
+        }),
+        0x39 => Some(Instruction {
             opcode: 0x39,
             operation: Operation::AND,
             mode: AddressingMode::ABSY,
             cycles: 4,
-        },
-        0x3D => Instruction {
+        }),
+        0x3D => Some(Instruction {
             opcode: 0x3D,
             operation: Operation::AND,
             mode: AddressingMode::ABSX,
             cycles: 4,
-        },
-        0x3E => Instruction {
+        }),
+        0x3E => Some(Instruction {
             opcode: 0x3E,
             operation: Operation::ROL,
             mode: AddressingMode::ABSX,
             cycles: 7,
-        },
-        0x40 => Instruction {
+        }),
+        0x40 => Some(Instruction {
             opcode: 0x40,
             operation: Operation::RTI,
             mode: AddressingMode::IMPL,
             cycles: 6,
-        },
-        0x41 => Instruction {
+        }),
+        0x41 => Some(Instruction {
             opcode: 0x41,
             operation: Operation::EOR,
             mode: AddressingMode::XIND,
             cycles: 6,
-        },
-        0x45 => Instruction {
+        }),
+        0x45 => Some(Instruction {
             opcode: 0x45,
             operation: Operation::EOR,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0x46 => Instruction {
+        }),
+        0x46 => Some(Instruction {
             opcode: 0x46,
             operation: Operation::LSR,
             mode: AddressingMode::ZPG,
             cycles: 5,
-        },
+        }),
 
-        0x48 => Instruction {
+        0x48 => Some(Instruction {
             opcode: 0x48,
             operation: Operation::PHA,
             mode: AddressingMode::IMPL,
             cycles: 3,
-        },
-        0x49 => Instruction {
+        }),
+        0x49 => Some(Instruction {
             opcode: 0x49,
             operation: Operation::EOR,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0x4A => Instruction {
+        }),
+        0x4A => Some(Instruction {
             opcode: 0x4A,
             operation: Operation::LSR,
             mode: AddressingMode::ACC,
             cycles: 2,
-        },
+        }),
 
-        0x4C => Instruction {
+        0x4C => Some(Instruction {
             opcode: 0x4C,
             operation: Operation::JMP,
             mode: AddressingMode::ABS,
             cycles: 3,
-        },
-        0x4D => Instruction {
+        }),
+        0x4D => Some(Instruction {
             opcode: 0x4D,
             operation: Operation::EOR,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0x4E => Instruction {
+        }),
+        0x4E => Some(Instruction {
             opcode: 0x4E,
             operation: Operation::LSR,
             mode: AddressingMode::ABS,
             cycles: 6,
-        },
-        0x50 => Instruction {
+        }),
+        0x50 => Some(Instruction {
             opcode: 0x50,
             operation: Operation::BVC,
             mode: AddressingMode::REL,
             cycles: 2,
-        },
-        0x51 => Instruction {
+        }),
+        0x51 => Some(Instruction {
             opcode: 0x51,
             operation: Operation::EOR,
             mode: AddressingMode::INDY,
             cycles: 5,
-        },
-        0x55 => Instruction {
+        }),
+        0x55 => Some(Instruction {
             opcode: 0x55,
             operation: Operation::EOR,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0x56 => Instruction {
+        }),
+        0x56 => Some(Instruction {
             opcode: 0x56,
             operation: Operation::LSR,
             mode: AddressingMode::ZPGX,
             cycles: 6,
-        },
+        }),
 
-        0x58 => Instruction {
+        0x58 => Some(Instruction {
             opcode: 0x58,
             operation: Operation::CLI,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0x59 => Instruction {
+        }),
+        0x59 => Some(Instruction {
             opcode: 0x59,
             operation: Operation::EOR,
             mode: AddressingMode::ABSY,
             cycles: 4,
-        },
+        }),
 
-        0x5D => Instruction {
+        0x5D => Some(Instruction {
             opcode: 0x5D,
             operation: Operation::EOR,
             mode: AddressingMode::ABSX,
             cycles: 4,
-        },
-        0x5E => Instruction {
+        }),
+        0x5E => Some(Instruction {
             opcode: 0x5E,
             operation: Operation::LSR,
             mode: AddressingMode::ABSX,
             cycles: 7,
-        },
-        0x60 => Instruction {
+        }),
+        0x60 => Some(Instruction {
             opcode: 0x60,
             operation: Operation::RTS,
             mode: AddressingMode::IMPL,
             cycles: 6,
-        },
-        0x61 => Instruction {
+        }),
+        0x61 => Some(Instruction {
             opcode: 0x61,
             operation: Operation::ADC,
             mode: AddressingMode::XIND,
             cycles: 6,
-        },
-        0x65 => Instruction {
+        }),
+        0x65 => Some(Instruction {
             opcode: 0x65,
             operation: Operation::ADC,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0x66 => Instruction {
+        }),
+        0x66 => Some(Instruction {
             opcode: 0x66,
             operation: Operation::ROR,
             mode: AddressingMode::ZPG,
             cycles: 5,
-        },
-        0x68 => Instruction {
+        }),
+        0x68 => Some(Instruction {
             opcode: 0x68,
             operation: Operation::PLA,
             mode: AddressingMode::IMPL,
             cycles: 4,
-        },
-        0x69 => Instruction {
+        }),
+        0x69 => Some(Instruction {
             opcode: 0x69,
             operation: Operation::ADC,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0x6A => Instruction {
+        }),
+        0x6A => Some(Instruction {
             opcode: 0x6A,
             operation: Operation::ROR,
             mode: AddressingMode::ACC,
             cycles: 2,
-        },
-        0x6C => Instruction {
+        }),
+        0x6C => Some(Instruction {
             opcode: 0x6C,
             operation: Operation::JMP,
             mode: AddressingMode::IND,
             cycles: 5,
-        },
-        0x6D => Instruction {
+        }),
+        0x6D => Some(Instruction {
             opcode: 0x6D,
             operation: Operation::ADC,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0x6E => Instruction {
+        }),
+        0x6E => Some(Instruction {
             opcode: 0x6E,
             operation: Operation::ROR,
             mode: AddressingMode::ABS,
             cycles: 6,
-        },
-        0x70 => Instruction {
+        }),
+        0x70 => Some(Instruction {
             opcode: 0x70,
             operation: Operation::BVS,
             mode: AddressingMode::REL,
             cycles: 2,
-        },
-        0x71 => Instruction {
+        }),
+        0x71 => Some(Instruction {
             opcode: 0x71,
             operation: Operation::ADC,
             mode: AddressingMode::INDY,
             cycles: 5,
-        },
-        0x75 => Instruction {
+        }),
+        0x75 => Some(Instruction {
             opcode: 0x75,
             operation: Operation::ADC,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0x76 => Instruction {
+        }),
+        0x76 => Some(Instruction {
             opcode: 0x76,
             operation: Operation::ROR,
             mode: AddressingMode::ZPGX,
             cycles: 6,
-        },
-        0x78 => Instruction {
+        }),
+        0x78 => Some(Instruction {
             opcode: 0x78,
             operation: Operation::SEI,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0x79 => Instruction {
+        }),
+        0x79 => Some(Instruction {
             opcode: 0x79,
             operation: Operation::ADC,
             mode: AddressingMode::ABSY,
             cycles: 4,
-        },
+        }),
 
-        0x7D => Instruction {
+        0x7D => Some(Instruction {
             opcode: 0x7D,
             operation: Operation::ADC,
             mode: AddressingMode::ABSX,
             cycles: 4,
-        },
-        0x7E => Instruction {
+        }),
+        0x7E => Some(Instruction {
             opcode: 0x7E,
             operation: Operation::ROR,
             mode: AddressingMode::ABSX,
             cycles: 7,
-        },
+        }),
 
-        0x81 => Instruction {
+        0x81 => Some(Instruction {
             opcode: 0x81,
             operation: Operation::STA,
             mode: AddressingMode::XIND,
             cycles: 6,
-        },
-        0x84 => Instruction {
+        }),
+        0x84 => Some(Instruction {
             opcode: 0x84,
             operation: Operation::STY,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0x85 => Instruction {
+        }),
+        0x85 => Some(Instruction {
             opcode: 0x85,
             operation: Operation::STA,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0x86 => Instruction {
+        }),
+        0x86 => Some(Instruction {
             opcode: 0x86,
             operation: Operation::STX,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
+        }),
 
-        0x88 => Instruction {
+        0x88 => Some(Instruction {
             opcode: 0x88,
             operation: Operation::DEY,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0x8A => Instruction {
+        }),
+        0x8A => Some(Instruction {
             opcode: 0x8A,
             operation: Operation::TXA,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
+        }),
 
-        0x8C => Instruction {
+        0x8C => Some(Instruction {
             opcode: 0x8C,
             operation: Operation::STY,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0x8D => Instruction {
+        }),
+        0x8D => Some(Instruction {
             opcode: 0x8D,
             operation: Operation::STA,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0x8E => Instruction {
+        }),
+        0x8E => Some(Instruction {
             opcode: 0x8E,
             operation: Operation::STX,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0x90 => Instruction {
+        }),
+        0x90 => Some(Instruction {
             opcode: 0x90,
             operation: Operation::BCC,
             mode: AddressingMode::REL,
             cycles: 2,
-        },
-        0x91 => Instruction {
+        }),
+        0x91 => Some(Instruction {
             opcode: 0x91,
             operation: Operation::STA,
             mode: AddressingMode::INDY,
             cycles: 6,
-        },
-        0x94 => Instruction {
+        }),
+        0x94 => Some(Instruction {
             opcode: 0x94,
             operation: Operation::STY,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0x95 => Instruction {
+        }),
+        0x95 => Some(Instruction {
             opcode: 0x95,
             operation: Operation::STA,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0x96 => Instruction {
+        }),
+        0x96 => Some(Instruction {
             opcode: 0x96,
             operation: Operation::STX,
             mode: AddressingMode::ZPGY,
             cycles: 4,
-        },
-        0x98 => Instruction {
+        }),
+        0x98 => Some(Instruction {
             opcode: 0x98,
             operation: Operation::TYA,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0x99 => Instruction {
+        }),
+        0x99 => Some(Instruction {
             opcode: 0x99,
             operation: Operation::STA,
             mode: AddressingMode::ABSY,
             cycles: 5,
-        },
-        0x9A => Instruction {
+        }),
+        0x9A => Some(Instruction {
             opcode: 0x9A,
             operation: Operation::TXS,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0x9D => Instruction {
+        }),
+        0x9D => Some(Instruction {
             opcode: 0x9D,
             operation: Operation::STA,
             mode: AddressingMode::ABSX,
             cycles: 5,
-        },
+        }),
 
-        0xA0 => Instruction {
+        0xA0 => Some(Instruction {
             opcode: 0xA0,
             operation: Operation::LDY,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0xA1 => Instruction {
+        }),
+        0xA1 => Some(Instruction {
             opcode: 0xA1,
             operation: Operation::LDA,
             mode: AddressingMode::XIND,
             cycles: 6,
-        },
-        0xA2 => Instruction {
+        }),
+        0xA2 => Some(Instruction {
             opcode: 0xA2,
             operation: Operation::LDX,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0xA4 => Instruction {
+        }),
+        0xA4 => Some(Instruction {
             opcode: 0xA4,
             operation: Operation::LDY,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0xA5 => Instruction {
+        }),
+        0xA5 => Some(Instruction {
             opcode: 0xA5,
             operation: Operation::LDA,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0xA6 => Instruction {
+        }),
+        0xA6 => Some(Instruction {
             opcode: 0xA6,
             operation: Operation::LDX,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0xA8 => Instruction {
+        }),
+        0xA8 => Some(Instruction {
             opcode: 0xA8,
             operation: Operation::TAY,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xA9 => Instruction {
+        }),
+        0xA9 => Some(Instruction {
             opcode: 0xA9,
             operation: Operation::LDA,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0xAA => Instruction {
+        }),
+        0xAA => Some(Instruction {
             opcode: 0xAA,
             operation: Operation::TAX,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xAC => Instruction {
+        }),
+        0xAC => Some(Instruction {
             opcode: 0xAC,
             operation: Operation::LDY,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0xAD => Instruction {
+        }),
+        0xAD => Some(Instruction {
             opcode: 0xAD,
             operation: Operation::LDA,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0xAE => Instruction {
+        }),
+        0xAE => Some(Instruction {
             opcode: 0xAE,
             operation: Operation::LDX,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0xB0 => Instruction {
+        }),
+        0xB0 => Some(Instruction {
             opcode: 0xB0,
             operation: Operation::BCS,
             mode: AddressingMode::REL,
             cycles: 2,
-        },
-        0xB1 => Instruction {
+        }),
+        0xB1 => Some(Instruction {
             opcode: 0xB1,
             operation: Operation::LDA,
             mode: AddressingMode::INDY,
             cycles: 5,
-        },
-        0xB4 => Instruction {
+        }),
+        0xB4 => Some(Instruction {
             opcode: 0xB4,
             operation: Operation::LDY,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0xB5 => Instruction {
+        }),
+        0xB5 => Some(Instruction {
             opcode: 0xB5,
             operation: Operation::LDA,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0xB6 => Instruction {
+        }),
+        0xB6 => Some(Instruction {
             opcode: 0xB6,
             operation: Operation::LDX,
             mode: AddressingMode::ZPGY,
             cycles: 4,
-        },
-        0xB8 => Instruction {
+        }),
+        0xB8 => Some(Instruction {
             opcode: 0xB8,
             operation: Operation::CLV,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xB9 => Instruction {
+        }),
+        0xB9 => Some(Instruction {
             opcode: 0xB9,
             operation: Operation::LDA,
             mode: AddressingMode::ABSY,
             cycles: 4,
-        },
-        0xBA => Instruction {
+        }),
+        0xBA => Some(Instruction {
             opcode: 0xBA,
             operation: Operation::TSX,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xBC => Instruction {
+        }),
+        0xBC => Some(Instruction {
             opcode: 0xBC,
             operation: Operation::LDY,
             mode: AddressingMode::ABSX,
             cycles: 4,
-        },
-        0xBD => Instruction {
+        }),
+        0xBD => Some(Instruction {
             opcode: 0xBD,
             operation: Operation::LDA,
             mode: AddressingMode::ABSX,
             cycles: 4,
-        },
-        0xBE => Instruction {
+        }),
+        0xBE => Some(Instruction {
             opcode: 0xBE,
             operation: Operation::LDX,
             mode: AddressingMode::ABSY,
             cycles: 4,
-        },
-        0xC0 => Instruction {
+        }),
+        0xC0 => Some(Instruction {
             opcode: 0xC0,
             operation: Operation::CPY,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0xC1 => Instruction {
+        }),
+        0xC1 => Some(Instruction {
             opcode: 0xC1,
             operation: Operation::CMP,
             mode: AddressingMode::XIND,
             cycles: 6,
-        },
-        0xC4 => Instruction {
+        }),
+        0xC4 => Some(Instruction {
             opcode: 0xC4,
             operation: Operation::CPY,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0xC5 => Instruction {
+        }),
+        0xC5 => Some(Instruction {
             opcode: 0xC5,
             operation: Operation::CMP,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0xC6 => Instruction {
+        }),
+        0xC6 => Some(Instruction {
             opcode: 0xC6,
             operation: Operation::DEC,
             mode: AddressingMode::ZPG,
             cycles: 5,
-        },
-        0xC8 => Instruction {
+        }),
+        0xC8 => Some(Instruction {
             opcode: 0xC8,
             operation: Operation::INY,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xC9 => Instruction {
+        }),
+        0xC9 => Some(Instruction {
             opcode: 0xC9,
             operation: Operation::CMP,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
+        }),
 
-        0xCA => Instruction {
+        0xCA => Some(Instruction {
             opcode: 0xCA,
             operation: Operation::DEX,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xCC => Instruction {
+        }),
+        0xCC => Some(Instruction {
             opcode: 0xCC,
             operation: Operation::CPY,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0xCD => Instruction {
+        }),
+        0xCD => Some(Instruction {
             opcode: 0xCD,
             operation: Operation::CMP,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0xCE => Instruction {
+        }),
+        0xCE => Some(Instruction {
             opcode: 0xCE,
             operation: Operation::DEC,
             mode: AddressingMode::ABS,
             cycles: 6,
-        },
-        0xD0 => Instruction {
+        }),
+        0xD0 => Some(Instruction {
             opcode: 0xD0,
             operation: Operation::BNE,
             mode: AddressingMode::REL,
             cycles: 2,
-        },
-        0xD1 => Instruction {
+        }),
+        0xD1 => Some(Instruction {
             opcode: 0xD1,
             operation: Operation::CMP,
             mode: AddressingMode::INDY,
             cycles: 5,
-        },
-        0xD5 => Instruction {
+        }),
+        0xD5 => Some(Instruction {
             opcode: 0xD5,
             operation: Operation::CMP,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0xD6 => Instruction {
+        }),
+        0xD6 => Some(Instruction {
             opcode: 0xD6,
             operation: Operation::DEC,
             mode: AddressingMode::ZPGX,
             cycles: 6,
-        },
-        0xD8 => Instruction {
+        }),
+        0xD8 => Some(Instruction {
             opcode: 0xD8,
             operation: Operation::CLD,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xD9 => Instruction {
+        }),
+        0xD9 => Some(Instruction {
             opcode: 0xD9,
             operation: Operation::CMP,
             mode: AddressingMode::ABSY,
             cycles: 4,
-        },
-        0xDD => Instruction {
+        }),
+        0xDD => Some(Instruction {
             opcode: 0xDD,
             operation: Operation::CMP,
             mode: AddressingMode::ABSX,
             cycles: 4,
-        },
-        0xDE => Instruction {
+        }),
+        0xDE => Some(Instruction {
             opcode: 0xDE,
             operation: Operation::DEC,
             mode: AddressingMode::ABSX,
             cycles: 7,
-        },
-        0xE0 => Instruction {
+        }),
+        0xE0 => Some(Instruction {
             opcode: 0xE0,
             operation: Operation::CPX,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0xE1 => Instruction {
+        }),
+        0xE1 => Some(Instruction {
             opcode: 0xE1,
             operation: Operation::SBC,
             mode: AddressingMode::XIND,
             cycles: 6,
-        },
-        0xE4 => Instruction {
+        }),
+        0xE4 => Some(Instruction {
             opcode: 0xE4,
             operation: Operation::CPX,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0xE5 => Instruction {
+        }),
+        0xE5 => Some(Instruction {
             opcode: 0xE5,
             operation: Operation::SBC,
             mode: AddressingMode::ZPG,
             cycles: 3,
-        },
-        0xE6 => Instruction {
+        }),
+        0xE6 => Some(Instruction {
             opcode: 0xE6,
             operation: Operation::INC,
             mode: AddressingMode::ZPG,
             cycles: 5,
-        },
-        0xE8 => Instruction {
+        }),
+        0xE8 => Some(Instruction {
             opcode: 0xE8,
             operation: Operation::INX,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xE9 => Instruction {
+        }),
+        0xE9 => Some(Instruction {
             opcode: 0xE9,
             operation: Operation::SBC,
             mode: AddressingMode::IMM,
             cycles: 2,
-        },
-        0xEA => Instruction {
+        }),
+        0xEA => Some(Instruction {
             opcode: 0xEA,
             operation: Operation::NOP,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xEC => Instruction {
+        }),
+        0xEC => Some(Instruction {
             opcode: 0xEC,
             operation: Operation::CPX,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0xED => Instruction {
+        }),
+        0xED => Some(Instruction {
             opcode: 0xED,
             operation: Operation::SBC,
             mode: AddressingMode::ABS,
             cycles: 4,
-        },
-        0xEE => Instruction {
+        }),
+        0xEE => Some(Instruction {
             opcode: 0xEE,
             operation: Operation::INC,
             mode: AddressingMode::ABS,
             cycles: 6,
-        },
-        0xF0 => Instruction {
+        }),
+        0xF0 => Some(Instruction {
             opcode: 0xF0,
             operation: Operation::BEQ,
             mode: AddressingMode::REL,
             cycles: 2,
-        },
-        0xF1 => Instruction {
+        }),
+        0xF1 => Some(Instruction {
             opcode: 0xF1,
             operation: Operation::SBC,
             mode: AddressingMode::INDY,
             cycles: 5,
-        },
-        0xF5 => Instruction {
+        }),
+        0xF5 => Some(Instruction {
             opcode: 0xF5,
             operation: Operation::SBC,
             mode: AddressingMode::ZPGX,
             cycles: 4,
-        },
-        0xF6 => Instruction {
+        }),
+        0xF6 => Some(Instruction {
             opcode: 0xF6,
             operation: Operation::INC,
             mode: AddressingMode::ZPGX,
             cycles: 6,
-        },
-        0xF8 => Instruction {
+        }),
+        0xF8 => Some(Instruction {
             opcode: 0xF8,
             operation: Operation::SED,
             mode: AddressingMode::IMPL,
             cycles: 2,
-        },
-        0xF9 => Instruction {
+        }),
+        0xF9 => Some(Instruction {
             opcode: 0xF9,
             operation: Operation::SBC,
             mode: AddressingMode::ABSY,
             cycles: 4,
-        },
-        0xFD => Instruction {
+        }),
+        0xFD => Some(Instruction {
             opcode: 0xFD,
             operation: Operation::SBC,
             mode: AddressingMode::ABSX,
             cycles: 4,
-        },
-        0xFE => Instruction {
+        }),
+        0xFE => Some(Instruction {
             opcode: 0xFE,
             operation: Operation::INC,
             mode: AddressingMode::ABSX,
             cycles: 7,
+        }),
+        _ => None,
+    }
+}
+
+/// Maps an undocumented NMOS opcode to an instruction. Returns `None` for the
+/// documented opcodes.
+/// See: <https://www.masswerk.at/6502/6502_instruction_set.html#illegals>
+pub fn undocumented_opcode_to_instruction(opcode: u8) -> Option<Instruction> {
+    match opcode {
+        0x02 => Some(Instruction {
+            opcode: 0x02,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x03 => Some(Instruction {
+            opcode: 0x03,
+            operation: Operation::SLO,
+            mode: AddressingMode::XIND,
+            cycles: 8,
+        }),
+        0x04 => Some(Instruction {
+            opcode: 0x04,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPG,
+            cycles: 3,
+        }),
+        0x07 => Some(Instruction {
+            opcode: 0x07,
+            operation: Operation::SLO,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        }),
+        0x0B => Some(Instruction {
+            opcode: 0x0B,
+            operation: Operation::ANC,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0x0C => Some(Instruction {
+            opcode: 0x0C,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABS,
+            cycles: 4,
+        }),
+        0x0F => Some(Instruction {
+            opcode: 0x0F,
+            operation: Operation::SLO,
+            mode: AddressingMode::ABS,
+            cycles: 6,
+        }),
+        0x12 => Some(Instruction {
+            opcode: 0x12,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x13 => Some(Instruction {
+            opcode: 0x13,
+            operation: Operation::SLO,
+            mode: AddressingMode::INDY,
+            cycles: 8,
+        }),
+        0x14 => Some(Instruction {
+            opcode: 0x14,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        }),
+        0x17 => Some(Instruction {
+            opcode: 0x17,
+            operation: Operation::SLO,
+            mode: AddressingMode::ZPGX,
+            cycles: 6,
+        }),
+        0x1A => Some(Instruction {
+            opcode: 0x1A,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x1B => Some(Instruction {
+            opcode: 0x1B,
+            operation: Operation::SLO,
+            mode: AddressingMode::ABSY,
+            cycles: 7,
+        }),
+        0x1C => Some(Instruction {
+            opcode: 0x1C,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABSX,
+            cycles: 4,
+        }),
+        0x1F => Some(Instruction {
+            opcode: 0x1F,
+            operation: Operation::SLO,
+            mode: AddressingMode::ABSX,
+            cycles: 7,
+        }),
+        0x22 => Some(Instruction {
+            opcode: 0x22,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x23 => Some(Instruction {
+            opcode: 0x23,
+            operation: Operation::RLA,
+            mode: AddressingMode::XIND,
+            cycles: 8,
+        }),
+        0x27 => Some(Instruction {
+            opcode: 0x27,
+            operation: Operation::RLA,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        }),
+        0x2B => Some(Instruction {
+            opcode: 0x2B,
+            operation: Operation::ANC,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0x2F => Some(Instruction {
+            opcode: 0x2F,
+            operation: Operation::RLA,
+            mode: AddressingMode::ABS,
+            cycles: 6,
+        }),
+        0x32 => Some(Instruction {
+            opcode: 0x32,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x33 => Some(Instruction {
+            opcode: 0x33,
+            operation: Operation::RLA,
+            mode: AddressingMode::INDY,
+            cycles: 8,
+        }),
+        0x34 => Some(Instruction {
+            opcode: 0x34,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        }),
+        0x37 => Some(Instruction {
+            opcode: 0x37,
+            operation: Operation::RLA,
+            mode: AddressingMode::ZPGX,
+            cycles: 6,
+        }),
+        0x3A => Some(Instruction {
+            opcode: 0x3A,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x3B => Some(Instruction {
+            opcode: 0x3B,
+            operation: Operation::RLA,
+            mode: AddressingMode::ABSY,
+            cycles: 7,
+        }),
+        0x3C => Some(Instruction {
+            opcode: 0x3C,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABSX,
+            cycles: 4,
+        }),
+        0x3F => Some(Instruction {
+            opcode: 0x3F,
+            operation: Operation::RLA,
+            mode: AddressingMode::ABSX,
+            cycles: 7,
+        }),
+        0x42 => Some(Instruction {
+            opcode: 0x42,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x43 => Some(Instruction {
+            opcode: 0x43,
+            operation: Operation::SRE,
+            mode: AddressingMode::XIND,
+            cycles: 8,
+        }),
+        0x44 => Some(Instruction {
+            opcode: 0x44,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPG,
+            cycles: 3,
+        }),
+        0x47 => Some(Instruction {
+            opcode: 0x47,
+            operation: Operation::SRE,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        }),
+        0x4B => Some(Instruction {
+            opcode: 0x4B,
+            operation: Operation::ALR,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0x4F => Some(Instruction {
+            opcode: 0x4F,
+            operation: Operation::SRE,
+            mode: AddressingMode::ABS,
+            cycles: 6,
+        }),
+        0x52 => Some(Instruction {
+            opcode: 0x52,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x53 => Some(Instruction {
+            opcode: 0x53,
+            operation: Operation::SRE,
+            mode: AddressingMode::INDY,
+            cycles: 8,
+        }),
+        0x54 => Some(Instruction {
+            opcode: 0x54,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        }),
+        0x57 => Some(Instruction {
+            opcode: 0x57,
+            operation: Operation::SRE,
+            mode: AddressingMode::ZPGX,
+            cycles: 6,
+        }),
+        0x5A => Some(Instruction {
+            opcode: 0x5A,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x5B => Some(Instruction {
+            opcode: 0x5B,
+            operation: Operation::SRE,
+            mode: AddressingMode::ABSY,
+            cycles: 7,
+        }),
+        0x5C => Some(Instruction {
+            opcode: 0x5C,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABSX,
+            cycles: 4,
+        }),
+        0x5F => Some(Instruction {
+            opcode: 0x5F,
+            operation: Operation::SRE,
+            mode: AddressingMode::ABSX,
+            cycles: 7,
+        }),
+        0x62 => Some(Instruction {
+            opcode: 0x62,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x63 => Some(Instruction {
+            opcode: 0x63,
+            operation: Operation::RRA,
+            mode: AddressingMode::XIND,
+            cycles: 8,
+        }),
+        0x64 => Some(Instruction {
+            opcode: 0x64,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPG,
+            cycles: 3,
+        }),
+        0x67 => Some(Instruction {
+            opcode: 0x67,
+            operation: Operation::RRA,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        }),
+        0x6B => Some(Instruction {
+            opcode: 0x6B,
+            operation: Operation::ARR,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0x6F => Some(Instruction {
+            opcode: 0x6F,
+            operation: Operation::RRA,
+            mode: AddressingMode::ABS,
+            cycles: 6,
+        }),
+        0x72 => Some(Instruction {
+            opcode: 0x72,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x73 => Some(Instruction {
+            opcode: 0x73,
+            operation: Operation::RRA,
+            mode: AddressingMode::INDY,
+            cycles: 8,
+        }),
+        0x74 => Some(Instruction {
+            opcode: 0x74,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        }),
+        0x77 => Some(Instruction {
+            opcode: 0x77,
+            operation: Operation::RRA,
+            mode: AddressingMode::ZPGX,
+            cycles: 6,
+        }),
+        0x7A => Some(Instruction {
+            opcode: 0x7A,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x7B => Some(Instruction {
+            opcode: 0x7B,
+            operation: Operation::RRA,
+            mode: AddressingMode::ABSY,
+            cycles: 7,
+        }),
+        0x7C => Some(Instruction {
+            opcode: 0x7C,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABSX,
+            cycles: 4,
+        }),
+        0x7F => Some(Instruction {
+            opcode: 0x7F,
+            operation: Operation::RRA,
+            mode: AddressingMode::ABSX,
+            cycles: 7,
+        }),
+        0x80 => Some(Instruction {
+            opcode: 0x80,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0x82 => Some(Instruction {
+            opcode: 0x82,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0x83 => Some(Instruction {
+            opcode: 0x83,
+            operation: Operation::SAX,
+            mode: AddressingMode::XIND,
+            cycles: 6,
+        }),
+        0x87 => Some(Instruction {
+            opcode: 0x87,
+            operation: Operation::SAX,
+            mode: AddressingMode::ZPG,
+            cycles: 3,
+        }),
+        0x89 => Some(Instruction {
+            opcode: 0x89,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0x8B => Some(Instruction {
+            opcode: 0x8B,
+            operation: Operation::ANE,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0x8F => Some(Instruction {
+            opcode: 0x8F,
+            operation: Operation::SAX,
+            mode: AddressingMode::ABS,
+            cycles: 4,
+        }),
+        0x92 => Some(Instruction {
+            opcode: 0x92,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0x93 => Some(Instruction {
+            opcode: 0x93,
+            operation: Operation::SHA,
+            mode: AddressingMode::INDY,
+            cycles: 6,
+        }),
+        0x97 => Some(Instruction {
+            opcode: 0x97,
+            operation: Operation::SAX,
+            mode: AddressingMode::ZPGY,
+            cycles: 4,
+        }),
+        0x9B => Some(Instruction {
+            opcode: 0x9B,
+            operation: Operation::TAS,
+            mode: AddressingMode::ABSY,
+            cycles: 5,
+        }),
+        0x9C => Some(Instruction {
+            opcode: 0x9C,
+            operation: Operation::SHY,
+            mode: AddressingMode::ABSX,
+            cycles: 5,
+        }),
+        0x9E => Some(Instruction {
+            opcode: 0x9E,
+            operation: Operation::SHX,
+            mode: AddressingMode::ABSY,
+            cycles: 5,
+        }),
+        0x9F => Some(Instruction {
+            opcode: 0x9F,
+            operation: Operation::SHA,
+            mode: AddressingMode::ABSY,
+            cycles: 5,
+        }),
+        0xA3 => Some(Instruction {
+            opcode: 0xA3,
+            operation: Operation::LAX,
+            mode: AddressingMode::XIND,
+            cycles: 6,
+        }),
+        0xA7 => Some(Instruction {
+            opcode: 0xA7,
+            operation: Operation::LAX,
+            mode: AddressingMode::ZPG,
+            cycles: 3,
+        }),
+        0xAB => Some(Instruction {
+            opcode: 0xAB,
+            operation: Operation::LXA,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0xAF => Some(Instruction {
+            opcode: 0xAF,
+            operation: Operation::LAX,
+            mode: AddressingMode::ABS,
+            cycles: 4,
+        }),
+        0xB2 => Some(Instruction {
+            opcode: 0xB2,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0xB3 => Some(Instruction {
+            opcode: 0xB3,
+            operation: Operation::LAX,
+            mode: AddressingMode::INDY,
+            cycles: 5,
+        }),
+        0xB7 => Some(Instruction {
+            opcode: 0xB7,
+            operation: Operation::LAX,
+            mode: AddressingMode::ZPGY,
+            cycles: 4,
+        }),
+        0xBB => Some(Instruction {
+            opcode: 0xBB,
+            operation: Operation::LAS,
+            mode: AddressingMode::ABSY,
+            cycles: 4,
+        }),
+        0xBF => Some(Instruction {
+            opcode: 0xBF,
+            operation: Operation::LAX,
+            mode: AddressingMode::ABSY,
+            cycles: 4,
+        }),
+        0xC2 => Some(Instruction {
+            opcode: 0xC2,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0xC3 => Some(Instruction {
+            opcode: 0xC3,
+            operation: Operation::DCP,
+            mode: AddressingMode::XIND,
+            cycles: 8,
+        }),
+        0xC7 => Some(Instruction {
+            opcode: 0xC7,
+            operation: Operation::DCP,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        }),
+        0xCB => Some(Instruction {
+            opcode: 0xCB,
+            operation: Operation::SBX,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0xCF => Some(Instruction {
+            opcode: 0xCF,
+            operation: Operation::DCP,
+            mode: AddressingMode::ABS,
+            cycles: 6,
+        }),
+        0xD2 => Some(Instruction {
+            opcode: 0xD2,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0xD3 => Some(Instruction {
+            opcode: 0xD3,
+            operation: Operation::DCP,
+            mode: AddressingMode::INDY,
+            cycles: 8,
+        }),
+        0xD4 => Some(Instruction {
+            opcode: 0xD4,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        }),
+        0xD7 => Some(Instruction {
+            opcode: 0xD7,
+            operation: Operation::DCP,
+            mode: AddressingMode::ZPGX,
+            cycles: 6,
+        }),
+        0xDA => Some(Instruction {
+            opcode: 0xDA,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0xDB => Some(Instruction {
+            opcode: 0xDB,
+            operation: Operation::DCP,
+            mode: AddressingMode::ABSY,
+            cycles: 7,
+        }),
+        0xDC => Some(Instruction {
+            opcode: 0xDC,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABSX,
+            cycles: 4,
+        }),
+        0xDF => Some(Instruction {
+            opcode: 0xDF,
+            operation: Operation::DCP,
+            mode: AddressingMode::ABSX,
+            cycles: 7,
+        }),
+        0xE2 => Some(Instruction {
+            opcode: 0xE2,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0xE3 => Some(Instruction {
+            opcode: 0xE3,
+            operation: Operation::ISC,
+            mode: AddressingMode::XIND,
+            cycles: 8,
+        }),
+        0xE7 => Some(Instruction {
+            opcode: 0xE7,
+            operation: Operation::ISC,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        }),
+        0xEB => Some(Instruction {
+            opcode: 0xEB,
+            operation: Operation::SBC,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        }),
+        0xEF => Some(Instruction {
+            opcode: 0xEF,
+            operation: Operation::ISC,
+            mode: AddressingMode::ABS,
+            cycles: 6,
+        }),
+        0xF2 => Some(Instruction {
+            opcode: 0xF2,
+            operation: Operation::JAM,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0xF3 => Some(Instruction {
+            opcode: 0xF3,
+            operation: Operation::ISC,
+            mode: AddressingMode::INDY,
+            cycles: 8,
+        }),
+        0xF4 => Some(Instruction {
+            opcode: 0xF4,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        }),
+        0xF7 => Some(Instruction {
+            opcode: 0xF7,
+            operation: Operation::ISC,
+            mode: AddressingMode::ZPGX,
+            cycles: 6,
+        }),
+        0xFA => Some(Instruction {
+            opcode: 0xFA,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMPL,
+            cycles: 2,
+        }),
+        0xFB => Some(Instruction {
+            opcode: 0xFB,
+            operation: Operation::ISC,
+            mode: AddressingMode::ABSY,
+            cycles: 7,
+        }),
+        0xFC => Some(Instruction {
+            opcode: 0xFC,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABSX,
+            cycles: 4,
+        }),
+        0xFF => Some(Instruction {
+            opcode: 0xFF,
+            operation: Operation::ISC,
+            mode: AddressingMode::ABSX,
+            cycles: 7,
+        }),
+        _ => None,
+    }
+}
+
+/// Maps a WDC 65C02 opcode to an instruction. Every opcode is defined, the
+/// ones without an instruction are NOPs of various lengths.
+/// See: <http://www.6502.org/tutorials/65c02opcodes.html>
+pub fn wdc65c02_opcode_to_instruction(opcode: u8) -> Instruction {
+    match opcode {
+        0x02 => Instruction {
+            opcode: 0x02,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        },
+        0x04 => Instruction {
+            opcode: 0x04,
+            operation: Operation::TSB,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x07 => Instruction {
+            opcode: 0x07,
+            operation: Operation::RMB0,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x0C => Instruction {
+            opcode: 0x0C,
+            operation: Operation::TSB,
+            mode: AddressingMode::ABS,
+            cycles: 6,
+        },
+        0x0F => Instruction {
+            opcode: 0x0F,
+            operation: Operation::BBR0,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x12 => Instruction {
+            opcode: 0x12,
+            operation: Operation::ORA,
+            mode: AddressingMode::ZPIND,
+            cycles: 5,
+        },
+        0x14 => Instruction {
+            opcode: 0x14,
+            operation: Operation::TRB,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x17 => Instruction {
+            opcode: 0x17,
+            operation: Operation::RMB1,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x1A => Instruction {
+            opcode: 0x1A,
+            operation: Operation::INC,
+            mode: AddressingMode::ACC,
+            cycles: 2,
+        },
+        0x1C => Instruction {
+            opcode: 0x1C,
+            operation: Operation::TRB,
+            mode: AddressingMode::ABS,
+            cycles: 6,
+        },
+        0x1E => Instruction {
+            opcode: 0x1E,
+            operation: Operation::ASL,
+            mode: AddressingMode::ABSX,
+            cycles: 6,
+        },
+        0x1F => Instruction {
+            opcode: 0x1F,
+            operation: Operation::BBR1,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x22 => Instruction {
+            opcode: 0x22,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        },
+        0x27 => Instruction {
+            opcode: 0x27,
+            operation: Operation::RMB2,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x2F => Instruction {
+            opcode: 0x2F,
+            operation: Operation::BBR2,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x32 => Instruction {
+            opcode: 0x32,
+            operation: Operation::AND,
+            mode: AddressingMode::ZPIND,
+            cycles: 5,
+        },
+        0x34 => Instruction {
+            opcode: 0x34,
+            operation: Operation::BIT,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        },
+        0x37 => Instruction {
+            opcode: 0x37,
+            operation: Operation::RMB3,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x3A => Instruction {
+            opcode: 0x3A,
+            operation: Operation::DEC,
+            mode: AddressingMode::ACC,
+            cycles: 2,
+        },
+        0x3C => Instruction {
+            opcode: 0x3C,
+            operation: Operation::BIT,
+            mode: AddressingMode::ABSX,
+            cycles: 4,
+        },
+        0x3E => Instruction {
+            opcode: 0x3E,
+            operation: Operation::ROL,
+            mode: AddressingMode::ABSX,
+            cycles: 6,
+        },
+        0x3F => Instruction {
+            opcode: 0x3F,
+            operation: Operation::BBR3,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x42 => Instruction {
+            opcode: 0x42,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        },
+        0x44 => Instruction {
+            opcode: 0x44,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPG,
+            cycles: 3,
+        },
+        0x47 => Instruction {
+            opcode: 0x47,
+            operation: Operation::RMB4,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x4F => Instruction {
+            opcode: 0x4F,
+            operation: Operation::BBR4,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x52 => Instruction {
+            opcode: 0x52,
+            operation: Operation::EOR,
+            mode: AddressingMode::ZPIND,
+            cycles: 5,
+        },
+        0x54 => Instruction {
+            opcode: 0x54,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        },
+        0x57 => Instruction {
+            opcode: 0x57,
+            operation: Operation::RMB5,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x5A => Instruction {
+            opcode: 0x5A,
+            operation: Operation::PHY,
+            mode: AddressingMode::IMPL,
+            cycles: 3,
+        },
+        0x5C => Instruction {
+            opcode: 0x5C,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABS,
+            cycles: 8,
+        },
+        0x5E => Instruction {
+            opcode: 0x5E,
+            operation: Operation::LSR,
+            mode: AddressingMode::ABSX,
+            cycles: 6,
+        },
+        0x5F => Instruction {
+            opcode: 0x5F,
+            operation: Operation::BBR5,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x62 => Instruction {
+            opcode: 0x62,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        },
+        0x64 => Instruction {
+            opcode: 0x64,
+            operation: Operation::STZ,
+            mode: AddressingMode::ZPG,
+            cycles: 3,
+        },
+        0x67 => Instruction {
+            opcode: 0x67,
+            operation: Operation::RMB6,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x6C => Instruction {
+            opcode: 0x6C,
+            operation: Operation::JMP,
+            mode: AddressingMode::IND,
+            cycles: 6,
+        },
+        0x6F => Instruction {
+            opcode: 0x6F,
+            operation: Operation::BBR6,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x72 => Instruction {
+            opcode: 0x72,
+            operation: Operation::ADC,
+            mode: AddressingMode::ZPIND,
+            cycles: 5,
+        },
+        0x74 => Instruction {
+            opcode: 0x74,
+            operation: Operation::STZ,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        },
+        0x77 => Instruction {
+            opcode: 0x77,
+            operation: Operation::RMB7,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x7A => Instruction {
+            opcode: 0x7A,
+            operation: Operation::PLY,
+            mode: AddressingMode::IMPL,
+            cycles: 4,
+        },
+        0x7C => Instruction {
+            opcode: 0x7C,
+            operation: Operation::JMP,
+            mode: AddressingMode::ABSXIND,
+            cycles: 6,
+        },
+        0x7E => Instruction {
+            opcode: 0x7E,
+            operation: Operation::ROR,
+            mode: AddressingMode::ABSX,
+            cycles: 6,
+        },
+        0x7F => Instruction {
+            opcode: 0x7F,
+            operation: Operation::BBR7,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x80 => Instruction {
+            opcode: 0x80,
+            operation: Operation::BRA,
+            mode: AddressingMode::REL,
+            cycles: 2,
+        },
+        0x82 => Instruction {
+            opcode: 0x82,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        },
+        0x87 => Instruction {
+            opcode: 0x87,
+            operation: Operation::SMB0,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x89 => Instruction {
+            opcode: 0x89,
+            operation: Operation::BIT,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        },
+        0x8F => Instruction {
+            opcode: 0x8F,
+            operation: Operation::BBS0,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0x92 => Instruction {
+            opcode: 0x92,
+            operation: Operation::STA,
+            mode: AddressingMode::ZPIND,
+            cycles: 5,
+        },
+        0x97 => Instruction {
+            opcode: 0x97,
+            operation: Operation::SMB1,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0x9C => Instruction {
+            opcode: 0x9C,
+            operation: Operation::STZ,
+            mode: AddressingMode::ABS,
+            cycles: 4,
+        },
+        0x9E => Instruction {
+            opcode: 0x9E,
+            operation: Operation::STZ,
+            mode: AddressingMode::ABSX,
+            cycles: 5,
+        },
+        0x9F => Instruction {
+            opcode: 0x9F,
+            operation: Operation::BBS1,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0xA7 => Instruction {
+            opcode: 0xA7,
+            operation: Operation::SMB2,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0xAF => Instruction {
+            opcode: 0xAF,
+            operation: Operation::BBS2,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0xB2 => Instruction {
+            opcode: 0xB2,
+            operation: Operation::LDA,
+            mode: AddressingMode::ZPIND,
+            cycles: 5,
+        },
+        0xB7 => Instruction {
+            opcode: 0xB7,
+            operation: Operation::SMB3,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0xBF => Instruction {
+            opcode: 0xBF,
+            operation: Operation::BBS3,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0xC2 => Instruction {
+            opcode: 0xC2,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        },
+        0xC7 => Instruction {
+            opcode: 0xC7,
+            operation: Operation::SMB4,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0xCB => Instruction {
+            opcode: 0xCB,
+            operation: Operation::WAI,
+            mode: AddressingMode::IMPL,
+            cycles: 3,
+        },
+        0xCF => Instruction {
+            opcode: 0xCF,
+            operation: Operation::BBS4,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0xD2 => Instruction {
+            opcode: 0xD2,
+            operation: Operation::CMP,
+            mode: AddressingMode::ZPIND,
+            cycles: 5,
+        },
+        0xD4 => Instruction {
+            opcode: 0xD4,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        },
+        0xD7 => Instruction {
+            opcode: 0xD7,
+            operation: Operation::SMB5,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0xDA => Instruction {
+            opcode: 0xDA,
+            operation: Operation::PHX,
+            mode: AddressingMode::IMPL,
+            cycles: 3,
+        },
+        0xDB => Instruction {
+            opcode: 0xDB,
+            operation: Operation::STP,
+            mode: AddressingMode::IMPL,
+            cycles: 3,
+        },
+        0xDC => Instruction {
+            opcode: 0xDC,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABS,
+            cycles: 4,
+        },
+        0xDF => Instruction {
+            opcode: 0xDF,
+            operation: Operation::BBS5,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0xE2 => Instruction {
+            opcode: 0xE2,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMM,
+            cycles: 2,
+        },
+        0xE7 => Instruction {
+            opcode: 0xE7,
+            operation: Operation::SMB6,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0xEF => Instruction {
+            opcode: 0xEF,
+            operation: Operation::BBS6,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        0xF2 => Instruction {
+            opcode: 0xF2,
+            operation: Operation::SBC,
+            mode: AddressingMode::ZPIND,
+            cycles: 5,
+        },
+        0xF4 => Instruction {
+            opcode: 0xF4,
+            operation: Operation::NOP,
+            mode: AddressingMode::ZPGX,
+            cycles: 4,
+        },
+        0xF7 => Instruction {
+            opcode: 0xF7,
+            operation: Operation::SMB7,
+            mode: AddressingMode::ZPG,
+            cycles: 5,
+        },
+        0xFA => Instruction {
+            opcode: 0xFA,
+            operation: Operation::PLX,
+            mode: AddressingMode::IMPL,
+            cycles: 4,
+        },
+        0xFC => Instruction {
+            opcode: 0xFC,
+            operation: Operation::NOP,
+            mode: AddressingMode::ABS,
+            cycles: 4,
+        },
+        0xFF => Instruction {
+            opcode: 0xFF,
+            operation: Operation::BBS7,
+            mode: AddressingMode::ZPREL,
+            cycles: 5,
+        },
+        // Remaining opcodes in columns 3 and B are single cycle NOPs
+        _ if opcode & 0x07 == 0x03 => Instruction {
+            opcode,
+            operation: Operation::NOP,
+            mode: AddressingMode::IMPL,
+            cycles: 1,
         },
-        _ => panic!("Unknown opcode: {:02X}", opcode),
+        _ => opcode_to_instruction(opcode).expect("other opcodes are the same as on the 6502"),
     }
 }
@@ -1,10 +1,16 @@
-//! Contains the `Trace` struct and functions for printing it to stdout.
+//! Contains the `Trace` struct, functions for printing it to stdout and the
+//! `TraceSink` trait for collecting traces from `CPU::execute()`.
 
-use crate::instruction;
-use crate::instruction::AddressingMode;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+
+use crate::disasm::format_operand;
+use crate::disasm::format_operation;
 use crate::instruction::Instruction;
 
 /// Represents the state of the CPU after executing an instruction.
+#[derive(Copy, Clone, Debug)]
 pub struct Trace {
     /// Address of the instruction that was just executed.
     pub pc: u16,
@@ -22,9 +28,16 @@ pub struct Trace {
     pub instruction: Instruction,
     /// Possible operand of the instruction
     pub operand: Option<u16>,
+    /// Cycles taken by the instruction including page crossing and branch
+    /// penalties
+    pub cycles: u8,
+    /// Bank mapped at PC when the instruction was fetched, if the memory is
+    /// banked there
+    pub bank: Option<u16>,
 }
 
 impl Trace {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         pc: u16,
         a: u8,
@@ -34,16 +47,19 @@ impl Trace {
         sr: u8,
         instruction: Instruction,
         operand: Option<u16>,
+        cycles: u8,
     ) -> Trace {
         Trace {
-            pc: pc,
-            a: a,
-            x: x,
-            y: y,
-            sp: sp,
-            sr: sr,
-            instruction: instruction,
-            operand: operand,
+            pc,
+            a,
+            x,
+            y,
+            sp,
+            sr,
+            instruction,
+            operand,
+            cycles,
+            bank: None,
         }
     }
 
@@ -51,7 +67,14 @@ impl Trace {
     /// ```text
     /// PC   Op Oper   Disassembly   |A  X  Y  SP|NVDIZC|C
     /// ```
+    /// PC is prefixed with the bank as in `03:8000` when the trace has one.
     pub fn print(&self) {
+        println!("{}", self);
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let n_flag = (self.sr >> 7) & 1;
         let v_flag = (self.sr >> 6) & 1;
         let d_flag = (self.sr >> 3) & 1;
@@ -78,7 +101,11 @@ impl Trace {
             None => "     ".to_string(),
         };
 
-        println!(
+        if let Some(bank) = self.bank {
+            write!(f, "{:02X}:", bank)?;
+        }
+        write!(
+            f,
             "{:04X} {:02X} {}  {} {:<9} |{:02X} {:02X} {:02X} {:02X}|{}{}{}{}{}{}|{} ",
             self.pc,
             self.instruction.opcode,
@@ -95,36 +122,107 @@ impl Trace {
             i_flag,
             z_flag,
             c_flag,
-            self.instruction.cycles,
-        );
+            self.cycles,
+        )
+    }
+}
+
+/// Receives the `Trace` of every instruction run by `CPU::execute()`.
+///
+/// Closures taking a `&Trace` implement the trait too.
+pub trait TraceSink {
+    fn trace(&mut self, trace: &Trace);
+}
+
+impl<F: FnMut(&Trace)> TraceSink for F {
+    fn trace(&mut self, trace: &Trace) {
+        self(trace)
+    }
+}
+
+/// Discards the traces, for running at full speed.
+pub struct NoopSink;
+
+impl TraceSink for NoopSink {
+    fn trace(&mut self, _trace: &Trace) {}
+}
+
+/// Prints the traces to stdout like `Trace::print()`.
+pub struct StdoutSink;
+
+impl TraceSink for StdoutSink {
+    fn trace(&mut self, trace: &Trace) {
+        trace.print();
+    }
+}
+
+/// Writes the traces line by line to a `std::io::Write`. Writing stops at the
+/// first error, which is kept for the caller to check.
+pub struct WriterSink<W: io::Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> WriterSink<W> {
+    pub fn new(writer: W) -> WriterSink<W> {
+        WriterSink {
+            writer,
+            error: None,
+        }
+    }
+
+    /// The error that stopped writing, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
     }
 }
 
-fn format_operation(operation: instruction::Operation) -> String {
-    format!("{:?}", operation)
+impl<W: io::Write> TraceSink for WriterSink<W> {
+    fn trace(&mut self, trace: &Trace) {
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.writer, "{}", trace) {
+                self.error = Some(error);
+            }
+        }
+    }
 }
 
-fn format_operand(operand: u16, pc: u16, mode: instruction::AddressingMode) -> String {
-    match mode {
-        AddressingMode::ACC => "A".to_string(),
-        AddressingMode::ABS => format!("${:04X}", operand),
-        AddressingMode::ABSX => format!("${:04X},X", operand),
-        AddressingMode::ABSY => format!("${:04X},Y", operand),
-        AddressingMode::IMM => format!("#${:02X}", operand),
-        AddressingMode::IMPL => "".to_string(),
-        AddressingMode::IND => format!("(${:04X})", operand),
-        AddressingMode::XIND => format!("(${:02X},X)", operand),
-        AddressingMode::INDY => format!("(${:02X}),Y", operand),
-        AddressingMode::REL => {
-            let signed_operand = operand as i8;
-            format!(
-                "${:04X}",
-                // 2 comes from having read the opcode and operand bytes
-                pc.wrapping_add(signed_operand as u16).wrapping_add(2)
-            )
+/// Keeps the last `capacity` traces, e.g. for dumping what led to a crash.
+pub struct RingBufferSink {
+    traces: VecDeque<Trace>,
+    capacity: usize,
+}
+
+impl RingBufferSink {
+    pub fn new(capacity: usize) -> RingBufferSink {
+        RingBufferSink {
+            traces: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Traces from the oldest to the newest
+    pub fn traces(&self) -> impl Iterator<Item = &Trace> {
+        self.traces.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.traces.clear();
+    }
+}
+
+impl TraceSink for RingBufferSink {
+    fn trace(&mut self, trace: &Trace) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.traces.len() == self.capacity {
+            self.traces.pop_front();
         }
-        AddressingMode::ZPG => format!("${:02X}", operand),
-        AddressingMode::ZPGX => format!("${:02X},X", operand),
-        AddressingMode::ZPGY => format!("${:02X},Y", operand),
+        self.traces.push_back(*trace);
     }
 }
@@ -0,0 +1,209 @@
+//! On-chip I/O port of the MOS 6510, with its data direction register at
+//! $0000 and its data register at $0001.
+//!
+//! A pin is an output when its bit in the data direction register is set.
+//! Reading the data register gives the output latch for output pins and the
+//! level on the pin for inputs. Bits 6 and 7 have no pins on the 6510, so
+//! like any other pin with nothing connected they float when they are inputs:
+//! they keep the level last driven on them for a while and then read 0.
+
+use crate::savestate::SaveStateError;
+
+/// Address of the data direction register
+pub const DDR_ADDR: u16 = 0x0000;
+/// Address of the data register
+pub const DATA_ADDR: u16 = 0x0001;
+
+/// Cycles a floating input keeps the level last driven on it, about a third
+/// of a second on a C64.
+pub const DECAY_CYCLES: u64 = 350_000;
+
+/// Bits without a pin on the 6510
+const UNBONDED: u8 = 0b1100_0000;
+
+/// Length of the state returned by `IoPort::save_state()`
+const STATE_LEN: usize = 5 + 8 * 8 + 8;
+
+/// I/O port of the 6510
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IoPort {
+    ddr: u8,
+    data: u8,
+    /// Levels driven onto the input pins from outside
+    inputs: u8,
+    /// Pins with nothing connected
+    floating: u8,
+    /// Level last driven on each floating pin
+    charge: u8,
+    /// Cycle at which each floating pin loses its charge
+    decay_at: [u64; 8],
+    decay_cycles: u64,
+}
+
+impl IoPort {
+    /// Port after reset: every pin is an input, pulled high apart from the
+    /// floating bits 6 and 7.
+    pub fn new() -> IoPort {
+        IoPort {
+            ddr: 0,
+            data: 0,
+            inputs: 0xFF,
+            floating: UNBONDED,
+            charge: 0,
+            decay_at: [0; 8],
+            decay_cycles: DECAY_CYCLES,
+        }
+    }
+
+    /// Make every pin an input, as the 6510 does on reset
+    pub fn reset(&mut self, now: u64) {
+        self.write(DDR_ADDR, 0, now);
+    }
+
+    pub fn ddr(&self) -> u8 {
+        self.ddr
+    }
+
+    /// Output latch of the data register
+    pub fn data(&self) -> u8 {
+        self.data
+    }
+
+    /// Levels on the pins: the output latch on output pins and what is
+    /// driven from outside on input pins, 0 for floating inputs. This is what
+    /// the hardware around the CPU sees, e.g. the bank switching lines of a
+    /// C64.
+    pub fn pins(&self) -> u8 {
+        (self.data & self.ddr) | (self.inputs & !self.ddr & !self.floating)
+    }
+
+    /// Drive the input pins from outside. Bits of pins that are floating are
+    /// ignored.
+    pub fn set_inputs(&mut self, levels: u8) {
+        self.inputs = levels;
+    }
+
+    /// Mark the pins with nothing connected to them. Bits 6 and 7 always
+    /// float as they have no pins.
+    pub fn set_floating(&mut self, mask: u8) {
+        self.floating = mask | UNBONDED;
+    }
+
+    /// Change how many cycles a floating input keeps its level, `u64::MAX`
+    /// for never losing it
+    pub fn set_decay_cycles(&mut self, cycles: u64) {
+        self.decay_cycles = cycles;
+    }
+
+    /// Value read from `address` at cycle `now`, without side effects
+    pub fn read(&self, address: u16, now: u64) -> u8 {
+        if address == DDR_ADDR {
+            return self.ddr;
+        }
+        let charged = (0..8)
+            .filter(|bit| now < self.decay_at[*bit])
+            .fold(0, |mask, bit| mask | (1 << bit));
+        let inputs = (self.inputs & !self.floating) | (self.charge & charged & self.floating);
+        (self.data & self.ddr) | (inputs & !self.ddr)
+    }
+
+    /// Write `value` to `address` at cycle `now`. A floating pin turned from
+    /// an output into an input keeps the level it was driven to until
+    /// `now` plus the decay time.
+    pub fn write(&mut self, address: u16, value: u8, now: u64) {
+        if address == DDR_ADDR {
+            let released = self.ddr & !value & self.floating;
+            for bit in (0..8).filter(|bit| released & (1 << bit) != 0) {
+                self.decay_at[bit] = now.saturating_add(self.decay_cycles);
+            }
+            self.charge = (self.charge & !released) | (self.data & released);
+            self.ddr = value;
+        } else {
+            self.data = value;
+        }
+    }
+
+    /// State of the port for a save state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_LEN);
+        state.extend([self.ddr, self.data, self.inputs, self.floating, self.charge]);
+        for decay_at in self.decay_at {
+            state.extend(decay_at.to_le_bytes());
+        }
+        state.extend(self.decay_cycles.to_le_bytes());
+        state
+    }
+
+    /// Restore a state returned by `save_state()`
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        if state.len() != STATE_LEN {
+            return Err(SaveStateError::Invalid(format!(
+                "expected {} bytes of I/O port state, got {}",
+                STATE_LEN,
+                state.len()
+            )));
+        }
+        let word = |i: usize| u64::from_le_bytes(state[i..i + 8].try_into().expect("8 bytes"));
+        self.ddr = state[0];
+        self.data = state[1];
+        self.inputs = state[2];
+        self.floating = state[3];
+        self.charge = state[4];
+        for (bit, decay_at) in self.decay_at.iter_mut().enumerate() {
+            *decay_at = word(5 + bit * 8);
+        }
+        self.decay_cycles = word(5 + 8 * 8);
+        Ok(())
+    }
+}
+
+impl Default for IoPort {
+    fn default() -> Self {
+        IoPort::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IoPort, DATA_ADDR, DDR_ADDR, DECAY_CYCLES};
+
+    #[test]
+    fn test_directions() {
+        let mut port = IoPort::new();
+        port.set_inputs(0b0001_0000);
+        port.write(DDR_ADDR, 0b0010_1111, 0);
+        port.write(DATA_ADDR, 0b1110_0101, 0);
+        assert_eq!(port.read(DDR_ADDR, 0), 0b0010_1111);
+        // Bit 4 is an input, bits 6 and 7 float without any charge
+        assert_eq!(port.read(DATA_ADDR, 0), 0b0011_0101);
+        assert_eq!(port.pins(), 0b0011_0101);
+    }
+
+    #[test]
+    fn test_floating_bits_decay() {
+        let mut port = IoPort::new();
+        port.write(DDR_ADDR, 0b1100_0000, 0);
+        port.write(DATA_ADDR, 0b1000_0000, 0);
+        assert_eq!(port.read(DATA_ADDR, 0) & 0xC0, 0b1000_0000);
+
+        // Bit 7 keeps its level after becoming an input, until it decays
+        port.write(DDR_ADDR, 0, 100);
+        assert_eq!(port.read(DATA_ADDR, 100) & 0xC0, 0b1000_0000);
+        assert_eq!(port.read(DATA_ADDR, 99 + DECAY_CYCLES) & 0xC0, 0b1000_0000);
+        assert_eq!(port.read(DATA_ADDR, 100 + DECAY_CYCLES) & 0xC0, 0);
+
+        // Writing the data register doesn't charge an input
+        port.write(DATA_ADDR, 0b1100_0000, 200);
+        assert_eq!(port.read(DATA_ADDR, 200) & 0xC0, 0b1000_0000);
+
+        // Never decaying
+        port.set_decay_cycles(u64::MAX);
+        port.write(DDR_ADDR, 0b1100_0000, 300);
+        port.write(DDR_ADDR, 0, 300);
+        assert_eq!(port.read(DATA_ADDR, u64::MAX - 1) & 0xC0, 0b1100_0000);
+
+        let mut restored = IoPort::new();
+        restored.load_state(&port.save_state()).unwrap();
+        assert_eq!(restored, port);
+    }
+}
@@ -4,6 +4,7 @@
 //! ```rust
 //! use phakebit::memory::PlainMemory;
 //! use phakebit::cpu::CPU;
+//! use phakebit::instrumentation::NoopSink;
 //! use phakebit::state::CPUState;
 //! use phakebit::state;
 //!
@@ -14,50 +15,145 @@
 //! cpu_state.reset();
 //!
 //! let mut cpu = CPU::new(cpu_state);
-//! cpu.execute(100000);
+//! cpu.execute(100000, &mut NoopSink);
 //! ```
 //!
+//! `CPUState::reset()` runs the 7 cycle reset sequence: A, X and Y keep their
+//! values, SP drops by three without writing to the stack and I is set.
+//! `CPUState::with_power_on()` starts from zeroed registers and RAM, a fixed
+//! byte pattern or seeded random contents, to catch code that depends on
+//! uninitialised state.
+//!
+//! `CPU::run_until()` runs until PC reaches an address, a cycle or instruction
+//! budget is used up, an instruction jumps to itself, a predicate over the
+//! `CPUState` holds or an instruction fails, and returns the `StopReason`. It
+//! also stops on the breakpoints, conditional breakpoints and memory
+//! watchpoints added to the CPU with `CPU::add_breakpoint()`,
+//! `CPU::add_condition()` and `CPU::add_watchpoint()`.
+//!
 //! ## Memory maps
 //! The `Memory` trait is used to implement memory. The PlainMemory struct is a
-//! simple implementation of this trait without any mappings. If you want to map
-//! parts of the memory to IO or ROM, you can implement the `Memory` trait for
-//! your own struct.
+//! simple implementation of this trait without any mappings. `MemoryMap`
+//! composes RAM, write-protected ROM, mirrors, open bus and devices
+//! implementing `MappedDevice` with a builder that rejects overlapping
+//! regions, and prints as a table of its regions. Banked RAM and ROM windows
+//! page a larger space through bank registers the program writes to. The
+//! bank mapped at PC is recorded in traces and can be matched by
+//! `CPU::add_bank_breakpoint()`. For anything else you can implement the
+//! `Memory` trait for your own struct.
+//!
+//! Reads take `&mut self`, so devices with read side effects can be owned by
+//! the memory. Every access carries an `AccessKind` telling opcode fetches,
+//! operands, data, stack, vectors and dummy cycles apart. `Memory::peek()`
+//! reads without side effects and is used by tracing, the disassembler and
+//! debuggers.
 //!
 //! ### Example
 //!
 //! ```rust
-//! use phakebit::memory::Memory;
+//! use phakebit::memory::{AccessKind, Memory};
 //!
 //! struct MemoryMappedIO {
 //!    state: [u8; 0x10000],
 //! }
 //!
 //! fn read_io(address: u16) -> u8 { 0 }
+//! fn peek_io(address: u16) -> u8 { 0 }
 //! fn write_io(address: u16, value: u8) {}
 //!
 //! impl Memory for MemoryMappedIO {
-//!    fn get(&self, address: u16) -> u8 {
+//!    fn read(&mut self, address: u16, _kind: AccessKind) -> u8 {
 //!       match address {
-//!         0x0000..=0x1FFF => self.state[address as usize],
 //!         0x2000..=0x3FFF => read_io(address),
-//!         0x4000..=0xFFFF => self.state[address as usize],
+//!         _ => self.state[address as usize],
 //!       }
 //!   }
 //!
-//!  fn set(&mut self, address: u16, value: u8) {
+//!  fn write(&mut self, address: u16, value: u8, _kind: AccessKind) {
 //!      match address {
 //!        0x0000..=0x1FFF => self.state[address as usize] = value,
 //!        0x2000..=0x3FFF => write_io(address, value),
 //!        0x4000..=0xFFFF => self.state[address as usize] = value,
 //!      }
 //!   }
+//!
+//!  fn peek(&self, address: u16) -> u8 {
+//!      match address {
+//!        0x2000..=0x3FFF => peek_io(address),
+//!        _ => self.state[address as usize],
+//!      }
+//!   }
 //! }
 //! ```
 //!
+//! ## Interrupts
+//! Devices signal interrupts through the `CPU::set_irq()` and `CPU::set_nmi()`
+//! input lines. IRQ is level-triggered and masked by the I flag, NMI is
+//! edge-triggered. Both are polled between instructions.
+//!
+//! `CPU::set_rdy()` pulls the RDY line low to halt the CPU on its next read
+//! cycle (the 65C02 also halts on writes), and `CPU::set_so()` sets the V flag
+//! on a falling edge of the SO pin. `CPU::stall()` steals cycles for DMA.
+//!
+//! ## Devices and scheduling
+//! Peripherals with their own timing implement the `Device` trait: `tick()`
+//! advances them by a number of cycles and `next_event()` tells when they
+//! will next change their IRQ or NMI output. A `Scheduler` owns the CPU, ticks
+//! the attached devices in step with it and drives the interrupt lines from
+//! their outputs, running the CPU one cycle at a time around device events.
+//!
+//! The crate ships reusable peripherals implementing both `Device` and
+//! `MappedDevice`: the 6522 VIA in `via`, the 6520/6821 PIA in `pia` and the
+//! 6551 ACIA in `acia`. What is connected to the PIA's ports is a `PiaPorts`
+//! implementation, called when the ports are read or written. The ACIA's
+//! serial side is a `SerialPort`, either an in-memory `BufferSerial` or a
+//! `StreamSerial` connected to stdin/stdout, a TCP socket or a
+//! pseudo-terminal.
+//!
+//! ## CPU variants
+//! `CPU::new()` emulates the original NMOS 6502. The WDC 65C02 is emulated with
+//! `CPU::with_variant(cpu_state, Variant::WDC65C02)`, which adds its new
+//! instructions and addressing modes, treats the unused opcodes as `NOP`s and
+//! uses its cycle timings and decimal mode flags. Decimal mode matches real
+//! chips of both variants for all inputs, including invalid BCD, as checked
+//! by Bruce Clark's decimal mode test.
+//!
+//! `Variant::Ricoh2A03` is the NES CPU, whose D flag can be set but does not
+//! affect `ADC` and `SBC`. `Variant::MOS6510` is the C64 CPU with its I/O
+//! port at $0000 and $0001, including the data direction register and the
+//! floating bits that lose their level some time after they stop being
+//! driven. The port is reached through `CPUState::io_port()`, and
+//! `Memory::set_io_port()` lets a memory switch banks on its pins.
+//!
+//! ## Save states
+//! `CPU::save_state()` snapshots the registers, interrupt lines and memory
+//! into a `SaveState`, and `CPU::load_state()` restores it. The memory and
+//! any devices behind it contribute their state through
+//! `Memory::save_state()` and `Memory::load_state()`. `SaveState::to_bytes()`
+//! and `SaveState::from_bytes()` use a versioned binary format, and the
+//! optional `serde` feature makes the `SaveState` serializable with serde.
+//!
+//! ## Rewind
+//! `CPU::enable_rewind()` keeps a checkpoint every N instructions plus the
+//! memory writes of each instruction in between. `CPU::step_back()` undoes
+//! one instruction, `CPU::run_back_until_break()` steps backwards to the
+//! previous breakpoint and `CPU::rewind_to_cycle()` jumps to an earlier cycle
+//! count. Execution can continue normally from the rewound state.
+//!
+//! ## Cycle-stepped execution
+//! `CPU::step()` executes a whole instruction at a time. `CPU::tick()` instead
+//! advances the CPU by a single clock cycle and does the one memory access the
+//! 6502 does on that cycle, including the dummy reads of indexed addressing
+//! and the double write of read-modify-write instructions. This lets devices
+//! behind the `Memory` trait observe cycle-exact access timing.
+//!
 //! ## Instrumentation
 //! The `Trace` struct is used to instrument the CPU. It contains the state of
 //! the CPU _after_ executing the instruction. The `CPU::step()` method returns
-//! a `Trace`.
+//! a `Trace`, and `CPU::execute()` passes each one to a `TraceSink`:
+//! `NoopSink` discards them, `StdoutSink` prints them, `WriterSink` writes
+//! them to any `std::io::Write`, `RingBufferSink` keeps the latest ones and a
+//! closure taking a `&Trace` can do anything else.
 //!
 //! ```text
 //! PC   Op Oper   Disassembly   |A  X  Y  SP|NVDIZC|C
@@ -70,9 +166,42 @@
 //! 340C A9 2B     LDA #$2B      |2B 0E FF FF|011001|2
 //! 340E 8D 00 02  STA $0200     |2B 0E FF FF|011001|4
 //! ```
+//!
+//! ## Disassembly
+//! The `disasm` module decodes instructions straight from any `Memory`, for
+//! showing code that has not been executed yet.
+//!
+//! ## Assembler
+//! `asm::assemble()` turns 6502 source with labels, expressions and the
+//! `.org`, `.byte` and `.word` directives into bytes and a symbol table. The
+//! `phakeasm` binary in the emulators crate assembles files into raw binaries
+//! or ones prefixed with the load address.
+//!
+//! ```rust
+//! use phakebit::asm;
+//! use phakebit::cpu::Variant;
+//!
+//! let program = asm::assemble("  .org $0600\nloop: JMP loop", Variant::MOS6502).unwrap();
+//! assert_eq!(program.bytes, [0x4C, 0x00, 0x06]);
+//! ```
 
+pub mod acia;
+pub mod asm;
 pub mod cpu;
+pub mod debug;
+pub mod device;
+pub mod disasm;
+pub mod error;
 pub mod instruction;
 pub mod instrumentation;
+pub mod io_port;
 pub mod memory;
+pub mod pia;
+pub mod rewind;
+pub mod run;
+pub mod savestate;
 pub mod state;
+pub mod via;
+
+#[cfg(test)]
+mod test_util;
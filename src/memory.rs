@@ -1,9 +1,77 @@
 //! Models the memory
 
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+use crate::savestate::SaveStateError;
+
+/// Why the CPU accesses memory on a bus cycle
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    /// Opcode fetch
+    Opcode,
+    /// Operand bytes following the opcode
+    Operand,
+    /// Data read or written by the instruction, including indirect pointers
+    Data,
+    /// Stack push or pull
+    Stack,
+    /// Interrupt or reset vector
+    Vector,
+    /// Bus cycle whose value the CPU ignores, e.g. while adding an index or
+    /// the first write of a read-modify-write instruction
+    Dummy,
+}
+
 /// Abstract memory interface
 pub trait Memory {
-    fn get(&self, address: u16) -> u8;
-    fn set(&mut self, address: u16, value: u8);
+    /// Read a byte on a CPU bus cycle. Reads may have side effects, like
+    /// clearing a device's status register.
+    fn read(&mut self, address: u16, kind: AccessKind) -> u8;
+    /// Write a byte on a CPU bus cycle.
+    fn write(&mut self, address: u16, value: u8, kind: AccessKind);
+    /// Read a byte without side effects, for debuggers, tracers and the
+    /// disassembler.
+    fn peek(&self, address: u16) -> u8;
+
+    /// Store a byte outside of CPU execution, e.g. to load a program or to
+    /// undo a write when rewinding. The default is a data write, memories
+    /// with write side effects should override it.
+    fn poke(&mut self, address: u16, value: u8) {
+        self.write(address, value, AccessKind::Data);
+    }
+
+    /// Bank currently mapped at `address`, for memories that page a larger
+    /// space through banked windows. Shown in traces and used by banked
+    /// breakpoints.
+    fn bank(&self, _address: u16) -> Option<u16> {
+        None
+    }
+
+    /// Set RAM to its power-on contents, taking a byte from `fill` for every
+    /// byte of RAM in address order. ROM and devices are left alone. The
+    /// default does nothing.
+    fn fill_ram(&mut self, _fill: &mut dyn FnMut() -> u8) {}
+
+    /// Called with the levels on the pins of the 6510's I/O port whenever
+    /// they may have changed, see `IoPort::pins()`. Memories that switch
+    /// banks on them, like the C64's, override this. The default does
+    /// nothing.
+    fn set_io_port(&mut self, _pins: u8) {}
+
+    /// State of the memory and any devices behind it for a save state. The
+    /// default saves nothing, so implementations holding RAM or device
+    /// registers should override this and `load_state()`.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the state returned by `save_state()`.
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), SaveStateError> {
+        Ok(())
+    }
 }
 
 /// Plain memory implementation with just 64K of RAM
@@ -11,6 +79,12 @@ pub struct PlainMemory {
     state: [u8; 0x10000],
 }
 
+impl Default for PlainMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PlainMemory {
     pub fn new() -> PlainMemory {
         PlainMemory {
@@ -20,15 +94,687 @@ impl PlainMemory {
 }
 
 impl Memory for PlainMemory {
-    fn get(&self, address: u16) -> u8 {
+    fn read(&mut self, address: u16, _kind: AccessKind) -> u8 {
+        self.peek(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8, _kind: AccessKind) {
+        self.poke(address, value);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
         let idx = address as usize;
         self.state[idx]
     }
 
-    fn set(&mut self, address: u16, value: u8) {
+    fn poke(&mut self, address: u16, value: u8) {
         let idx = address as usize;
         self.state[idx] = value;
     }
+
+    fn fill_ram(&mut self, fill: &mut dyn FnMut() -> u8) {
+        self.state.iter_mut().for_each(|byte| *byte = fill());
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.state.to_vec()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        if state.len() != self.state.len() {
+            return Err(SaveStateError::Invalid(format!(
+                "expected {} bytes of memory, got {}",
+                self.state.len(),
+                state.len()
+            )));
+        }
+        self.state.copy_from_slice(state);
+        Ok(())
+    }
+}
+
+/// A memory-mapped device in a `MemoryMap`. Offsets are relative to the
+/// start of the device's region.
+pub trait MappedDevice {
+    fn read(&mut self, offset: u16) -> u8;
+    fn write(&mut self, offset: u16, value: u8);
+    /// Read a register without side effects, see `Memory::peek()`
+    fn peek(&self, offset: u16) -> u8;
+
+    /// Name shown in the region table
+    fn name(&self) -> &'static str {
+        "device"
+    }
+
+    /// State of the device for a save state, see `Memory::save_state()`
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
+
+/// Devices shared with the rest of the program are mapped through an
+/// `Rc<RefCell<..>>`.
+impl<D: MappedDevice> MappedDevice for Rc<RefCell<D>> {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.borrow_mut().read(offset)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.borrow_mut().write(offset, value)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.borrow().peek(offset)
+    }
+
+    fn name(&self) -> &'static str {
+        self.borrow().name()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.borrow().save_state()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        self.borrow_mut().load_state(state)
+    }
+}
+
+/// Error building a `MemoryMap`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MemoryMapError {
+    /// Two regions share addresses
+    Overlap {
+        first: RangeInclusive<u16>,
+        second: RangeInclusive<u16>,
+    },
+    /// A region is empty or extends past $FFFF
+    InvalidRange { start: u16, len: usize },
+    /// A mirror points to addresses that are unmapped or mirrors themselves
+    MirrorTarget {
+        mirror: RangeInclusive<u16>,
+        target: RangeInclusive<u16>,
+    },
+    /// A bank register refers to an address that doesn't start a banked
+    /// window
+    BankRegister { address: u16, window: u16 },
+    /// A bank register refers to a window with more banks than its 8 bits
+    /// can select
+    TooManyBanks { address: u16, banks: usize },
+}
+
+impl fmt::Display for MemoryMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryMapError::Overlap { first, second } => write!(
+                f,
+                "Region {:04X}-{:04X} overlaps {:04X}-{:04X}",
+                second.start(),
+                second.end(),
+                first.start(),
+                first.end()
+            ),
+            MemoryMapError::InvalidRange { start, len } => {
+                write!(f, "Invalid region of {} bytes at {:04X}", len, start)
+            }
+            MemoryMapError::MirrorTarget { mirror, target } => write!(
+                f,
+                "Mirror {:04X}-{:04X} has invalid target {:04X}-{:04X}",
+                mirror.start(),
+                mirror.end(),
+                target.start(),
+                target.end()
+            ),
+            MemoryMapError::BankRegister { address, window } => write!(
+                f,
+                "Bank register {:04X} refers to {:04X}, which is not a banked window",
+                address, window
+            ),
+            MemoryMapError::TooManyBanks { address, banks } => write!(
+                f,
+                "Bank register {:04X} can't select all of {} banks",
+                address, banks
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MemoryMapError {}
+
+enum Backing {
+    Ram(Vec<u8>),
+    Rom(Vec<u8>),
+    Mirror(RangeInclusive<u16>),
+    OpenBus,
+    Device(Box<dyn MappedDevice>),
+    Banked(Bank),
+    /// Selects the bank of the window starting at the address
+    BankRegister(u16),
+}
+
+/// Storage of a banked window
+struct Bank {
+    data: Vec<u8>,
+    size: usize,
+    current: usize,
+    writable: bool,
+}
+
+impl Bank {
+    fn count(&self) -> usize {
+        self.data.len() / self.size
+    }
+
+    fn index(&self, offset: usize) -> usize {
+        self.current * self.size + offset
+    }
+}
+
+/// Current mapping of a banked window
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BankWindow {
+    pub range: RangeInclusive<u16>,
+    pub bank: u16,
+    pub banks: u16,
+}
+
+struct Region {
+    start: u16,
+    end: u16,
+    backing: Backing,
+}
+
+impl Region {
+    fn range(&self) -> RangeInclusive<u16> {
+        self.start..=self.end
+    }
+}
+
+/// Builder for a `MemoryMap`
+#[derive(Default)]
+pub struct MemoryMapBuilder {
+    regions: Vec<Region>,
+    invalid: Option<MemoryMapError>,
+}
+
+impl MemoryMapBuilder {
+    pub fn new() -> MemoryMapBuilder {
+        MemoryMapBuilder::default()
+    }
+
+    /// RAM initialized to zero
+    pub fn ram(self, range: RangeInclusive<u16>) -> MemoryMapBuilder {
+        let len = range_len(&range);
+        self.region(range, Backing::Ram(vec![0; len]))
+    }
+
+    /// Write-protected memory holding `data`, starting at `start`
+    pub fn rom(mut self, start: u16, data: &[u8]) -> MemoryMapBuilder {
+        if data.is_empty() || start as usize + data.len() > 0x10000 {
+            self.invalid.get_or_insert(MemoryMapError::InvalidRange {
+                start,
+                len: data.len(),
+            });
+            return self;
+        }
+        let end = (start as usize + data.len() - 1) as u16;
+        self.region(start..=end, Backing::Rom(data.to_vec()))
+    }
+
+    /// Repeat `target` over `range`. The target must be mapped to regions
+    /// other than mirrors.
+    pub fn mirror(
+        self,
+        range: RangeInclusive<u16>,
+        target: RangeInclusive<u16>,
+    ) -> MemoryMapBuilder {
+        self.region(range, Backing::Mirror(target))
+    }
+
+    /// Unconnected addresses. Reads return the last value on the data bus
+    /// and writes are ignored. Addresses not in any region behave the same.
+    pub fn open_bus(self, range: RangeInclusive<u16>) -> MemoryMapBuilder {
+        self.region(range, Backing::OpenBus)
+    }
+
+    /// Window of `banks` banks of RAM, starting with bank 0. Windows of ROM
+    /// and RAM can have up to 65535 banks.
+    pub fn banked_ram(self, range: RangeInclusive<u16>, banks: u16) -> MemoryMapBuilder {
+        let size = range_len(&range);
+        self.banked(range, vec![0; size * banks as usize], true)
+    }
+
+    /// Window into ROM `data`, which is split into banks of the window's
+    /// size
+    pub fn banked_rom(self, range: RangeInclusive<u16>, data: &[u8]) -> MemoryMapBuilder {
+        self.banked(range, data.to_vec(), false)
+    }
+
+    fn banked(
+        mut self,
+        range: RangeInclusive<u16>,
+        data: Vec<u8>,
+        writable: bool,
+    ) -> MemoryMapBuilder {
+        let size = range_len(&range);
+        if size == 0
+            || data.is_empty()
+            || !data.len().is_multiple_of(size)
+            || data.len() / size > u16::MAX as usize
+        {
+            self.invalid.get_or_insert(MemoryMapError::InvalidRange {
+                start: *range.start(),
+                len: data.len(),
+            });
+            return self;
+        }
+        self.region(
+            range,
+            Backing::Banked(Bank {
+                data,
+                size,
+                current: 0,
+                writable,
+            }),
+        )
+    }
+
+    /// Latch at `address` selecting the bank of the banked window starting
+    /// at `window`. The register is 8 bits wide, so the window can have at
+    /// most 256 banks. Writes select the bank modulo the number of banks,
+    /// reads return the current bank.
+    pub fn bank_register(self, address: u16, window: u16) -> MemoryMapBuilder {
+        self.region(address..=address, Backing::BankRegister(window))
+    }
+
+    /// Map a device. Pass an `Rc<RefCell<..>>` of the device to keep a
+    /// handle to it while the CPU runs.
+    pub fn device<D: MappedDevice + 'static>(
+        self,
+        range: RangeInclusive<u16>,
+        device: D,
+    ) -> MemoryMapBuilder {
+        self.region(range, Backing::Device(Box::new(device)))
+    }
+
+    fn region(mut self, range: RangeInclusive<u16>, backing: Backing) -> MemoryMapBuilder {
+        if range.is_empty() {
+            self.invalid.get_or_insert(MemoryMapError::InvalidRange {
+                start: *range.start(),
+                len: 0,
+            });
+            return self;
+        }
+        self.regions.push(Region {
+            start: *range.start(),
+            end: *range.end(),
+            backing,
+        });
+        self
+    }
+
+    /// Check the regions and build the map
+    pub fn build(self) -> Result<MemoryMap, MemoryMapError> {
+        if let Some(error) = self.invalid {
+            return Err(error);
+        }
+        let mut regions = self.regions;
+        regions.sort_by_key(|region| region.start);
+        for pair in regions.windows(2) {
+            if pair[1].start <= pair[0].end {
+                return Err(MemoryMapError::Overlap {
+                    first: pair[0].range(),
+                    second: pair[1].range(),
+                });
+            }
+        }
+
+        let map = MemoryMap { regions, bus: 0 };
+        for region in &map.regions {
+            if let Backing::BankRegister(window) = region.backing {
+                match map.window(window).map(|index| &map.regions[index].backing) {
+                    Some(Backing::Banked(bank)) if bank.count() > 0x100 => {
+                        return Err(MemoryMapError::TooManyBanks {
+                            address: region.start,
+                            banks: bank.count(),
+                        });
+                    }
+                    Some(_) => (),
+                    None => {
+                        return Err(MemoryMapError::BankRegister {
+                            address: region.start,
+                            window,
+                        });
+                    }
+                }
+            }
+            if let Backing::Mirror(target) = &region.backing {
+                let valid = !target.is_empty()
+                    && target.clone().all(|address| {
+                        map.find(address).is_some_and(|index| {
+                            !matches!(map.regions[index].backing, Backing::Mirror(_))
+                        })
+                    });
+                if !valid {
+                    return Err(MemoryMapError::MirrorTarget {
+                        mirror: region.range(),
+                        target: target.clone(),
+                    });
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// Memory composed of RAM, ROM, mirrors, open bus and device regions.
+///
+/// ```rust
+/// use phakebit::memory::{AccessKind, Memory, MemoryMap};
+///
+/// let mut memory = MemoryMap::builder()
+///     .ram(0x0000..=0x07FF)
+///     .mirror(0x0800..=0x1FFF, 0x0000..=0x07FF)
+///     .rom(0xF000, &[0xEA; 0x1000])
+///     .build()
+///     .unwrap();
+/// memory.write(0x0801, 0x42, AccessKind::Data);
+/// assert_eq!(memory.peek(0x0001), 0x42);
+/// println!("{}", memory);
+/// ```
+pub struct MemoryMap {
+    /// Sorted by start address, not overlapping
+    regions: Vec<Region>,
+    /// Last value on the data bus, returned by open bus reads
+    bus: u8,
+}
+
+impl MemoryMap {
+    pub fn builder() -> MemoryMapBuilder {
+        MemoryMapBuilder::new()
+    }
+
+    /// Copy `data` to memory starting at `address` with `Memory::poke()`
+    pub fn load(&mut self, address: u16, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.poke(address.wrapping_add(i as u16), *byte);
+        }
+    }
+
+    /// Select the bank of the banked window starting at `window`. Returns
+    /// false if there is no such window or bank.
+    pub fn set_bank(&mut self, window: u16, bank: u16) -> bool {
+        let Some(index) = self.window(window) else {
+            return false;
+        };
+        match &mut self.regions[index].backing {
+            Backing::Banked(window) if (bank as usize) < window.count() => {
+                window.current = bank as usize;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Current mapping of every banked window
+    pub fn bank_windows(&self) -> Vec<BankWindow> {
+        self.regions
+            .iter()
+            .filter_map(|region| match &region.backing {
+                Backing::Banked(bank) => Some(BankWindow {
+                    range: region.range(),
+                    bank: bank.current as u16,
+                    banks: bank.count() as u16,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Index of the banked window starting at `start`
+    fn window(&self, start: u16) -> Option<usize> {
+        self.find(start).filter(|index| {
+            let region = &self.regions[*index];
+            region.start == start && matches!(region.backing, Backing::Banked(_))
+        })
+    }
+
+    fn find(&self, address: u16) -> Option<usize> {
+        let index = self
+            .regions
+            .partition_point(|region| region.start <= address)
+            .checked_sub(1)?;
+        (self.regions[index].end >= address).then_some(index)
+    }
+
+    /// Region and offset backing `address`, following mirrors
+    fn resolve(&self, address: u16) -> Option<(usize, usize)> {
+        let index = self.find(address)?;
+        let region = &self.regions[index];
+        match &region.backing {
+            Backing::Mirror(target) => {
+                let offset = (address - region.start) as usize % range_len(target);
+                let address = target.start() + offset as u16;
+                let index = self.find(address)?;
+                Some((index, (address - self.regions[index].start) as usize))
+            }
+            _ => Some((index, (address - region.start) as usize)),
+        }
+    }
+}
+
+impl Memory for MemoryMap {
+    fn read(&mut self, address: u16, _kind: AccessKind) -> u8 {
+        let value = match self.resolve(address) {
+            Some((index, offset)) => match &mut self.regions[index].backing {
+                Backing::Device(device) => device.read(offset as u16),
+                _ => self.peek(address),
+            },
+            None => self.bus,
+        };
+        self.bus = value;
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8, _kind: AccessKind) {
+        self.bus = value;
+        if let Some((index, offset)) = self.resolve(address) {
+            match &mut self.regions[index].backing {
+                Backing::Ram(_) | Backing::BankRegister(_) => self.poke(address, value),
+                Backing::Banked(bank) if bank.writable => self.poke(address, value),
+                Backing::Device(device) => device.write(offset as u16, value),
+                Backing::Rom(_) | Backing::Banked(_) | Backing::Mirror(_) | Backing::OpenBus => (),
+            }
+        }
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        let Some((index, offset)) = self.resolve(address) else {
+            return self.bus;
+        };
+        match &self.regions[index].backing {
+            Backing::Ram(bytes) | Backing::Rom(bytes) => bytes[offset],
+            Backing::Device(device) => device.peek(offset as u16),
+            Backing::Banked(bank) => bank.data[bank.index(offset)],
+            Backing::BankRegister(window) => self
+                .window(*window)
+                .and_then(|index| match &self.regions[index].backing {
+                    Backing::Banked(bank) => Some(bank.current as u8),
+                    _ => None,
+                })
+                .unwrap_or(self.bus),
+            Backing::Mirror(_) | Backing::OpenBus => self.bus,
+        }
+    }
+
+    /// Stores to RAM and ROM ignoring write protection and selects banks
+    /// through bank registers. Devices are left alone.
+    fn poke(&mut self, address: u16, value: u8) {
+        let Some((index, offset)) = self.resolve(address) else {
+            return;
+        };
+        match &mut self.regions[index].backing {
+            Backing::Ram(bytes) | Backing::Rom(bytes) => bytes[offset] = value,
+            Backing::Banked(bank) => {
+                let index = bank.index(offset);
+                bank.data[index] = value;
+            }
+            Backing::BankRegister(window) => {
+                let window = *window;
+                if let Some(index) = self.window(window) {
+                    if let Backing::Banked(bank) = &mut self.regions[index].backing {
+                        bank.current = value as usize % bank.count();
+                    }
+                }
+            }
+            Backing::Device(_) | Backing::Mirror(_) | Backing::OpenBus => (),
+        }
+    }
+
+    fn bank(&self, address: u16) -> Option<u16> {
+        let (index, _) = self.resolve(address)?;
+        match &self.regions[index].backing {
+            Backing::Banked(bank) => Some(bank.current as u16),
+            _ => None,
+        }
+    }
+
+    /// Fills RAM and every bank of banked RAM
+    fn fill_ram(&mut self, fill: &mut dyn FnMut() -> u8) {
+        for region in &mut self.regions {
+            match &mut region.backing {
+                Backing::Ram(bytes) => bytes.iter_mut().for_each(|byte| *byte = fill()),
+                Backing::Banked(bank) if bank.writable => {
+                    bank.data.iter_mut().for_each(|byte| *byte = fill())
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Open bus value, RAM contents, banks and the length-prefixed state of
+    /// each device
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.bus];
+        for region in &self.regions {
+            match &region.backing {
+                Backing::Ram(bytes) => state.extend_from_slice(bytes),
+                Backing::Banked(bank) => {
+                    state.extend_from_slice(&(bank.current as u16).to_le_bytes());
+                    if bank.writable {
+                        state.extend_from_slice(&bank.data);
+                    }
+                }
+                Backing::Device(device) => {
+                    let device_state = device.save_state();
+                    state.extend_from_slice(&(device_state.len() as u32).to_le_bytes());
+                    state.extend_from_slice(&device_state);
+                }
+                _ => (),
+            }
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let (bus, mut rest) = state.split_first().ok_or(SaveStateError::Truncated)?;
+        self.bus = *bus;
+        for region in &mut self.regions {
+            match &mut region.backing {
+                Backing::Ram(bytes) => {
+                    if rest.len() < bytes.len() {
+                        return Err(SaveStateError::Truncated);
+                    }
+                    let (ram, tail) = rest.split_at(bytes.len());
+                    bytes.copy_from_slice(ram);
+                    rest = tail;
+                }
+                Backing::Banked(bank) => {
+                    let len = 2 + if bank.writable { bank.data.len() } else { 0 };
+                    if rest.len() < len {
+                        return Err(SaveStateError::Truncated);
+                    }
+                    let (bank_state, tail) = rest.split_at(len);
+                    let current = u16::from_le_bytes([bank_state[0], bank_state[1]]) as usize;
+                    if current >= bank.count() {
+                        return Err(SaveStateError::Invalid(format!(
+                            "bank {} out of range",
+                            current
+                        )));
+                    }
+                    bank.current = current;
+                    if bank.writable {
+                        bank.data.copy_from_slice(&bank_state[2..]);
+                    }
+                    rest = tail;
+                }
+                Backing::Device(device) => {
+                    if rest.len() < 4 {
+                        return Err(SaveStateError::Truncated);
+                    }
+                    let (len, tail) = rest.split_at(4);
+                    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+                    if tail.len() < len {
+                        return Err(SaveStateError::Truncated);
+                    }
+                    let (device_state, tail) = tail.split_at(len);
+                    device.load_state(device_state)?;
+                    rest = tail;
+                }
+                _ => (),
+            }
+        }
+        if !rest.is_empty() {
+            return Err(SaveStateError::Invalid(format!(
+                "{} bytes of memory state left over",
+                rest.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Table of the regions, one per line. Gaps are shown as unmapped.
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut next = 0u32;
+        for region in &self.regions {
+            if (region.start as u32) > next {
+                writeln!(f, "{:04X}-{:04X}  unmapped", next, region.start - 1)?;
+            }
+            write!(f, "{:04X}-{:04X}  ", region.start, region.end)?;
+            match &region.backing {
+                Backing::Ram(_) => writeln!(f, "RAM")?,
+                Backing::Rom(_) => writeln!(f, "ROM")?,
+                Backing::Mirror(target) => {
+                    writeln!(f, "mirror of {:04X}-{:04X}", target.start(), target.end())?
+                }
+                Backing::OpenBus => writeln!(f, "open bus")?,
+                Backing::Device(device) => writeln!(f, "{}", device.name())?,
+                Backing::Banked(bank) => writeln!(
+                    f,
+                    "banked {}, bank {} of {}",
+                    if bank.writable { "RAM" } else { "ROM" },
+                    bank.current,
+                    bank.count()
+                )?,
+                Backing::BankRegister(window) => writeln!(f, "bank register for {:04X}", window)?,
+            }
+            next = region.end as u32 + 1;
+        }
+        if next <= 0xFFFF {
+            writeln!(f, "{:04X}-FFFF  unmapped", next)?;
+        }
+        Ok(())
+    }
+}
+
+fn range_len(range: &RangeInclusive<u16>) -> usize {
+    (*range.end() as usize + 1).saturating_sub(*range.start() as usize)
 }
 
 #[cfg(test)]
@@ -38,17 +784,23 @@ mod tests {
 
     use crate::cpu::CPU;
 
+    use super::AccessKind;
+    use super::BankWindow;
+    use super::MappedDevice;
     use super::Memory;
+    use super::MemoryMap;
+    use super::MemoryMapError;
     use super::PlainMemory;
 
     #[test]
     fn gets() {
         let mut m = PlainMemory::new();
-        let zero = m.get(0x0000);
+        let zero = m.read(0x0000, AccessKind::Data);
         assert_eq!(zero, 0);
 
-        m.set(0x1FF, 0x01);
-        assert_eq!(m.get(0x1FF), 0x01);
+        m.write(0x1FF, 0x01, AccessKind::Data);
+        assert_eq!(m.read(0x1FF, AccessKind::Data), 0x01);
+        assert_eq!(m.peek(0x1FF), 0x01);
     }
 
     #[test]
@@ -76,49 +828,240 @@ mod tests {
 
         struct MappedMemory {
             state: [u8; 0x10000],
-            chip: Rc<RefCell<Chip>>,
+            chip: Chip,
         }
 
         impl MappedMemory {
-            fn new(chip: Rc<RefCell<Chip>>) -> MappedMemory {
+            fn new(chip: Chip) -> MappedMemory {
                 MappedMemory {
                     state: [0; 0x10000],
-                    chip: chip,
+                    chip,
                 }
             }
         }
 
         impl Memory for MappedMemory {
-            fn get(&self, address: u16) -> u8 {
+            fn read(&mut self, address: u16, _kind: AccessKind) -> u8 {
                 match address {
-                    0x0000..=0x1FFF => self.state[address as usize],
-                    0x2000..=0x3FFF => self.chip.borrow_mut().read(),
-                    0x4000..=0xFFFF => self.state[address as usize],
+                    0x2000..=0x3FFF => self.chip.read(),
+                    _ => self.peek(address),
                 }
             }
 
-            fn set(&mut self, address: u16, value: u8) {
+            fn write(&mut self, address: u16, value: u8, _kind: AccessKind) {
                 match address {
                     0x0000..=0x1FFF => self.state[address as usize] = value,
-                    0x2000..=0x3FFF => (*self.chip).borrow_mut().write(value),
+                    0x2000..=0x3FFF => self.chip.write(value),
                     0x4000..=0xFFFF => self.state[address as usize] = value,
                 }
             }
+
+            fn peek(&self, address: u16) -> u8 {
+                match address {
+                    0x0000..=0x1FFF => self.state[address as usize],
+                    0x2000..=0x3FFF => self.chip.register,
+                    0x4000..=0xFFFF => self.state[address as usize],
+                }
+            }
         }
 
-        let chip = Rc::new(RefCell::new(Chip::new()));
-        let mut m = MappedMemory::new(chip.clone());
-        m.set(0x2000, 0x01);
-        assert_eq!(chip.borrow_mut().read(), 0x01);
-        assert_eq!(m.get(0x2000), 0x01);
+        let mut m = MappedMemory::new(Chip::new());
+        m.write(0x2000, 0x01, AccessKind::Data);
+        assert_eq!(m.read(0x2000, AccessKind::Data), 0x01);
+        assert_eq!(m.chip.read_count, 1);
 
         let cpu_state = crate::state::CPUState::new(m);
         let mut cpu = CPU::new(cpu_state);
 
         let state = cpu.get_mut_state();
         assert_eq!(state.read_byte(0x2000), 0x01);
+        assert_eq!(state.peek_byte(0x2000), 0x01);
+        assert_eq!(state.get_memory().chip.read_count, 2);
+    }
+
+    struct Latch {
+        value: u8,
+        last_offset: u16,
+    }
+
+    impl MappedDevice for Latch {
+        fn read(&mut self, offset: u16) -> u8 {
+            self.last_offset = offset;
+            self.value
+        }
+        fn write(&mut self, offset: u16, value: u8) {
+            self.last_offset = offset;
+            self.value = value;
+        }
+        fn peek(&self, _offset: u16) -> u8 {
+            self.value
+        }
+        fn name(&self) -> &'static str {
+            "latch"
+        }
+    }
+
+    #[test]
+    fn memory_map_builder() {
+        let latch = Rc::new(RefCell::new(Latch {
+            value: 0,
+            last_offset: 0,
+        }));
+        let mut m = MemoryMap::builder()
+            .ram(0x0000..=0x07FF)
+            .mirror(0x0800..=0x1FFF, 0x0000..=0x07FF)
+            .device(0xD010..=0xD013, latch.clone())
+            .open_bus(0xD014..=0xDFFF)
+            .rom(0xE000, &[0xEA; 0x2000])
+            .build()
+            .unwrap();
+
+        m.write(0x1801, 0x42, AccessKind::Data);
+        assert_eq!(m.read(0x0001, AccessKind::Data), 0x42);
+        assert_eq!(m.read(0x0801, AccessKind::Data), 0x42);
+
+        m.write(0xD012, 0x17, AccessKind::Data);
+        assert_eq!(latch.borrow().value, 0x17);
+        assert_eq!(latch.borrow().last_offset, 2);
+
+        m.write(0xE000, 0x00, AccessKind::Data);
+        assert_eq!(m.read(0xE000, AccessKind::Data), 0xEA);
+        m.load(0xE000, &[0x00]);
+        assert_eq!(m.read(0xE000, AccessKind::Data), 0x00);
+
+        // Open bus and unmapped reads return the last value on the bus
+        assert_eq!(m.read(0x0001, AccessKind::Data), 0x42);
+        assert_eq!(m.read(0xD100, AccessKind::Data), 0x42);
+        assert_eq!(m.read(0x4000, AccessKind::Data), 0x42);
+
+        assert_eq!(
+            m.to_string(),
+            "0000-07FF  RAM\n\
+             0800-1FFF  mirror of 0000-07FF\n\
+             2000-D00F  unmapped\n\
+             D010-D013  latch\n\
+             D014-DFFF  open bus\n\
+             E000-FFFF  ROM\n"
+        );
+
+        let state = m.save_state();
+        m.write(0x0001, 0x00, AccessKind::Data);
+        m.load_state(&state).unwrap();
+        assert_eq!(m.read(0x0001, AccessKind::Data), 0x42);
+    }
+
+    #[test]
+    fn memory_map_errors() {
+        let overlap = MemoryMap::builder()
+            .ram(0x0000..=0x0FFF)
+            .ram(0x0800..=0x1FFF)
+            .build();
+        assert_eq!(
+            overlap.err(),
+            Some(MemoryMapError::Overlap {
+                first: 0x0000..=0x0FFF,
+                second: 0x0800..=0x1FFF
+            })
+        );
+
+        let mirror = MemoryMap::builder()
+            .ram(0x0000..=0x07FF)
+            .mirror(0x0800..=0x0FFF, 0x0400..=0x0BFF)
+            .build();
+        assert!(matches!(
+            mirror.err(),
+            Some(MemoryMapError::MirrorTarget { .. })
+        ));
+
+        let rom = MemoryMap::builder().rom(0xF000, &[0; 0x2000]).build();
+        assert_eq!(
+            rom.err(),
+            Some(MemoryMapError::InvalidRange {
+                start: 0xF000,
+                len: 0x2000
+            })
+        );
+    }
+
+    #[test]
+    fn memory_map_banks() {
+        let mut m = MemoryMap::builder()
+            .ram(0x0000..=0x7FFF)
+            .banked_ram(0x8000..=0xBFFF, 32)
+            .banked_rom(0xC000..=0xDFFF, &[[1; 0x2000], [2; 0x2000]].concat())
+            .bank_register(0xFFF0, 0x8000)
+            .bank_register(0xFFF1, 0xC000)
+            .build()
+            .unwrap();
+
+        assert_eq!(m.read(0xC000, AccessKind::Data), 1);
+        m.write(0xFFF1, 3, AccessKind::Data);
+        assert_eq!(m.read(0xFFF1, AccessKind::Data), 1);
+        assert_eq!(m.read(0xC000, AccessKind::Data), 2);
+        assert_eq!(m.bank(0xC123), Some(1));
+        assert_eq!(m.bank(0x0123), None);
+
+        m.write(0x8000, 0xAA, AccessKind::Data);
+        m.write(0xFFF0, 31, AccessKind::Data);
+        assert_eq!(m.read(0x8000, AccessKind::Data), 0);
+        m.write(0x8000, 0xBB, AccessKind::Data);
+        assert!(m.set_bank(0x8000, 0));
+        assert!(!m.set_bank(0x8000, 32));
+        assert_eq!(m.read(0x8000, AccessKind::Data), 0xAA);
+
+        assert_eq!(
+            m.bank_windows(),
+            vec![
+                BankWindow {
+                    range: 0x8000..=0xBFFF,
+                    bank: 0,
+                    banks: 32
+                },
+                BankWindow {
+                    range: 0xC000..=0xDFFF,
+                    bank: 1,
+                    banks: 2
+                }
+            ]
+        );
+        assert!(m
+            .to_string()
+            .contains("8000-BFFF  banked RAM, bank 0 of 32\n"));
+
+        let state = m.save_state();
+        m.set_bank(0x8000, 31);
+        m.load_state(&state).unwrap();
+        assert_eq!(m.read(0x8000, AccessKind::Data), 0xAA);
+        m.set_bank(0x8000, 31);
+        assert_eq!(m.read(0x8000, AccessKind::Data), 0xBB);
+
+        let error = MemoryMap::builder()
+            .ram(0x0000..=0x7FFF)
+            .bank_register(0xFFF0, 0x8000)
+            .build();
+        assert_eq!(
+            error.err(),
+            Some(MemoryMapError::BankRegister {
+                address: 0xFFF0,
+                window: 0x8000
+            })
+        );
 
-        (*chip).borrow_mut().write(0x02);
-        assert_eq!(chip.borrow_mut().read(), 0x02);
+        let error = MemoryMap::builder()
+            .banked_ram(0x8000..=0x80FF, 257)
+            .bank_register(0xFFF0, 0x8000)
+            .build();
+        assert_eq!(
+            error.err(),
+            Some(MemoryMapError::TooManyBanks {
+                address: 0xFFF0,
+                banks: 257
+            })
+        );
+        // One bank per byte of a 64K ROM doesn't fit the bank numbers
+        assert!(MemoryMap::builder()
+            .banked_rom(0x8000..=0x8000, &[0; 0x10000])
+            .build()
+            .is_err());
     }
 }
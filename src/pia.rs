@@ -0,0 +1,546 @@
+//! MOS 6520 / Motorola 6821 Peripheral Interface Adapter. `Pia` implements
+//! `MappedDevice` for its four registers and `Device` for its interrupt
+//! outputs. What is connected to the ports is a `PiaPorts`, which supplies
+//! the input levels and is told about the outputs.
+
+use crate::device::Device;
+use crate::memory::MappedDevice;
+use crate::savestate::SaveStateError;
+
+/// Register offsets. The data and data direction registers of a port share
+/// an offset, selected by bit 2 of its control register.
+pub const PORT_A: u16 = 0x0;
+pub const CRA: u16 = 0x1;
+pub const PORT_B: u16 = 0x2;
+pub const CRB: u16 = 0x3;
+
+/// Control register bits
+pub const CR_C1_IRQ: u8 = 0x01;
+pub const CR_C1_RISING: u8 = 0x02;
+pub const CR_OUTPUT_REGISTER: u8 = 0x04;
+pub const CR_IRQ2: u8 = 0x40;
+pub const CR_IRQ1: u8 = 0x80;
+
+/// Length of the save state of a `Pia`
+const STATE_LEN: usize = 14;
+
+/// Levels on the control lines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ControlLines {
+    pub ca1: bool,
+    pub ca2: bool,
+    pub cb1: bool,
+    pub cb2: bool,
+}
+
+/// Peripherals connected to a `Pia`. All methods have defaults for
+/// unconnected pins, which are pulled high.
+pub trait PiaPorts {
+    /// Levels on the port A pins. Only the bits configured as inputs are
+    /// used.
+    fn read_a(&mut self) -> u8 {
+        0xFF
+    }
+
+    /// Levels on the port B pins. Only the bits configured as inputs are
+    /// used.
+    fn read_b(&mut self) -> u8 {
+        0xFF
+    }
+
+    /// The port A outputs changed. Bits configured as inputs are high.
+    fn write_a(&mut self, _value: u8) {}
+
+    /// The port B outputs changed. Bits configured as inputs are high.
+    fn write_b(&mut self, _value: u8) {}
+
+    /// CA2 changed level while configured as an output
+    fn set_ca2(&mut self, _level: bool) {}
+
+    /// CB2 changed level while configured as an output
+    fn set_cb2(&mut self, _level: bool) {}
+
+    /// Update the control line inputs. Called before every register access
+    /// and tick; CA2 and CB2 are ignored while they are outputs.
+    fn update_lines(&mut self, _lines: &mut ControlLines) {}
+}
+
+/// Nothing connected
+impl PiaPorts for () {}
+
+/// Port A or B with its control lines
+#[derive(Clone, Debug)]
+struct Port {
+    output: u8,
+    ddr: u8,
+    control: u8,
+    /// Pin levels last read from the ports
+    input: u8,
+    c1: bool,
+    /// C2 level driven from outside when it is an input
+    c2_input: bool,
+    /// C2 level when it is an output
+    c2_output: bool,
+}
+
+impl Port {
+    fn new() -> Port {
+        Port {
+            output: 0,
+            ddr: 0,
+            control: 0,
+            input: 0xFF,
+            c1: true,
+            c2_input: true,
+            c2_output: true,
+        }
+    }
+
+    /// Pin levels: outputs driven by the PIA, inputs from outside
+    fn pins(&self) -> u8 {
+        (self.output & self.ddr) | (self.input & !self.ddr)
+    }
+
+    /// Level on the output pins, with the inputs pulled high
+    fn outputs(&self) -> u8 {
+        self.output | !self.ddr
+    }
+
+    fn c2_is_output(&self) -> bool {
+        self.control & 0x20 != 0
+    }
+
+    /// C2 is a handshake output: a strobe that goes low on a port access
+    fn c2_handshake(&self) -> bool {
+        self.control & 0x30 == 0x20
+    }
+
+    fn irq(&self) -> bool {
+        (self.control & CR_IRQ1 != 0 && self.control & CR_C1_IRQ != 0)
+            || (self.control & CR_IRQ2 != 0 && self.control & 0x28 == 0x08)
+    }
+
+    fn register(&self) -> u8 {
+        if self.control & CR_OUTPUT_REGISTER != 0 {
+            self.pins()
+        } else {
+            self.ddr
+        }
+    }
+}
+
+/// MOS 6520 / Motorola 6821 PIA
+pub struct Pia<P: PiaPorts> {
+    ports: P,
+    a: Port,
+    b: Port,
+}
+
+impl<P: PiaPorts> Pia<P> {
+    pub fn new(ports: P) -> Pia<P> {
+        Pia {
+            ports,
+            a: Port::new(),
+            b: Port::new(),
+        }
+    }
+
+    /// Hardware reset. Clears all registers and makes every pin an input.
+    pub fn reset(&mut self) {
+        for port in [&mut self.a, &mut self.b] {
+            port.output = 0;
+            port.ddr = 0;
+            port.control = 0;
+            port.c2_output = true;
+        }
+        self.ports.write_a(self.a.outputs());
+        self.ports.write_b(self.b.outputs());
+    }
+
+    pub fn ports(&self) -> &P {
+        &self.ports
+    }
+
+    pub fn ports_mut(&mut self) -> &mut P {
+        &mut self.ports
+    }
+
+    /// Levels on the port A pins
+    pub fn port_a(&self) -> u8 {
+        self.a.pins()
+    }
+
+    /// Levels on the port B pins
+    pub fn port_b(&self) -> u8 {
+        self.b.pins()
+    }
+
+    /// Levels on the control lines, with CA2 and CB2 as driven by the PIA
+    /// when they are outputs
+    pub fn lines(&self) -> ControlLines {
+        let c2 = |port: &Port| {
+            if port.c2_is_output() {
+                port.c2_output
+            } else {
+                port.c2_input
+            }
+        };
+        ControlLines {
+            ca1: self.a.c1,
+            ca2: c2(&self.a),
+            cb1: self.b.c1,
+            cb2: c2(&self.b),
+        }
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        if let Some(restore) = Self::c1_edge(&mut self.a, level) {
+            self.ports.set_ca2(restore);
+        }
+    }
+
+    pub fn set_ca2(&mut self, level: bool) {
+        Self::c2_edge(&mut self.a, level);
+    }
+
+    pub fn set_cb1(&mut self, level: bool) {
+        if let Some(restore) = Self::c1_edge(&mut self.b, level) {
+            self.ports.set_cb2(restore);
+        }
+    }
+
+    pub fn set_cb2(&mut self, level: bool) {
+        Self::c2_edge(&mut self.b, level);
+    }
+
+    /// Set the C1 level of `port`, flagging an active edge. Returns the new
+    /// C2 level if the edge ended a handshake.
+    fn c1_edge(port: &mut Port, level: bool) -> Option<bool> {
+        if port.c1 == level {
+            return None;
+        }
+        port.c1 = level;
+        if level != (port.control & CR_C1_RISING != 0) {
+            return None;
+        }
+        port.control |= CR_IRQ1;
+        if port.control & 0x38 == 0x20 && !port.c2_output {
+            port.c2_output = true;
+            return Some(true);
+        }
+        None
+    }
+
+    fn c2_edge(port: &mut Port, level: bool) {
+        if port.c2_input == level {
+            return;
+        }
+        port.c2_input = level;
+        if !port.c2_is_output() && level == (port.control & 0x10 != 0) {
+            port.control |= CR_IRQ2;
+        }
+    }
+
+    /// Let the peripherals update the control line inputs
+    fn sample(&mut self) {
+        let mut lines = ControlLines {
+            ca1: self.a.c1,
+            ca2: self.a.c2_input,
+            cb1: self.b.c1,
+            cb2: self.b.c2_input,
+        };
+        self.ports.update_lines(&mut lines);
+        self.set_ca1(lines.ca1);
+        self.set_ca2(lines.ca2);
+        self.set_cb1(lines.cb1);
+        self.set_cb2(lines.cb2);
+    }
+
+    /// Take C2 low on a port access in handshake mode. Returns whether it
+    /// went low, and whether it returned high right away in pulse mode.
+    fn strobe(port: &mut Port) -> Option<bool> {
+        if !port.c2_handshake() {
+            return None;
+        }
+        if port.control & 0x08 != 0 {
+            return Some(true);
+        }
+        if !port.c2_output {
+            return None;
+        }
+        port.c2_output = false;
+        Some(false)
+    }
+
+    fn write_control(port: &mut Port, value: u8) -> Option<bool> {
+        port.control = (port.control & 0xC0) | (value & 0x3F);
+        if port.c2_is_output() {
+            // The C2 flag is only set while C2 is an input
+            port.control &= !CR_IRQ2;
+            let level = if port.control & 0x10 != 0 {
+                port.control & 0x08 != 0
+            } else {
+                true
+            };
+            if level != port.c2_output {
+                port.c2_output = level;
+                return Some(level);
+            }
+        }
+        None
+    }
+}
+
+impl<P: PiaPorts> MappedDevice for Pia<P> {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.sample();
+        match offset & 0x03 {
+            PORT_A if self.a.control & CR_OUTPUT_REGISTER != 0 => {
+                self.a.input = self.ports.read_a();
+                let value = self.a.pins();
+                self.a.control &= !(CR_IRQ1 | CR_IRQ2);
+                // Read strobe
+                if let Some(level) = Self::strobe(&mut self.a) {
+                    self.ports.set_ca2(false);
+                    if level {
+                        self.ports.set_ca2(true);
+                    }
+                }
+                value
+            }
+            PORT_B if self.b.control & CR_OUTPUT_REGISTER != 0 => {
+                self.b.input = self.ports.read_b();
+                self.b.control &= !(CR_IRQ1 | CR_IRQ2);
+                self.b.pins()
+            }
+            offset => self.peek(offset),
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.sample();
+        match offset & 0x03 {
+            PORT_A => {
+                if self.a.control & CR_OUTPUT_REGISTER != 0 {
+                    self.a.output = value;
+                } else {
+                    self.a.ddr = value;
+                }
+                self.ports.write_a(self.a.outputs());
+            }
+            CRA => {
+                if let Some(level) = Self::write_control(&mut self.a, value) {
+                    self.ports.set_ca2(level);
+                }
+            }
+            PORT_B => {
+                if self.b.control & CR_OUTPUT_REGISTER != 0 {
+                    self.b.output = value;
+                    self.ports.write_b(self.b.outputs());
+                    // Write strobe
+                    if let Some(level) = Self::strobe(&mut self.b) {
+                        self.ports.set_cb2(false);
+                        if level {
+                            self.ports.set_cb2(true);
+                        }
+                    }
+                } else {
+                    self.b.ddr = value;
+                    self.ports.write_b(self.b.outputs());
+                }
+            }
+            CRB => {
+                if let Some(level) = Self::write_control(&mut self.b, value) {
+                    self.ports.set_cb2(level);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        match offset & 0x03 {
+            PORT_A => self.a.register(),
+            CRA => self.a.control,
+            PORT_B => self.b.register(),
+            CRB => self.b.control,
+            _ => unreachable!(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "6821 PIA"
+    }
+
+    /// Registers and line levels. The peripherals aren't saved.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_LEN);
+        for port in [&self.a, &self.b] {
+            state.extend_from_slice(&[
+                port.output,
+                port.ddr,
+                port.control,
+                port.input,
+                port.c1 as u8,
+                port.c2_input as u8,
+                port.c2_output as u8,
+            ]);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        if state.len() != STATE_LEN {
+            return Err(SaveStateError::Invalid(format!(
+                "expected {} bytes of PIA state, got {}",
+                STATE_LEN,
+                state.len()
+            )));
+        }
+        for (port, state) in [&mut self.a, &mut self.b].into_iter().zip(state.chunks(7)) {
+            port.output = state[0];
+            port.ddr = state[1];
+            port.control = state[2];
+            port.input = state[3];
+            port.c1 = state[4] != 0;
+            port.c2_input = state[5] != 0;
+            port.c2_output = state[6] != 0;
+        }
+        Ok(())
+    }
+}
+
+impl<P: PiaPorts> Device for Pia<P> {
+    fn tick(&mut self, _cycles: u64) {
+        self.sample();
+    }
+
+    /// IRQA and IRQB, which are usually wired together
+    fn irq(&self) -> bool {
+        self.a.irq() || self.b.irq()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records what the PIA drives
+    #[derive(Default)]
+    struct Recorder {
+        input_a: u8,
+        output_b: Vec<u8>,
+        cb2: Vec<bool>,
+        ca1: bool,
+    }
+
+    impl PiaPorts for Recorder {
+        fn read_a(&mut self) -> u8 {
+            self.input_a
+        }
+
+        fn write_b(&mut self, value: u8) {
+            self.output_b.push(value);
+        }
+
+        fn set_cb2(&mut self, level: bool) {
+            self.cb2.push(level);
+        }
+
+        fn update_lines(&mut self, lines: &mut ControlLines) {
+            lines.ca1 = self.ca1;
+        }
+    }
+
+    fn setup() -> Pia<Recorder> {
+        Pia::new(Recorder {
+            ca1: true,
+            ..Recorder::default()
+        })
+    }
+
+    #[test]
+    fn test_data_direction() {
+        let mut pia = setup();
+        pia.ports_mut().input_a = 0x5A;
+
+        // DDRA is selected after reset
+        pia.write(PORT_A, 0xF0);
+        assert_eq!(pia.read(PORT_A), 0xF0);
+        pia.write(CRA, CR_OUTPUT_REGISTER);
+        pia.write(PORT_A, 0xCC);
+        assert_eq!(pia.read(PORT_A), 0xCA);
+        assert_eq!(pia.port_a(), 0xCA);
+
+        pia.write(PORT_B, 0x0F);
+        pia.write(CRB, CR_OUTPUT_REGISTER);
+        pia.write(PORT_B, 0x33);
+        assert_eq!(pia.ports().output_b, vec![0xF0, 0xF3]);
+    }
+
+    #[test]
+    fn test_interrupt_flags() {
+        let mut pia = setup();
+        // CA1 interrupt on a falling edge
+        pia.write(CRA, CR_OUTPUT_REGISTER | CR_C1_IRQ);
+        assert!(!pia.irq());
+
+        pia.ports_mut().ca1 = false;
+        pia.tick(1);
+        assert!(pia.irq());
+        assert_eq!(pia.peek(CRA) & CR_IRQ1, CR_IRQ1);
+        // Rising edge is ignored, reading port A clears the flag
+        pia.ports_mut().ca1 = true;
+        pia.read(PORT_A);
+        assert!(!pia.irq());
+        assert_eq!(pia.read(CRA) & CR_IRQ1, 0);
+
+        // CB2 input, interrupt on a rising edge
+        pia.write(CRB, CR_OUTPUT_REGISTER | 0x18);
+        pia.set_cb2(false);
+        assert!(!pia.irq());
+        pia.set_cb2(true);
+        assert!(pia.irq());
+        assert_eq!(pia.peek(CRB) & CR_IRQ2, CR_IRQ2);
+        pia.read(PORT_B);
+        assert!(!pia.irq());
+    }
+
+    #[test]
+    fn test_write_handshake() {
+        let mut pia = setup();
+        // CB2 write strobe, restored by a rising CB1 edge
+        pia.write(CRB, CR_OUTPUT_REGISTER | CR_C1_RISING | 0x20);
+        pia.write(PORT_B, 0x41);
+        assert!(!pia.lines().cb2);
+        pia.set_cb1(false);
+        assert!(!pia.lines().cb2);
+        pia.set_cb1(true);
+        assert!(pia.lines().cb2);
+        assert_eq!(pia.peek(CRB) & CR_IRQ1, CR_IRQ1);
+
+        // Pulse mode
+        pia.write(CRB, CR_OUTPUT_REGISTER | 0x28);
+        pia.write(PORT_B, 0x42);
+        assert!(pia.lines().cb2);
+        // Manual output
+        pia.write(CRB, CR_OUTPUT_REGISTER | 0x30);
+        assert!(!pia.lines().cb2);
+        assert_eq!(pia.ports().cb2, vec![false, true, false, true, false]);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut pia = setup();
+        pia.write(PORT_A, 0x0F);
+        pia.write(CRA, CR_OUTPUT_REGISTER | 0x3C);
+        pia.write(PORT_A, 0x05);
+        let state = pia.save_state();
+
+        let mut restored = Pia::new(());
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.peek(CRA), CR_OUTPUT_REGISTER | 0x3C);
+        assert_eq!(restored.port_a() & 0x0F, 0x05);
+        assert!(restored.lines().ca2);
+    }
+}
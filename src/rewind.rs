@@ -0,0 +1,398 @@
+//! Rewinding execution. While enabled, the CPU keeps a full save state every
+//! `interval` instructions and, in between, the registers and memory writes
+//! of each instruction. Stepping backwards undoes the writes one instruction
+//! at a time, jumping to an earlier cycle restores the closest checkpoint and
+//! replays the writes from there.
+//!
+//! Only writes done by the CPU are recorded. Changes made by devices on their
+//! own are restored from the checkpoints, which rely on
+//! `Memory::save_state()`.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::cpu::CPU;
+use crate::debug::Break;
+use crate::io_port::IoPort;
+use crate::memory::Memory;
+use crate::savestate::SaveState;
+use crate::savestate::SaveStateError;
+use crate::state::Registers;
+
+/// Error jumping to an earlier point of execution
+#[derive(Debug)]
+pub enum RewindError {
+    /// Rewinding is not enabled
+    Disabled,
+    /// The requested cycle is older than the kept history
+    OutOfHistory,
+    /// Restoring a checkpoint failed
+    SaveState(SaveStateError),
+}
+
+impl fmt::Display for RewindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RewindError::Disabled => write!(f, "Rewinding is not enabled"),
+            RewindError::OutOfHistory => write!(f, "Cycle is not in the rewind history"),
+            RewindError::SaveState(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for RewindError {}
+
+impl From<SaveStateError> for RewindError {
+    fn from(error: SaveStateError) -> Self {
+        RewindError::SaveState(error)
+    }
+}
+
+/// A memory write done by an instruction
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct MemoryWrite {
+    pub(crate) address: u16,
+    pub(crate) old: u8,
+    pub(crate) new: u8,
+}
+
+/// CPU state kept for every instruction
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Snapshot {
+    pub(crate) registers: Registers,
+    pub(crate) irq: bool,
+    pub(crate) nmi: bool,
+    pub(crate) nmi_pending: bool,
+    pub(crate) halted: bool,
+    pub(crate) waiting: bool,
+    pub(crate) rdy: bool,
+    pub(crate) so: bool,
+    pub(crate) stall: u64,
+    pub(crate) io_port: Option<IoPort>,
+}
+
+#[derive(Clone)]
+struct Step {
+    before: Snapshot,
+    after: Snapshot,
+    writes: Vec<MemoryWrite>,
+}
+
+/// Save state followed by the instructions executed after it
+struct Checkpoint {
+    state: SaveState,
+    steps: Vec<Step>,
+}
+
+/// Rewind history of the CPU
+pub(crate) struct Rewind {
+    interval: usize,
+    capacity: usize,
+    checkpoints: VecDeque<Checkpoint>,
+}
+
+impl Rewind {
+    fn new(interval: usize, capacity: usize, state: SaveState) -> Rewind {
+        let mut rewind = Rewind {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            checkpoints: VecDeque::new(),
+        };
+        rewind.checkpoint(state);
+        rewind
+    }
+
+    pub(crate) fn record(&mut self, before: Snapshot, after: Snapshot, writes: Vec<MemoryWrite>) {
+        let checkpoint = self
+            .checkpoints
+            .back_mut()
+            .expect("there is always a checkpoint");
+        checkpoint.steps.push(Step {
+            before,
+            after,
+            writes,
+        });
+    }
+
+    pub(crate) fn needs_checkpoint(&self) -> bool {
+        self.checkpoints
+            .back()
+            .is_some_and(|checkpoint| checkpoint.steps.len() >= self.interval)
+    }
+
+    pub(crate) fn checkpoint(&mut self, state: SaveState) {
+        if self.checkpoints.len() == self.capacity {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(Checkpoint {
+            state,
+            steps: Vec::new(),
+        });
+    }
+
+    /// Remove the latest instruction from the history
+    fn pop_step(&mut self) -> Option<Step> {
+        loop {
+            let checkpoint = self.checkpoints.back_mut()?;
+            if let Some(step) = checkpoint.steps.pop() {
+                return Some(step);
+            }
+            if self.checkpoints.len() == 1 {
+                return None;
+            }
+            // The checkpoint is the state after the last step of the previous
+            // one
+            self.checkpoints.pop_back();
+        }
+    }
+
+    /// Find the checkpoint to restore for going back to `cycles`. Returns
+    /// its index and the number of its steps to replay after it.
+    fn seek(&self, cycles: u64) -> Option<(usize, usize)> {
+        let index = self
+            .checkpoints
+            .iter()
+            .rposition(|checkpoint| checkpoint.state.registers.cycles <= cycles)?;
+        let kept = self.checkpoints[index]
+            .steps
+            .iter()
+            .take_while(|step| step.after.registers.cycles <= cycles)
+            .count();
+        Some((index, kept))
+    }
+
+    /// Drop the history after the first `kept` steps of checkpoint `index`
+    fn truncate(&mut self, index: usize, kept: usize) {
+        self.checkpoints.truncate(index + 1);
+        if let Some(checkpoint) = self.checkpoints.back_mut() {
+            checkpoint.steps.truncate(kept);
+        }
+    }
+
+    fn earliest_cycle(&self) -> u64 {
+        self.checkpoints
+            .front()
+            .map_or(0, |checkpoint| checkpoint.state.registers.cycles)
+    }
+}
+
+impl<T: Memory> CPU<T> {
+    /// Start keeping a rewind history with a checkpoint every `interval`
+    /// instructions. The `capacity` most recent checkpoints are kept, which
+    /// bounds the history to about `interval * capacity` instructions.
+    pub fn enable_rewind(&mut self, interval: usize, capacity: usize) -> Result<(), RewindError> {
+        let state = self.save_state()?;
+        self.rewind = Some(Rewind::new(interval, capacity, state));
+        self.get_mut_state().set_journal(true);
+        Ok(())
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+        self.get_mut_state().set_journal(false);
+    }
+
+    /// Earliest cycle that can be rewound to, if rewinding is enabled
+    pub fn earliest_rewind_cycle(&self) -> Option<u64> {
+        self.rewind.as_ref().map(Rewind::earliest_cycle)
+    }
+
+    /// Undo the last instruction. An instruction in progress from `tick()`
+    /// is abandoned instead, going back to the state before it. Returns false
+    /// when there is no history left.
+    pub fn step_back(&mut self) -> bool {
+        if self.rewind.is_none() {
+            return false;
+        }
+        if self.abandon_instruction() {
+            return true;
+        }
+        let Some(step) = self.rewind.as_mut().and_then(Rewind::pop_step) else {
+            return false;
+        };
+        let memory = self.get_mut_state().get_mut_memory();
+        for write in step.writes.iter().rev() {
+            memory.poke(write.address, write.old);
+        }
+        self.restore_snapshot(&step.before);
+        true
+    }
+
+    /// Step backwards until a breakpoint of the CPU is hit. Watchpoints are
+    /// not checked. Returns `None` when the start of the history is reached.
+    pub fn run_back_until_break(&mut self) -> Option<Break> {
+        while self.step_back() {
+            if let Some(hit) = self.check_breakpoints() {
+                return Some(hit);
+            }
+        }
+        None
+    }
+
+    /// Go back to the start of the last instruction that ended at or before
+    /// `cycles`. Returns the cycle count reached. An instruction in progress
+    /// from `tick()` is abandoned first.
+    pub fn rewind_to_cycle(&mut self, cycles: u64) -> Result<u64, RewindError> {
+        if self.rewind.is_none() {
+            return Err(RewindError::Disabled);
+        }
+        self.abandon_instruction();
+        let mut rewind = self.rewind.take().ok_or(RewindError::Disabled)?;
+        let result = match rewind.seek(cycles) {
+            None => Err(RewindError::OutOfHistory),
+            Some((index, kept)) => self.replay(&rewind.checkpoints[index], kept).map(|_| {
+                // Only drop the history once the state has been restored
+                rewind.truncate(index, kept);
+                self.get_state().cycles
+            }),
+        };
+        self.rewind = Some(rewind);
+        result
+    }
+
+    /// Restore `checkpoint` and replay its first `steps` steps
+    fn replay(&mut self, checkpoint: &Checkpoint, steps: usize) -> Result<(), RewindError> {
+        self.load_state(&checkpoint.state)?;
+        for step in &checkpoint.steps[..steps] {
+            let memory = self.get_mut_state().get_mut_memory();
+            for write in &step.writes {
+                memory.poke(write.address, write.new);
+            }
+            self.restore_snapshot(&step.after);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debug::Break;
+    use crate::memory::Memory;
+    use crate::test_util::cpu_with_program;
+
+    use super::RewindError;
+
+    // LDX #$00, INX, STX $0200, JMP $0602
+    const PROGRAM: [u8; 9] = [0xA2, 0x00, 0xE8, 0x8E, 0x00, 0x02, 0x4C, 0x02, 0x06];
+
+    #[test]
+    fn test_step_back() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        assert!(!cpu.step_back());
+        cpu.enable_rewind(4, 4).unwrap();
+        for _ in 0..31 {
+            cpu.step();
+        }
+        assert_eq!(cpu.get_state().x, 10);
+        assert_eq!(cpu.get_state().get_memory().peek(0x0200), 10);
+
+        // Undo JMP and STX
+        assert!(cpu.step_back());
+        assert!(cpu.step_back());
+        assert_eq!(cpu.get_state().pc, 0x0603);
+        assert_eq!(cpu.get_state().x, 10);
+        assert_eq!(cpu.get_state().get_memory().peek(0x0200), 9);
+
+        let mut steps = 2;
+        while cpu.step_back() {
+            steps += 1;
+        }
+        // Only the last four checkpoints are kept
+        assert!(steps < 31);
+        assert_eq!(cpu.get_state().cycles, cpu.earliest_rewind_cycle().unwrap());
+    }
+
+    #[test]
+    fn test_step_back_in_progress() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        cpu.enable_rewind(4, 4).unwrap();
+        cpu.step();
+        cpu.step();
+        // Start STX, then undo it completely
+        cpu.tick();
+        assert!(cpu.step_back());
+        assert_eq!(cpu.get_state().pc, 0x0603);
+        assert_eq!(cpu.get_state().cycles, 7 + 4);
+        assert_eq!(cpu.get_state().get_memory().peek(0x0200), 0);
+    }
+
+    #[test]
+    fn test_rewind_while_not_ready() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        cpu.enable_rewind(4, 4).unwrap();
+        cpu.step();
+        cpu.step();
+        // Start STX and hold it with RDY, as during a DMA transfer
+        cpu.tick();
+        cpu.set_rdy(false);
+        assert!(cpu.step_back());
+        assert_eq!(cpu.get_state().pc, 0x0603);
+        assert_eq!(cpu.get_state().cycles, 7 + 4);
+
+        cpu.tick();
+        assert_eq!(cpu.rewind_to_cycle(7 + 2).unwrap(), 7 + 2);
+        assert_eq!(cpu.get_state().pc, 0x0602);
+
+        // The abandoned instruction runs normally once RDY is back
+        cpu.set_rdy(true);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.get_state().pc, 0x0606);
+        assert_eq!(cpu.get_state().get_memory().peek(0x0200), 1);
+    }
+
+    #[test]
+    fn test_rewind_to_cycle() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        assert!(matches!(cpu.rewind_to_cycle(0), Err(RewindError::Disabled)));
+        cpu.enable_rewind(4, 100).unwrap();
+        for _ in 0..30 {
+            cpu.step();
+        }
+        // Reset takes 7 cycles and LDX 2, then each loop INX 2 + STX 4 + JMP 3
+        let cycles = 7 + 2 + 9 * 3 + 2;
+        assert_eq!(cpu.rewind_to_cycle(cycles).unwrap(), cycles);
+        assert_eq!(cpu.get_state().pc, 0x0603);
+        assert_eq!(cpu.get_state().x, 4);
+        assert_eq!(cpu.get_state().get_memory().peek(0x0200), 3);
+
+        // Execution continues from there
+        cpu.step();
+        assert_eq!(cpu.get_state().get_memory().peek(0x0200), 4);
+        assert!(cpu.step_back());
+        assert_eq!(cpu.get_state().get_memory().peek(0x0200), 3);
+    }
+
+    #[test]
+    fn test_rewind_failure_keeps_history() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        cpu.enable_rewind(4, 100).unwrap();
+        for _ in 0..30 {
+            cpu.step();
+        }
+        let rewind = cpu.rewind.as_mut().unwrap();
+        rewind.checkpoints[1].state.memory.clear();
+        let earlier = rewind.checkpoints[1].state.registers.cycles;
+        let later = rewind.checkpoints[2].state.registers.cycles;
+
+        assert!(matches!(
+            cpu.rewind_to_cycle(earlier),
+            Err(RewindError::SaveState(_))
+        ));
+        // The history after the failed target is still there
+        assert_eq!(cpu.rewind_to_cycle(later).unwrap(), later);
+    }
+
+    #[test]
+    fn test_run_back_until_break() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        cpu.enable_rewind(4, 100).unwrap();
+        for _ in 0..30 {
+            cpu.step();
+        }
+        cpu.add_condition(|state| state.x == 5);
+        assert_eq!(cpu.run_back_until_break(), Some(Break::Condition(0)));
+        assert_eq!(cpu.get_state().x, 5);
+        assert_eq!(cpu.get_state().pc, 0x0602);
+    }
+}
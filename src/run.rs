@@ -0,0 +1,220 @@
+//! Running the CPU until a stop condition is met
+
+use crate::cpu::CPU;
+use crate::debug::Break;
+use crate::error::CpuError;
+use crate::instrumentation::TraceSink;
+use crate::memory::Memory;
+use crate::state::CPUState;
+
+/// Predicate over the state after each instruction
+type Predicate<'a, T> = Box<dyn FnMut(&CPUState<T>) -> bool + 'a>;
+
+/// Why `CPU::run_until()` stopped
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// PC reached the requested address
+    Pc(u16),
+    /// The cycle budget was used up. The last instruction may have run past
+    /// it by `overshoot` cycles.
+    Cycles { overshoot: u64 },
+    /// The requested number of instructions were executed
+    Instructions,
+    /// The instruction at `pc` jumps or branches to itself
+    InfiniteLoop { pc: u16 },
+    /// The predicate returned true
+    Predicate,
+    /// A breakpoint or watchpoint of the CPU was hit
+    Break(Break),
+    /// The next instruction could not be executed
+    Error(CpuError),
+}
+
+/// Conditions for `CPU::run_until()`. Execution stops after the first
+/// instruction that meets any of them, the cycle and instruction counts are
+/// relative to the start of the run.
+pub struct StopConditions<'a, T: Memory> {
+    pc: Option<u16>,
+    cycles: Option<u64>,
+    instructions: Option<u64>,
+    infinite_loop: bool,
+    predicate: Option<Predicate<'a, T>>,
+}
+
+impl<'a, T: Memory> StopConditions<'a, T> {
+    /// No conditions, runs until an error
+    pub fn new() -> StopConditions<'a, T> {
+        StopConditions {
+            pc: None,
+            cycles: None,
+            instructions: None,
+            infinite_loop: false,
+            predicate: None,
+        }
+    }
+
+    /// Stop when PC reaches `pc`, before executing the instruction there
+    pub fn pc(mut self, pc: u16) -> Self {
+        self.pc = Some(pc);
+        self
+    }
+
+    /// Stop when at least `cycles` cycles have been executed
+    pub fn cycles(mut self, cycles: u64) -> Self {
+        self.cycles = Some(cycles);
+        self
+    }
+
+    /// Stop after executing `instructions` instructions
+    pub fn instructions(mut self, instructions: u64) -> Self {
+        self.instructions = Some(instructions);
+        self
+    }
+
+    /// Stop on an instruction that jumps or branches to itself, the usual
+    /// way for test programs to signal success or failure
+    pub fn infinite_loop(mut self) -> Self {
+        self.infinite_loop = true;
+        self
+    }
+
+    /// Stop when `predicate` returns true for the state after an instruction
+    pub fn predicate(mut self, predicate: impl FnMut(&CPUState<T>) -> bool + 'a) -> Self {
+        self.predicate = Some(Box::new(predicate));
+        self
+    }
+}
+
+impl<T: Memory> Default for StopConditions<'_, T> {
+    fn default() -> Self {
+        StopConditions::new()
+    }
+}
+
+impl<T: Memory> CPU<T> {
+    /// Execute instructions until one of `conditions` is met, a breakpoint is
+    /// hit or an instruction fails. The trace of each instruction executed is
+    /// passed to `sink`.
+    ///
+    /// A CPU halted by `JAM` or `STP` stops with `CpuError::Halted` rather
+    /// than as an infinite loop, and one waiting in `WAI` keeps running.
+    pub fn run_until<S: TraceSink + ?Sized>(
+        &mut self,
+        mut conditions: StopConditions<T>,
+        sink: &mut S,
+    ) -> StopReason {
+        let start_cycles = self.get_state().cycles;
+        let mut instructions = 0;
+        // Forget accesses done before this run
+        self.get_mut_state().watchpoints.take_hit();
+
+        loop {
+            let trace = match self.try_step() {
+                Ok(trace) => trace,
+                Err(error) => return StopReason::Error(error),
+            };
+            sink.trace(&trace);
+            instructions += 1;
+
+            if let Some(hit) = self.check_breakpoints() {
+                return StopReason::Break(hit);
+            }
+
+            let state = self.get_state();
+            if conditions.pc == Some(state.pc) {
+                return StopReason::Pc(state.pc);
+            }
+            let stuck = self.is_halted() || self.is_waiting();
+            if conditions.infinite_loop && !stuck && state.pc == trace.pc {
+                return StopReason::InfiniteLoop { pc: trace.pc };
+            }
+            if let Some(predicate) = conditions.predicate.as_mut() {
+                if predicate(state) {
+                    return StopReason::Predicate;
+                }
+            }
+            if conditions.instructions == Some(instructions) {
+                return StopReason::Instructions;
+            }
+            if let Some(budget) = conditions.cycles {
+                let cycles = state.cycles - start_cycles;
+                if cycles >= budget {
+                    return StopReason::Cycles {
+                        overshoot: cycles - budget,
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StopConditions, StopReason};
+    use crate::cpu::Variant;
+    use crate::error::CpuError;
+    use crate::instrumentation::NoopSink;
+    use crate::test_util::{cpu_with_program, cpu_with_variant};
+
+    // INX, INX, INY, JMP $0603
+    const PROGRAM: [u8; 6] = [0xE8, 0xE8, 0xC8, 0x4C, 0x03, 0x06];
+
+    #[test]
+    fn test_stop_conditions() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        let reason = cpu.run_until(StopConditions::new().pc(0x0602), &mut NoopSink);
+        assert_eq!(reason, StopReason::Pc(0x0602));
+
+        let reason = cpu.run_until(StopConditions::new().instructions(1), &mut NoopSink);
+        assert_eq!(reason, StopReason::Instructions);
+        assert_eq!(cpu.get_state().y, 1);
+
+        let reason = cpu.run_until(
+            StopConditions::new().predicate(|state| state.pc == 0x0603),
+            &mut NoopSink,
+        );
+        assert_eq!(reason, StopReason::Predicate);
+
+        let reason = cpu.run_until(StopConditions::new().infinite_loop(), &mut NoopSink);
+        assert_eq!(reason, StopReason::InfiniteLoop { pc: 0x0603 });
+    }
+
+    #[test]
+    fn test_cycle_budget() {
+        let mut cpu = cpu_with_program(&PROGRAM, 0x0600);
+        // 2 + 2 + 2 + 3 cycles
+        let reason = cpu.run_until(StopConditions::new().cycles(8), &mut NoopSink);
+        assert_eq!(reason, StopReason::Cycles { overshoot: 1 });
+    }
+
+    #[test]
+    fn test_error() {
+        let mut cpu = cpu_with_program(&[0xEA, 0x02], 0x0600);
+        let reason = cpu.run_until(StopConditions::new(), &mut NoopSink);
+        assert_eq!(
+            reason,
+            StopReason::Error(CpuError::UnknownOpcode {
+                opcode: 0x02,
+                pc: 0x0601
+            })
+        );
+    }
+
+    #[test]
+    fn test_halted_and_waiting() {
+        // NOP, JAM
+        let mut cpu = cpu_with_program(&[0xEA, 0x02], 0x0600);
+        cpu.set_undocumented_opcodes(true);
+        let reason = cpu.run_until(StopConditions::new().infinite_loop(), &mut NoopSink);
+        assert_eq!(reason, StopReason::Error(CpuError::Halted { pc: 0x0601 }));
+
+        // WAI, until an IRQ
+        let mut cpu = cpu_with_variant(&[0xCB], 0x0600, Variant::WDC65C02);
+        let reason = cpu.run_until(
+            StopConditions::new().infinite_loop().instructions(10),
+            &mut NoopSink,
+        );
+        assert_eq!(reason, StopReason::Instructions);
+        assert!(cpu.is_waiting());
+    }
+}
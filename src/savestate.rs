@@ -0,0 +1,325 @@
+//! Save states of the whole machine: CPU registers, interrupt lines and the
+//! state contributed by the `Memory` implementation.
+//!
+//! `SaveState::to_bytes()` and `SaveState::from_bytes()` use a versioned
+//! binary format. With the `serde` feature the `SaveState` can be stored in
+//! any format supported by serde instead.
+
+use std::fmt;
+use std::io;
+
+use crate::cpu::Variant;
+use crate::state::Registers;
+
+/// Version of the save state format written by this crate
+pub const SAVE_STATE_VERSION: u32 = 1;
+
+const MAGIC: [u8; 4] = *b"PHKB";
+
+/// Error saving or restoring a save state
+#[derive(Debug)]
+pub enum SaveStateError {
+    /// The data is not a save state
+    BadMagic,
+    /// The save state was written by an incompatible version
+    UnsupportedVersion(u32),
+    /// The data ends in the middle of the save state
+    Truncated,
+    /// A field has an invalid value
+    Invalid(String),
+    /// A state can't be saved in the middle of an instruction started with
+    /// `CPU::tick()`
+    InstructionInProgress,
+    /// Reading or writing the save state failed
+    Io(io::Error),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "Not a save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported save state version {}", version)
+            }
+            SaveStateError::Truncated => write!(f, "Save state is truncated"),
+            SaveStateError::Invalid(message) => write!(f, "Invalid save state: {}", message),
+            SaveStateError::InstructionInProgress => write!(f, "Instruction in progress"),
+            SaveStateError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(error: io::Error) -> Self {
+        SaveStateError::Io(error)
+    }
+}
+
+/// Snapshot of the machine between two instructions, taken with
+/// `CPU::save_state()` and restored with `CPU::load_state()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SaveState {
+    pub version: u32,
+    pub variant: Variant,
+    pub registers: Registers,
+    pub irq: bool,
+    pub nmi: bool,
+    pub nmi_pending: bool,
+    pub halted: bool,
+    pub waiting: bool,
+    pub undocumented_opcodes: bool,
+    pub rdy: bool,
+    pub so: bool,
+    /// Cycles stolen by DMA that the CPU hasn't spent yet
+    pub stall: u64,
+    /// State of the 6510's I/O port, empty for the other variants
+    pub io_port: Vec<u8>,
+    /// State returned by `Memory::save_state()`
+    pub memory: Vec<u8>,
+}
+
+impl SaveState {
+    /// Encode in the binary save state format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let registers = &self.registers;
+        let flags = [
+            self.irq,
+            self.nmi,
+            self.nmi_pending,
+            self.halted,
+            self.waiting,
+            self.undocumented_opcodes,
+            self.rdy,
+            self.so,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0u8, |flags, (bit, set)| flags | ((*set as u8) << bit));
+
+        let mut bytes = Vec::with_capacity(44 + self.io_port.len() + self.memory.len());
+        bytes.extend(MAGIC);
+        bytes.extend(self.version.to_le_bytes());
+        bytes.push(variant_to_byte(self.variant));
+        bytes.extend([registers.a, registers.x, registers.y]);
+        bytes.extend(registers.pc.to_le_bytes());
+        bytes.extend([registers.sp, registers.status]);
+        bytes.extend(registers.cycles.to_le_bytes());
+        bytes.push(flags);
+        bytes.extend(self.stall.to_le_bytes());
+        bytes.extend((self.io_port.len() as u32).to_le_bytes());
+        bytes.extend(&self.io_port);
+        bytes.extend((self.memory.len() as u32).to_le_bytes());
+        bytes.extend(&self.memory);
+        bytes
+    }
+
+    /// Decode the binary save state format
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveState, SaveStateError> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = u32::from_le_bytes(reader.array()?);
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let variant = byte_to_variant(reader.array::<1>()?[0])?;
+        let [a, x, y] = reader.array()?;
+        let pc = u16::from_le_bytes(reader.array()?);
+        let [sp, status] = reader.array()?;
+        let cycles = u64::from_le_bytes(reader.array()?);
+        let [flags] = reader.array()?;
+        let stall = u64::from_le_bytes(reader.array()?);
+        let io_port_length = u32::from_le_bytes(reader.array()?) as usize;
+        let io_port = reader.take(io_port_length)?.to_vec();
+        let memory_length = u32::from_le_bytes(reader.array()?) as usize;
+        let memory = reader.take(memory_length)?.to_vec();
+        let flag = |bit: u8| flags & (1 << bit) != 0;
+
+        Ok(SaveState {
+            version,
+            variant,
+            registers: Registers {
+                a,
+                x,
+                y,
+                pc,
+                sp,
+                status,
+                cycles,
+            },
+            irq: flag(0),
+            nmi: flag(1),
+            nmi_pending: flag(2),
+            halted: flag(3),
+            waiting: flag(4),
+            undocumented_opcodes: flag(5),
+            rdy: flag(6),
+            so: flag(7),
+            stall,
+            io_port,
+            memory,
+        })
+    }
+
+    /// Write in the binary format, e.g. to a file
+    pub fn write_to<W: io::Write>(&self, mut writer: W) -> Result<(), SaveStateError> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Read the binary format, e.g. from a file
+    pub fn read_from<R: io::Read>(mut reader: R) -> Result<SaveState, SaveStateError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        SaveState::from_bytes(&bytes)
+    }
+}
+
+fn variant_to_byte(variant: Variant) -> u8 {
+    match variant {
+        Variant::MOS6502 => 0,
+        Variant::WDC65C02 => 1,
+        Variant::Ricoh2A03 => 2,
+        Variant::MOS6510 => 3,
+    }
+}
+
+fn byte_to_variant(byte: u8) -> Result<Variant, SaveStateError> {
+    match byte {
+        0 => Ok(Variant::MOS6502),
+        1 => Ok(Variant::WDC65C02),
+        2 => Ok(Variant::Ricoh2A03),
+        3 => Ok(Variant::MOS6510),
+        _ => Err(SaveStateError::Invalid(format!(
+            "unknown CPU variant {}",
+            byte
+        ))),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.bytes.len() < length {
+            return Err(SaveStateError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SaveState, SaveStateError};
+    use crate::cpu::Variant;
+    use crate::io_port::{DATA_ADDR, DDR_ADDR};
+    use crate::memory::Memory;
+    use crate::test_util::cpu_with_variant;
+
+    // INC $10, JMP $0600
+    const PROGRAM: [u8; 5] = [0xE6, 0x10, 0x4C, 0x00, 0x06];
+
+    #[test]
+    fn test_save_and_restore() {
+        let mut cpu = cpu_with_variant(&PROGRAM, 0x0600, Variant::MOS6502);
+        for _ in 0..10 {
+            cpu.step();
+        }
+        cpu.set_irq(true);
+        let save_state = cpu.save_state().unwrap();
+        let bytes = save_state.to_bytes();
+        let registers = cpu.get_state().registers();
+
+        cpu.set_irq(false);
+        for _ in 0..10 {
+            cpu.step();
+        }
+        assert_eq!(cpu.get_state().get_memory().peek(0x0010), 10);
+
+        let decoded = SaveState::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, save_state);
+        cpu.load_state(&decoded).unwrap();
+        assert_eq!(cpu.get_state().registers(), registers);
+        assert_eq!(cpu.get_state().get_memory().peek(0x0010), 5);
+        assert!(cpu.get_irq());
+    }
+
+    #[test]
+    fn test_invalid_save_states() {
+        let mut cpu = cpu_with_variant(&PROGRAM, 0x0600, Variant::MOS6502);
+        cpu.tick();
+        assert!(matches!(
+            cpu.save_state(),
+            Err(SaveStateError::InstructionInProgress)
+        ));
+        cpu.step();
+
+        let bytes = cpu.save_state().unwrap().to_bytes();
+        assert!(matches!(
+            SaveState::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SaveStateError::Truncated)
+        ));
+        assert!(matches!(
+            SaveState::from_bytes(b"not a save state"),
+            Err(SaveStateError::BadMagic)
+        ));
+
+        let mut future = bytes.clone();
+        future[4] = 99;
+        assert!(matches!(
+            SaveState::from_bytes(&future),
+            Err(SaveStateError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn test_load_corrupted_state() {
+        let mut cpu = cpu_with_variant(&PROGRAM, 0x0600, Variant::MOS6510);
+        let mut save_state = cpu.save_state().unwrap();
+        save_state.io_port.pop();
+        for _ in 0..10 {
+            cpu.step();
+        }
+        let registers = cpu.get_state().registers();
+
+        assert!(matches!(
+            cpu.load_state(&save_state),
+            Err(SaveStateError::Invalid(_))
+        ));
+        assert_eq!(cpu.get_state().registers(), registers);
+        assert_eq!(cpu.get_state().get_memory().peek(0x0010), 5);
+    }
+
+    #[test]
+    fn test_io_port_state() {
+        let mut cpu = cpu_with_variant(&PROGRAM, 0x0600, Variant::MOS6510);
+        cpu.get_mut_state().write_byte(DDR_ADDR, 0xFF);
+        cpu.get_mut_state().write_byte(DATA_ADDR, 0x37);
+        let bytes = cpu.save_state().unwrap().to_bytes();
+
+        cpu.get_mut_state().write_byte(DATA_ADDR, 0x30);
+        cpu.load_state(&SaveState::from_bytes(&bytes).unwrap())
+            .unwrap();
+        assert_eq!(cpu.get_state().io_port().unwrap().data(), 0x37);
+
+        // The port goes away with a save state of another variant
+        let other = cpu_with_variant(&PROGRAM, 0x0600, Variant::MOS6502)
+            .save_state()
+            .unwrap();
+        assert!(other.io_port.is_empty());
+        cpu.load_state(&other).unwrap();
+        assert!(cpu.get_state().io_port().is_none());
+    }
+}
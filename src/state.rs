@@ -1,18 +1,134 @@
 //! Model the state of the CPU in addition to accessing memory.
 
+use crate::debug::Watchpoints;
+use crate::error::CpuError;
 use crate::instruction::AddressingMode;
+use crate::io_port;
+use crate::io_port::IoPort;
+use crate::memory::AccessKind;
 use crate::memory::Memory;
+use crate::rewind::MemoryWrite;
 
 /// Stack page start address
 pub const STACK_PAGE: u16 = 0x100;
+/// Address where the NMI vector is stored
+pub const NMI_VECTOR_ADDR: u16 = 0xFFFA;
 /// Address where the reset vector is stored
 pub const RESET_VECTOR_ADDR: u16 = 0xFFFC;
 /// Address where the IRQ vector is stored
 pub const IRQ_VECTOR_ADDR: u16 = 0xFFFE;
 
+/// Snapshot of the CPU registers
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub pc: u16,
+    pub sp: u8,
+    pub status: u8,
+    pub cycles: u64,
+}
+
+/// Contents of the registers and RAM when the machine is switched on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PowerOn {
+    /// Everything is zero
+    Zeroed,
+    /// Every register and RAM byte holds the same value
+    Pattern(u8),
+    /// Pseudo-random contents, the same for the same seed
+    Random(u64),
+}
+
+impl PowerOn {
+    /// Source of the power-on bytes
+    fn bytes(self) -> impl FnMut() -> u8 {
+        // SplitMix64
+        let mut seed = match self {
+            PowerOn::Random(seed) => seed,
+            _ => 0,
+        };
+        move || match self {
+            PowerOn::Zeroed => 0,
+            PowerOn::Pattern(value) => value,
+            PowerOn::Random(_) => {
+                seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = seed;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                (z ^ (z >> 31)) as u8
+            }
+        }
+    }
+}
+
+/// A single bus access
+#[derive(Copy, Clone, Debug)]
+struct BusCycle {
+    address: u16,
+    value: u8,
+}
+
+/// Bus activity of an instruction that is being executed one cycle at a
+/// time. The instruction is re-run from the start on every cycle: accesses
+/// done on earlier cycles are served from `log` without touching memory,
+/// one new access goes to memory and anything past `limit` is discarded.
+#[derive(Default)]
+struct BusReplay {
+    log: Vec<BusCycle>,
+    position: usize,
+    limit: usize,
+    /// RDY is low: the new access waits if it is a read, or a write when
+    /// `wait_writes` is set
+    wait_reads: bool,
+    wait_writes: bool,
+    /// The new access of this cycle waited
+    waited: bool,
+    /// Cycles spent waiting for RDY
+    waits: u64,
+}
+
+enum Access {
+    /// Access done on an earlier cycle, with the value that was read
+    Logged(u8),
+    /// Access to perform against memory on this cycle
+    Live,
+    /// Access belonging to a later cycle
+    Overrun,
+}
+
+impl BusReplay {
+    fn next(&mut self, address: u16, write: bool) -> Access {
+        let position = self.position;
+        self.position += 1;
+        if let Some(cycle) = self.log.get(position) {
+            debug_assert_eq!(cycle.address, address, "instruction replayed differently");
+            Access::Logged(cycle.value)
+        } else if position < self.limit {
+            if (write && self.wait_writes) || (!write && self.wait_reads) {
+                self.waited = true;
+                self.waits += 1;
+                return Access::Overrun;
+            }
+            Access::Live
+        } else {
+            Access::Overrun
+        }
+    }
+}
+
 /// Represents the state of the CPU.
 pub struct CPUState<T: Memory> {
     memory: T,
+    replay: Option<BusReplay>,
+    pub(crate) watchpoints: Watchpoints,
+    /// Memory writes of the current instruction, kept while rewinding is
+    /// enabled
+    journal: Option<Vec<MemoryWrite>>,
+    /// On-chip I/O port of the 6510
+    io_port: Option<IoPort>,
 
     pub a: u8,
     pub x: u8,
@@ -33,34 +149,265 @@ impl<T: Memory> CPUState<T> {
             sp: 0,
             status: 0,
             cycles: 0,
-            memory: memory,
+            memory,
+            replay: None,
+            watchpoints: Watchpoints::default(),
+            journal: None,
+            io_port: None,
         }
     }
 
-    /// Resets the CPU to the initial state and sets PC to the reset vector.
-    pub fn reset(&mut self) {
-        self.a = 0;
-        self.x = 0;
-        self.y = 0;
-        self.sp = 0xFF;
-        self.status = 0x36;
-        self.cycles = 0;
+    /// State of a machine that was just switched on: the registers and RAM
+    /// hold the contents given by `power_on`. Load programs after this, then
+    /// `reset()`.
+    pub fn with_power_on(memory: T, power_on: PowerOn) -> CPUState<T> {
+        let mut state = CPUState::new(memory);
+        let mut bytes = power_on.bytes();
+        state.a = bytes();
+        state.x = bytes();
+        state.y = bytes();
+        state.sp = bytes();
+        state.status = bytes();
+        state.pc = u16::from_le_bytes([bytes(), bytes()]);
+        state.memory.fill_ram(&mut bytes);
+        state
+    }
 
-        self.pc = self.read_word(RESET_VECTOR_ADDR);
+    pub fn get_memory(&self) -> &T {
+        &self.memory
     }
 
+    pub fn get_mut_memory(&mut self) -> &mut T {
+        &mut self.memory
+    }
+
+    /// I/O port at $0000 and $0001, only present on the 6510
+    pub fn io_port(&self) -> Option<&IoPort> {
+        self.io_port.as_ref()
+    }
+
+    /// Change the I/O port, e.g. to drive its input pins. The memory is told
+    /// about the new levels on the pins.
+    pub fn update_io_port(&mut self, change: impl FnOnce(&mut IoPort)) {
+        if let Some(io_port) = self.io_port.as_mut() {
+            change(io_port);
+            self.memory.set_io_port(io_port.pins());
+        }
+    }
+
+    /// Add or remove the I/O port, telling the memory about its pins
+    pub(crate) fn set_io_port(&mut self, io_port: Option<IoPort>) {
+        self.io_port = io_port;
+        if let Some(io_port) = self.io_port {
+            self.memory.set_io_port(io_port.pins());
+        }
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            pc: self.pc,
+            sp: self.sp,
+            status: self.status,
+            cycles: self.cycles,
+        }
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.a = registers.a;
+        self.x = registers.x;
+        self.y = registers.y;
+        self.pc = registers.pc;
+        self.sp = registers.sp;
+        self.status = registers.status;
+        self.cycles = registers.cycles;
+    }
+
+    /// Start re-running the current instruction with one more bus cycle than
+    /// on the previous attempt. While RDY is low the new bus cycle waits
+    /// instead if it is a read, or a write when `wait_writes` is set.
+    pub(crate) fn begin_cycle(&mut self, rdy: bool, wait_writes: bool) {
+        let mut replay = self.replay.take().unwrap_or_default();
+        replay.position = 0;
+        replay.limit = replay.log.len() + 1;
+        replay.wait_reads = !rdy;
+        replay.wait_writes = !rdy && wait_writes;
+        replay.waited = false;
+        self.replay = Some(replay);
+    }
+
+    /// Finish a cycle started with `begin_cycle`. Returns the number of
+    /// cycles done so far if the instruction needs more of them, or `None`
+    /// if the instruction completed on this cycle. Cycles spent waiting for
+    /// RDY are added to `cycles`.
+    pub(crate) fn end_cycle(&mut self) -> Option<u64> {
+        let replay = self.replay.as_ref()?;
+        if replay.waited || replay.position > replay.limit {
+            Some(replay.log.len() as u64 + replay.waits)
+        } else {
+            self.cycles += replay.waits;
+            self.replay = None;
+            None
+        }
+    }
+
+    /// Run the reset sequence, which takes 7 cycles. It is an interrupt
+    /// sequence with the stack writes turned into reads, so SP drops by 3
+    /// without writing anything. A, X and Y are kept, I is set and PC is
+    /// loaded from the reset vector. The pins of the 6510's I/O port become
+    /// inputs.
+    pub fn reset(&mut self) {
+        let now = self.cycles;
+        self.update_io_port(|io_port| io_port.reset(now));
+        self.dummy_read(self.pc);
+        self.dummy_read(self.pc);
+        for _ in 0..3 {
+            self.read_stack();
+            self.sp = self.sp.wrapping_sub(1);
+        }
+        self.status |= 0b0010_0100;
+        self.pc = self.read_vector(RESET_VECTOR_ADDR);
+        self.cycles += 7;
+    }
+
+    /// Read a byte of data from memory. Every call is one bus cycle of the
+    /// CPU.
     pub fn read_byte(&mut self, address: u16) -> u8 {
-        let val = self.memory.get(address);
-        val
+        self.read_byte_as(address, AccessKind::Data)
+    }
+
+    /// Read a byte from memory as an access of the given kind.
+    pub fn read_byte_as(&mut self, address: u16, kind: AccessKind) -> u8 {
+        match self.replay.as_mut().map(|r| r.next(address, false)) {
+            Some(Access::Logged(value)) => value,
+            Some(Access::Overrun) => 0,
+            Some(Access::Live) | None => {
+                let mut value = self.memory.read(address, kind);
+                if let Some(io_port) = self.io_port_at(address) {
+                    value = io_port.read(address, self.cycles);
+                }
+                self.log_access(address, value);
+                self.watchpoints.check(address, value, false);
+                value
+            }
+        }
     }
 
+    /// Write a byte of data to memory. Every call is one bus cycle of the
+    /// CPU.
     pub fn write_byte(&mut self, address: u16, value: u8) {
-        self.memory.set(address, value);
+        self.write_byte_as(address, value, AccessKind::Data);
+    }
+
+    /// Write a byte to memory as an access of the given kind.
+    pub fn write_byte_as(&mut self, address: u16, value: u8, kind: AccessKind) {
+        match self.replay.as_mut().map(|r| r.next(address, true)) {
+            Some(Access::Logged(_)) | Some(Access::Overrun) => (),
+            Some(Access::Live) | None => {
+                let old = self.journal.is_some().then(|| self.memory.peek(address));
+                self.memory.write(address, value, kind);
+                let now = self.cycles;
+                if self.io_port_at(address).is_some() {
+                    self.update_io_port(|io_port| io_port.write(address, value, now));
+                }
+                if let (Some(journal), Some(old)) = (self.journal.as_mut(), old) {
+                    // What the write left in memory, which isn't `value` for
+                    // ROM
+                    let new = self.memory.peek(address);
+                    journal.push(MemoryWrite { address, old, new });
+                }
+                self.log_access(address, value);
+                self.watchpoints.check(address, value, true);
+            }
+        }
+    }
+
+    /// Start or stop keeping a journal of memory writes
+    pub(crate) fn set_journal(&mut self, enabled: bool) {
+        self.journal = enabled.then(Vec::new);
+    }
+
+    pub(crate) fn clear_journal(&mut self) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.clear();
+        }
+    }
+
+    pub(crate) fn take_journal(&mut self) -> Vec<MemoryWrite> {
+        self.journal
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Forget the bus cycles of an instruction that won't be completed
+    pub(crate) fn abandon_cycles(&mut self) {
+        self.replay = None;
+    }
+
+    fn log_access(&mut self, address: u16, value: u8) {
+        if let Some(replay) = self.replay.as_mut() {
+            replay.log.push(BusCycle { address, value });
+        }
+    }
+
+    /// Bus cycle whose value the CPU ignores
+    pub fn dummy_read(&mut self, address: u16) {
+        self.read_byte_as(address, AccessKind::Dummy);
+    }
+
+    /// Read a byte without it counting as a bus cycle or having side
+    /// effects, e.g. for tracing.
+    pub fn peek_byte(&self, address: u16) -> u8 {
+        match self.io_port_at(address) {
+            Some(io_port) => io_port.read(address, self.cycles),
+            None => self.memory.peek(address),
+        }
+    }
+
+    /// The I/O port if it is mapped at `address`
+    fn io_port_at(&self, address: u16) -> Option<&IoPort> {
+        self.io_port
+            .as_ref()
+            .filter(|_| matches!(address, io_port::DDR_ADDR | io_port::DATA_ADDR))
+    }
+
+    /// Read a word without it counting as bus cycles.
+    pub fn peek_word(&self, address: u16) -> u16 {
+        let low = self.peek_byte(address) as u16;
+        let high = self.peek_byte(address.wrapping_add(1)) as u16;
+        (high << 8) | low
     }
 
     pub fn read_word(&mut self, address: u16) -> u16 {
         let low = self.read_byte(address) as u16;
-        let high = self.read_byte(address + 1) as u16;
+        let high = self.read_byte(address.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    /// Read a word whose high byte comes from the same page as the low
+    /// byte, like the NMOS `JMP ($xxFF)` bug.
+    pub fn read_word_in_page(&mut self, address: u16) -> u16 {
+        let low = self.read_byte(address) as u16;
+        let high_address = (address & 0xFF00) | (address.wrapping_add(1) & 0x00FF);
+        let high = self.read_byte(high_address) as u16;
+        (high << 8) | low
+    }
+
+    /// Read a pointer from zero page. A pointer at $FF takes its high byte
+    /// from $00.
+    pub fn read_zero_page_word(&mut self, address: u8) -> u16 {
+        let low = self.read_byte(address as u16) as u16;
+        let high = self.read_byte(address.wrapping_add(1) as u16) as u16;
+        (high << 8) | low
+    }
+
+    /// Read an interrupt or reset vector
+    pub fn read_vector(&mut self, address: u16) -> u16 {
+        let low = self.read_byte_as(address, AccessKind::Vector) as u16;
+        let high = self.read_byte_as(address.wrapping_add(1), AccessKind::Vector) as u16;
         (high << 8) | low
     }
 
@@ -68,26 +415,33 @@ impl<T: Memory> CPUState<T> {
         let low = value as u8;
         let high = (value >> 8) as u8;
         self.write_byte(address, low);
-        self.write_byte(address + 1, high);
+        self.write_byte(address.wrapping_add(1), high);
+    }
+
+    /// Fetch the opcode at PC and increment PC.
+    pub fn fetch_opcode(&mut self) -> u8 {
+        let byte = self.read_byte_as(self.pc, AccessKind::Opcode);
+        self.pc = self.pc.wrapping_add(1);
+        byte
     }
 
-    /// Fetch the next byte at PC and increment PC.
+    /// Fetch the next operand byte at PC and increment PC.
     pub fn fetch_byte(&mut self) -> u8 {
-        let byte = self.read_byte(self.pc);
-        self.pc += 1;
+        let byte = self.read_byte_as(self.pc, AccessKind::Operand);
+        self.pc = self.pc.wrapping_add(1);
         byte
     }
 
-    /// Fetch the next word at PC and increment PC.
+    /// Fetch the next operand word at PC and increment PC.
     pub fn fetch_word(&mut self) -> u16 {
-        let word = self.read_word(self.pc);
-        self.pc += 2;
-        word
+        let low = self.fetch_byte() as u16;
+        let high = self.fetch_byte() as u16;
+        (high << 8) | low
     }
 
     /// Push a byte to stack
     pub fn push_byte(&mut self, value: u8) {
-        self.write_byte(STACK_PAGE + self.sp as u16, value);
+        self.write_byte_as(STACK_PAGE + self.sp as u16, value, AccessKind::Stack);
         self.sp = self.sp.wrapping_sub(1);
     }
 
@@ -99,10 +453,16 @@ impl<T: Memory> CPUState<T> {
         self.push_byte(low);
     }
 
+    /// Dummy read from the top of the stack. The 6502 does this while it
+    /// increments SP before pulling, or waits for the stack to be written.
+    pub fn read_stack(&mut self) {
+        self.dummy_read(STACK_PAGE + self.sp as u16);
+    }
+
     /// Pop a byte from stack
     pub fn pop_byte(&mut self) -> u8 {
         self.sp = self.sp.wrapping_add(1);
-        self.read_byte(0x100 + self.sp as u16)
+        self.read_byte_as(STACK_PAGE + self.sp as u16, AccessKind::Stack)
     }
 
     /// Pop a word from stack
@@ -204,17 +564,36 @@ impl<T: Memory> CPUState<T> {
         (self.status & 0b0000_1000) >> 3
     }
 
-    /// Resolve the effective address of an instruction.
-    pub fn resolve_address(&mut self, mode: AddressingMode) -> u16 {
-        match mode {
+    pub fn set_i(&mut self, value: u8) {
+        if value != 0 {
+            self.status |= 0b0000_0100;
+        } else {
+            self.status &= 0b1111_1011;
+        }
+    }
+
+    pub fn get_i(&self) -> u8 {
+        (self.status & 0b0000_0100) >> 2
+    }
+
+    /// Resolve the effective address of an instruction that writes to or
+    /// modifies memory. Indexed modes spend a cycle reading from the
+    /// address before the high byte is fixed up, whether or not the index
+    /// crossed a page.
+    pub fn resolve_address(&mut self, mode: AddressingMode) -> Result<u16, CpuError> {
+        Ok(match mode {
             AddressingMode::ZPG => self.fetch_byte() as u16,
             AddressingMode::ZPGX => {
                 let operand = self.fetch_byte();
+                // Dummy read while the index is added
+                self.dummy_read(operand as u16);
                 let address = operand.wrapping_add(self.x);
                 address as u16
             }
             AddressingMode::ZPGY => {
                 let operand = self.fetch_byte();
+                // Dummy read while the index is added
+                self.dummy_read(operand as u16);
                 let address = operand.wrapping_add(self.y);
                 address as u16
             }
@@ -222,99 +601,181 @@ impl<T: Memory> CPUState<T> {
             AddressingMode::ABSX => {
                 let operand = self.fetch_word();
                 let address = operand.wrapping_add(self.x as u16);
+                self.dummy_read(unfixed_address(operand, address));
                 address
             }
             AddressingMode::ABSY => {
                 let operand = self.fetch_word();
                 let address = operand.wrapping_add(self.y as u16);
+                self.dummy_read(unfixed_address(operand, address));
                 address
             }
             AddressingMode::IND => {
                 let indirect_address = self.fetch_word();
-                self.read_word(indirect_address)
+                self.read_word_in_page(indirect_address)
             }
             AddressingMode::XIND => {
                 let operand = self.fetch_byte();
+                // Dummy read while the index is added
+                self.dummy_read(operand as u16);
                 // Wraps around to stay in zero-page
-                let zero_page_address = (operand.wrapping_add(self.x)) as u8;
-                let effective_address = self.read_word(zero_page_address as u16);
-                effective_address
+                let zero_page_address = operand.wrapping_add(self.x);
+                self.read_zero_page_word(zero_page_address)
             }
             AddressingMode::INDY => {
                 let zero_page_address = self.fetch_byte();
-                let indirect_address = self.read_word(zero_page_address as u16);
-                let effective_address = indirect_address.wrapping_add(self.y as u16);
-                effective_address
+                let indirect_address = self.read_zero_page_word(zero_page_address);
+                let address = indirect_address.wrapping_add(self.y as u16);
+                self.dummy_read(unfixed_address(indirect_address, address));
+                address
+            }
+            AddressingMode::ZPIND => {
+                let zero_page_address = self.fetch_byte();
+                self.read_zero_page_word(zero_page_address)
+            }
+            AddressingMode::ABSXIND => {
+                let operand = self.fetch_word();
+                // Dummy read while the index is added
+                self.dummy_read(self.pc.wrapping_sub(1));
+                self.read_word(operand.wrapping_add(self.x as u16))
             }
             AddressingMode::REL => {
                 let unsigned_operand = self.fetch_byte();
                 let operand = unsigned_operand as i8;
                 self.pc.wrapping_add(operand as u16)
             }
-            _ => panic!("Unsupported addressing mode: {:?}", mode),
+            _ => return Err(CpuError::UnsupportedAddressingMode { mode }),
+        })
+    }
+
+    /// Indexed reads take an extra cycle when the index carries into the high
+    /// byte of the address. The 6502 spends that cycle reading from the
+    /// address with the high byte not yet fixed up.
+    pub(crate) fn page_cross_penalty(&mut self, base: u16, address: u16) {
+        if base & 0xFF00 != address & 0xFF00 {
+            self.dummy_read(unfixed_address(base, address));
+            self.increment_cycles(1);
         }
     }
 
-    /// Fetch the operand of an instruction.
-    pub fn fetch_operand(&mut self, mode: AddressingMode) -> u8 {
-        match mode {
+    /// Fetch the operand of an instruction. Indexed addressing modes add the
+    /// page crossing cycle to `cycles`.
+    pub fn fetch_operand(&mut self, mode: AddressingMode) -> Result<u8, CpuError> {
+        Ok(match mode {
             AddressingMode::ACC => self.get_a(),
             AddressingMode::IMM => self.fetch_byte(),
             AddressingMode::ZPG => {
-                let address = self.resolve_address(mode);
-                self.read_byte(address as u16)
+                let address = self.resolve_address(mode)?;
+                self.read_byte(address)
             }
             AddressingMode::ZPGX => {
-                let address = self.resolve_address(mode);
-                self.read_byte(address as u16)
+                let address = self.resolve_address(mode)?;
+                self.read_byte(address)
             }
             AddressingMode::ZPGY => {
-                let address = self.resolve_address(mode);
-                self.read_byte(address as u16)
+                let address = self.resolve_address(mode)?;
+                self.read_byte(address)
             }
             AddressingMode::ABS => {
-                let address = self.resolve_address(mode);
+                let address = self.resolve_address(mode)?;
                 self.read_byte(address)
             }
             AddressingMode::ABSX => {
-                let address = self.resolve_address(mode);
+                let base = self.fetch_word();
+                let address = base.wrapping_add(self.x as u16);
+                self.page_cross_penalty(base, address);
                 self.read_byte(address)
             }
             AddressingMode::ABSY => {
-                let address = self.resolve_address(mode);
+                let base = self.fetch_word();
+                let address = base.wrapping_add(self.y as u16);
+                self.page_cross_penalty(base, address);
                 self.read_byte(address)
             }
             AddressingMode::IND => {
-                let address = self.resolve_address(mode);
+                let address = self.resolve_address(mode)?;
                 self.read_byte(address)
             }
             AddressingMode::XIND => {
-                let address = self.resolve_address(mode);
+                let address = self.resolve_address(mode)?;
                 self.read_byte(address)
             }
             AddressingMode::INDY => {
-                let address = self.resolve_address(mode);
+                let zero_page_address = self.fetch_byte();
+                let base = self.read_zero_page_word(zero_page_address);
+                let address = base.wrapping_add(self.y as u16);
+                self.page_cross_penalty(base, address);
                 self.read_byte(address)
             }
-            _ => panic!("Unsupported addressing mode: {:?}", mode),
-        }
+            AddressingMode::ZPIND => {
+                let address = self.resolve_address(mode)?;
+                self.read_byte(address)
+            }
+            _ => return Err(CpuError::UnsupportedAddressingMode { mode }),
+        })
     }
 }
 
+/// Address formed by an indexed addressing mode before the carry from the
+/// low byte has been added to the high byte.
+pub(crate) fn unfixed_address(base: u16, address: u16) -> u16 {
+    (base & 0xFF00) | (address & 0x00FF)
+}
+
+#[cfg(test)]
 mod tests {
-    use crate::memory::PlainMemory;
+    use super::PowerOn;
+    use crate::memory::{Memory, MemoryMap, PlainMemory};
 
     #[test]
     fn test_reset() {
         let memory = PlainMemory::new();
-        let mut state = super::CPUState::new(memory);
+        let mut state = super::CPUState::with_power_on(memory, PowerOn::Pattern(0x42));
+        state.write_word(super::RESET_VECTOR_ADDR, 0x0600);
         state.reset();
-        assert_eq!(state.a, 0);
-        assert_eq!(state.x, 0);
-        assert_eq!(state.y, 0);
-        assert_eq!(state.pc, 0x0000);
-        assert_eq!(state.sp, 0xFF);
-        assert_eq!(state.status, 0x36);
+        assert_eq!(state.a, 0x42);
+        assert_eq!(state.x, 0x42);
+        assert_eq!(state.y, 0x42);
+        assert_eq!(state.pc, 0x0600);
+        assert_eq!(state.sp, 0x3F);
+        assert_eq!(state.status, 0x66);
+        assert_eq!(state.cycles, 7);
+        // The stack isn't written
+        assert_eq!(state.peek_byte(0x0142), 0x42);
+        assert_eq!(state.peek_byte(0x0141), 0x42);
+        assert_eq!(state.peek_byte(0x0140), 0x42);
+    }
+
+    #[test]
+    fn test_power_on() {
+        let random =
+            |seed| super::CPUState::with_power_on(PlainMemory::new(), PowerOn::Random(seed));
+        let first = random(1);
+        assert_eq!(first.registers(), random(1).registers());
+        assert_eq!(
+            first.get_memory().save_state(),
+            random(1).get_memory().save_state()
+        );
+        assert_ne!(
+            first.get_memory().save_state(),
+            random(2).get_memory().save_state()
+        );
+
+        // ROM keeps its contents
+        let memory = MemoryMap::builder()
+            .ram(0x0000..=0x7FFF)
+            .rom(0x8000, &[0xEA; 0x8000])
+            .build()
+            .unwrap();
+        let state = super::CPUState::with_power_on(memory, PowerOn::Pattern(0xFF));
+        assert_eq!(state.peek_byte(0x1234), 0xFF);
+        assert_eq!(state.peek_byte(0x8000), 0xEA);
+
+        let state = super::CPUState::with_power_on(PlainMemory::new(), PowerOn::Zeroed);
+        assert_eq!(
+            state.registers(),
+            super::CPUState::new(PlainMemory::new()).registers()
+        );
     }
 
     #[test]
@@ -0,0 +1,22 @@
+//! Fixtures shared by the unit tests
+
+use crate::cpu::{Variant, CPU};
+use crate::memory::{Memory, PlainMemory};
+use crate::state::{CPUState, RESET_VECTOR_ADDR};
+
+/// NMOS 6502 that has been reset into `program`, loaded at `address`
+pub(crate) fn cpu_with_program(program: &[u8], address: u16) -> CPU<PlainMemory> {
+    cpu_with_variant(program, address, Variant::MOS6502)
+}
+
+/// Like `cpu_with_program()`, emulating `variant`
+pub(crate) fn cpu_with_variant(program: &[u8], address: u16, variant: Variant) -> CPU<PlainMemory> {
+    let mut memory = PlainMemory::new();
+    for (i, byte) in program.iter().enumerate() {
+        memory.poke(address.wrapping_add(i as u16), *byte);
+    }
+    let mut cpu_state = CPUState::new(memory);
+    cpu_state.write_word(RESET_VECTOR_ADDR, address);
+    cpu_state.reset();
+    CPU::with_variant(cpu_state, variant)
+}
@@ -0,0 +1,780 @@
+//! MOS 6522 Versatile Interface Adapter: two 8-bit ports with data direction
+//! registers, the CA1/CA2/CB1/CB2 handshake lines, two 16-bit timers, a shift
+//! register and interrupt flag/enable registers.
+//!
+//! `Via` implements `MappedDevice` for its 16 registers and `Device` for its
+//! timing, so it is usually shared between a `MemoryMap` and a `Scheduler`.
+//! The peripheral side is driven through `set_port_a()`, `set_ca1()` etc. and
+//! read back through `port_a()`, `ca2()` etc.
+
+use crate::device::Device;
+use crate::memory::MappedDevice;
+use crate::savestate::SaveStateError;
+
+/// Register offsets
+pub const ORB: u16 = 0x0;
+pub const ORA: u16 = 0x1;
+pub const DDRB: u16 = 0x2;
+pub const DDRA: u16 = 0x3;
+pub const T1CL: u16 = 0x4;
+pub const T1CH: u16 = 0x5;
+pub const T1LL: u16 = 0x6;
+pub const T1LH: u16 = 0x7;
+pub const T2CL: u16 = 0x8;
+pub const T2CH: u16 = 0x9;
+pub const SR: u16 = 0xA;
+pub const ACR: u16 = 0xB;
+pub const PCR: u16 = 0xC;
+pub const IFR: u16 = 0xD;
+pub const IER: u16 = 0xE;
+pub const ORA_NO_HANDSHAKE: u16 = 0xF;
+
+/// Interrupt flag and enable bits
+pub const IRQ_CA2: u8 = 0x01;
+pub const IRQ_CA1: u8 = 0x02;
+pub const IRQ_SR: u8 = 0x04;
+pub const IRQ_CB2: u8 = 0x08;
+pub const IRQ_CB1: u8 = 0x10;
+pub const IRQ_T2: u8 = 0x20;
+pub const IRQ_T1: u8 = 0x40;
+
+/// Length of the save state of a `Via`
+const STATE_LEN: usize = 32;
+
+/// Port A or B with its handshake lines
+#[derive(Clone, Debug)]
+struct Port {
+    output: u8,
+    ddr: u8,
+    /// Levels driven on the pins from outside
+    input: u8,
+    /// Input value latched on a C1 edge
+    latch: u8,
+    c1: bool,
+    /// C2 level driven from outside when it is an input
+    c2_input: bool,
+    /// C2 level when it is an output
+    c2_output: bool,
+    /// C2 returns high on the next cycle after a pulse
+    c2_pulse: bool,
+}
+
+impl Port {
+    fn new() -> Port {
+        Port {
+            output: 0,
+            ddr: 0,
+            input: 0xFF,
+            latch: 0xFF,
+            c1: true,
+            c2_input: true,
+            c2_output: true,
+            c2_pulse: false,
+        }
+    }
+
+    /// Pin levels: outputs driven by the VIA, inputs from outside
+    fn pins(&self) -> u8 {
+        (self.output & self.ddr) | (self.input & !self.ddr)
+    }
+}
+
+/// MOS 6522 VIA
+#[derive(Clone, Debug)]
+pub struct Via {
+    a: Port,
+    b: Port,
+    t1_counter: u16,
+    t1_latch: u16,
+    /// T1 interrupts on its next time-out
+    t1_armed: bool,
+    /// T1 reloads from the latch on the next cycle
+    t1_reload: bool,
+    /// PB7 output level driven by T1
+    t1_pb7: bool,
+    t2_counter: u16,
+    t2_latch_low: u8,
+    t2_armed: bool,
+    shift: u8,
+    /// Bits shifted since the shift register was last accessed
+    shift_count: u8,
+    /// Cycles until the next shift clock edge in T2 controlled modes
+    shift_timer: u16,
+    /// CB1 level driven by the shift clock
+    shift_clock: bool,
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+}
+
+impl Default for Via {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Via {
+    pub fn new() -> Via {
+        Via {
+            a: Port::new(),
+            b: Port::new(),
+            t1_counter: 0xFFFF,
+            t1_latch: 0xFFFF,
+            t1_armed: false,
+            t1_reload: false,
+            t1_pb7: true,
+            t2_counter: 0xFFFF,
+            t2_latch_low: 0xFF,
+            t2_armed: false,
+            shift: 0,
+            shift_count: 8,
+            shift_timer: 0,
+            shift_clock: true,
+            acr: 0,
+            pcr: 0,
+            ifr: 0,
+            ier: 0,
+        }
+    }
+
+    /// Hardware reset. Clears the port, control and interrupt registers but
+    /// not the timers or the shift register.
+    pub fn reset(&mut self) {
+        for port in [&mut self.a, &mut self.b] {
+            port.output = 0;
+            port.ddr = 0;
+            port.c2_output = true;
+            port.c2_pulse = false;
+        }
+        self.t1_armed = false;
+        self.t1_reload = false;
+        self.t2_armed = false;
+        self.shift_count = 8;
+        self.acr = 0;
+        self.pcr = 0;
+        self.ifr = 0;
+        self.ier = 0;
+    }
+
+    /// Drive the port A input pins
+    pub fn set_port_a(&mut self, value: u8) {
+        self.a.input = value;
+    }
+
+    /// Drive the port B input pins. A falling edge on PB6 counts down timer
+    /// 2 in pulse counting mode.
+    pub fn set_port_b(&mut self, value: u8) {
+        let falling = self.b.input & !value & 0x40 != 0;
+        self.b.input = value;
+        if falling && self.acr & 0x20 != 0 {
+            self.t2_counter = self.t2_counter.wrapping_sub(1);
+            if self.t2_counter == 0xFFFF && self.t2_armed {
+                self.ifr |= IRQ_T2;
+                self.t2_armed = false;
+            }
+        }
+    }
+
+    /// Levels on the port A pins
+    pub fn port_a(&self) -> u8 {
+        self.a.pins()
+    }
+
+    /// Levels on the port B pins, including PB7 when driven by timer 1
+    pub fn port_b(&self) -> u8 {
+        let pins = self.b.pins();
+        if self.acr & 0x80 != 0 {
+            (pins & 0x7F) | ((self.t1_pb7 as u8) << 7)
+        } else {
+            pins
+        }
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        if self.a.c1 == level {
+            return;
+        }
+        self.a.c1 = level;
+        if level == (self.pcr & 0x01 != 0) {
+            self.ifr |= IRQ_CA1;
+            if self.acr & 0x01 != 0 {
+                self.a.latch = self.a.pins();
+            }
+            if (self.pcr >> 1) & 0x07 == 0b100 {
+                self.a.c2_output = true;
+            }
+        }
+    }
+
+    pub fn set_ca2(&mut self, level: bool) {
+        let control = (self.pcr >> 1) & 0x07;
+        let previous = self.a.c2_input;
+        self.a.c2_input = level;
+        if control & 0b100 == 0 && previous != level && level == (control & 0b010 != 0) {
+            self.ifr |= IRQ_CA2;
+        }
+    }
+
+    /// CB1 is also the external clock of the shift register
+    pub fn set_cb1(&mut self, level: bool) {
+        if self.b.c1 == level {
+            return;
+        }
+        self.b.c1 = level;
+        if level && self.shift_mode() & 0b011 == 0b011 {
+            self.shift_bit();
+        }
+        if level == (self.pcr & 0x10 != 0) {
+            self.ifr |= IRQ_CB1;
+            if self.acr & 0x02 != 0 {
+                self.b.latch = self.b.pins();
+            }
+            if (self.pcr >> 5) & 0x07 == 0b100 {
+                self.b.c2_output = true;
+            }
+        }
+    }
+
+    /// CB2 is also the serial input of the shift register
+    pub fn set_cb2(&mut self, level: bool) {
+        let control = (self.pcr >> 5) & 0x07;
+        let previous = self.b.c2_input;
+        self.b.c2_input = level;
+        if control & 0b100 == 0 && previous != level && level == (control & 0b010 != 0) {
+            self.ifr |= IRQ_CB2;
+        }
+    }
+
+    /// CA2 level when it is an output, otherwise the level driven on it
+    pub fn ca2(&self) -> bool {
+        if self.pcr & 0x08 != 0 {
+            self.a.c2_output
+        } else {
+            self.a.c2_input
+        }
+    }
+
+    /// CB2 level when it is an output or shifts data out, otherwise the
+    /// level driven on it
+    pub fn cb2(&self) -> bool {
+        if self.shift_mode() & 0b100 != 0 || self.pcr & 0x80 != 0 {
+            self.b.c2_output
+        } else {
+            self.b.c2_input
+        }
+    }
+
+    /// CB1 level, which is the shift clock output in the internally clocked
+    /// shift modes
+    pub fn cb1(&self) -> bool {
+        match self.shift_mode() {
+            0b000 | 0b011 | 0b111 => self.b.c1,
+            _ => self.shift_clock,
+        }
+    }
+
+    fn shift_mode(&self) -> u8 {
+        (self.acr >> 2) & 0x07
+    }
+
+    fn irq_active(&self) -> bool {
+        self.ifr & self.ier & 0x7F != 0
+    }
+
+    /// Reading or writing ORA/ORB clears the C1 flag and the C2 flag unless
+    /// C2 is an independent input, and starts handshakes.
+    fn port_a_access(&mut self) {
+        let control = (self.pcr >> 1) & 0x07;
+        self.ifr &= !IRQ_CA1;
+        if control & 0b101 != 0b001 {
+            self.ifr &= !IRQ_CA2;
+        }
+        match control {
+            0b100 => self.a.c2_output = false,
+            0b101 => {
+                self.a.c2_output = false;
+                self.a.c2_pulse = true;
+            }
+            _ => (),
+        }
+    }
+
+    fn port_b_access(&mut self, write: bool) {
+        let control = (self.pcr >> 5) & 0x07;
+        self.ifr &= !IRQ_CB1;
+        if control & 0b101 != 0b001 {
+            self.ifr &= !IRQ_CB2;
+        }
+        // Port B handshakes only on writes
+        if write {
+            match control {
+                0b100 => self.b.c2_output = false,
+                0b101 => {
+                    self.b.c2_output = false;
+                    self.b.c2_pulse = true;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn read_port_a(&self) -> u8 {
+        if self.acr & 0x01 != 0 {
+            self.a.latch
+        } else {
+            self.a.pins()
+        }
+    }
+
+    /// Output bits read back the output register, input bits the pins
+    fn read_port_b(&self) -> u8 {
+        let input = if self.acr & 0x02 != 0 {
+            self.b.latch
+        } else {
+            self.b.input
+        };
+        let value = (self.b.output & self.b.ddr) | (input & !self.b.ddr);
+        if self.acr & 0x80 != 0 {
+            (value & 0x7F) | ((self.t1_pb7 as u8) << 7)
+        } else {
+            value
+        }
+    }
+
+    fn restart_shift(&mut self) {
+        self.ifr &= !IRQ_SR;
+        self.shift_count = 0;
+        self.shift_timer = self.t2_latch_low as u16 + 1;
+    }
+
+    fn shift_bit(&mut self) {
+        let mode = self.shift_mode();
+        if mode == 0 || (self.shift_count >= 8 && mode != 0b100) {
+            return;
+        }
+        if mode & 0b100 != 0 {
+            self.b.c2_output = self.shift & 0x80 != 0;
+            self.shift = self.shift.rotate_left(1);
+        } else {
+            self.shift = (self.shift << 1) | self.b.c2_input as u8;
+        }
+        if mode == 0b100 {
+            // Free-running output recirculates without interrupting
+            return;
+        }
+        self.shift_count += 1;
+        if self.shift_count == 8 {
+            self.ifr |= IRQ_SR;
+        }
+    }
+
+    /// Advance by one clock cycle
+    fn cycle(&mut self) {
+        for port in [&mut self.a, &mut self.b] {
+            if port.c2_pulse {
+                port.c2_pulse = false;
+                port.c2_output = true;
+            }
+        }
+
+        if self.t1_reload {
+            self.t1_reload = false;
+            self.t1_counter = self.t1_latch;
+        } else {
+            self.t1_counter = self.t1_counter.wrapping_sub(1);
+            if self.t1_counter == 0xFFFF && self.t1_armed {
+                self.ifr |= IRQ_T1;
+                if self.acr & 0x40 != 0 {
+                    // Free-running: reload and keep interrupting
+                    self.t1_reload = true;
+                    self.t1_pb7 = !self.t1_pb7;
+                } else {
+                    self.t1_armed = false;
+                    self.t1_pb7 = true;
+                }
+            }
+        }
+
+        if self.acr & 0x20 == 0 {
+            self.t2_counter = self.t2_counter.wrapping_sub(1);
+            if self.t2_counter == 0xFFFF && self.t2_armed {
+                self.ifr |= IRQ_T2;
+                self.t2_armed = false;
+            }
+        }
+
+        match self.shift_mode() {
+            0b001 | 0b100 | 0b101 => {
+                // Shift clock toggles every time the low byte of T2 runs out
+                if self.shift_timer == 0 {
+                    self.shift_timer = self.t2_latch_low as u16 + 1;
+                    self.toggle_shift_clock();
+                } else {
+                    self.shift_timer -= 1;
+                }
+            }
+            0b010 | 0b110 => self.toggle_shift_clock(),
+            _ => (),
+        }
+    }
+
+    fn toggle_shift_clock(&mut self) {
+        let stopped = self.shift_count >= 8 && self.shift_mode() != 0b100;
+        if stopped {
+            self.shift_clock = true;
+            return;
+        }
+        self.shift_clock = !self.shift_clock;
+        if self.shift_clock {
+            self.shift_bit();
+        }
+    }
+
+    /// Register value without the side effects of reading it
+    fn register(&self, offset: u16) -> u8 {
+        match offset & 0x0F {
+            ORB => self.read_port_b(),
+            ORA | ORA_NO_HANDSHAKE => self.read_port_a(),
+            DDRB => self.b.ddr,
+            DDRA => self.a.ddr,
+            T1CL => self.t1_counter as u8,
+            T1CH => (self.t1_counter >> 8) as u8,
+            T1LL => self.t1_latch as u8,
+            T1LH => (self.t1_latch >> 8) as u8,
+            T2CL => self.t2_counter as u8,
+            T2CH => (self.t2_counter >> 8) as u8,
+            SR => self.shift,
+            ACR => self.acr,
+            PCR => self.pcr,
+            IFR => self.ifr | ((self.irq_active() as u8) << 7),
+            IER => self.ier | 0x80,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl MappedDevice for Via {
+    fn read(&mut self, offset: u16) -> u8 {
+        let value = self.register(offset);
+        match offset & 0x0F {
+            ORB => self.port_b_access(false),
+            ORA => self.port_a_access(),
+            T1CL => self.ifr &= !IRQ_T1,
+            T2CL => self.ifr &= !IRQ_T2,
+            SR => self.restart_shift(),
+            _ => (),
+        }
+        value
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        match offset & 0x0F {
+            ORB => {
+                self.b.output = value;
+                self.port_b_access(true);
+            }
+            ORA => {
+                self.a.output = value;
+                self.port_a_access();
+            }
+            ORA_NO_HANDSHAKE => self.a.output = value,
+            DDRB => self.b.ddr = value,
+            DDRA => self.a.ddr = value,
+            T1CL | T1LL => self.t1_latch = (self.t1_latch & 0xFF00) | value as u16,
+            T1CH => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | ((value as u16) << 8);
+                self.t1_counter = self.t1_latch;
+                self.t1_reload = false;
+                self.t1_armed = true;
+                self.t1_pb7 = false;
+                self.ifr &= !IRQ_T1;
+            }
+            T1LH => {
+                self.t1_latch = (self.t1_latch & 0x00FF) | ((value as u16) << 8);
+                self.ifr &= !IRQ_T1;
+            }
+            T2CL => self.t2_latch_low = value,
+            T2CH => {
+                self.t2_counter = ((value as u16) << 8) | self.t2_latch_low as u16;
+                self.t2_armed = true;
+                self.ifr &= !IRQ_T2;
+            }
+            SR => {
+                self.shift = value;
+                self.restart_shift();
+            }
+            ACR => self.acr = value,
+            PCR => {
+                self.pcr = value;
+                // Manual output modes drive C2 right away
+                match (value >> 1) & 0x07 {
+                    0b110 => self.a.c2_output = false,
+                    0b111 => self.a.c2_output = true,
+                    _ => (),
+                }
+                match (value >> 5) & 0x07 {
+                    0b110 => self.b.c2_output = false,
+                    0b111 => self.b.c2_output = true,
+                    _ => (),
+                }
+            }
+            IFR => self.ifr &= !(value & 0x7F),
+            IER => {
+                if value & 0x80 != 0 {
+                    self.ier |= value & 0x7F;
+                } else {
+                    self.ier &= !(value & 0x7F);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.register(offset)
+    }
+
+    fn name(&self) -> &'static str {
+        "6522 VIA"
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_LEN);
+        for port in [&self.a, &self.b] {
+            state.extend_from_slice(&[port.output, port.ddr, port.input, port.latch]);
+            state.push(
+                port.c1 as u8
+                    | (port.c2_input as u8) << 1
+                    | (port.c2_output as u8) << 2
+                    | (port.c2_pulse as u8) << 3,
+            );
+        }
+        state.extend_from_slice(&self.t1_counter.to_le_bytes());
+        state.extend_from_slice(&self.t1_latch.to_le_bytes());
+        state.push(
+            self.t1_armed as u8
+                | (self.t1_reload as u8) << 1
+                | (self.t1_pb7 as u8) << 2
+                | (self.t2_armed as u8) << 3
+                | (self.shift_clock as u8) << 4,
+        );
+        state.extend_from_slice(&self.t2_counter.to_le_bytes());
+        state.push(self.t2_latch_low);
+        state.extend_from_slice(&[self.shift, self.shift_count]);
+        state.extend_from_slice(&self.shift_timer.to_le_bytes());
+        state.extend_from_slice(&[self.acr, self.pcr, self.ifr, self.ier]);
+        state.resize(STATE_LEN, 0);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        if state.len() != STATE_LEN {
+            return Err(SaveStateError::Invalid(format!(
+                "expected {} bytes of VIA state, got {}",
+                STATE_LEN,
+                state.len()
+            )));
+        }
+        for (port, bytes) in [&mut self.a, &mut self.b].into_iter().zip(state.chunks(5)) {
+            port.output = bytes[0];
+            port.ddr = bytes[1];
+            port.input = bytes[2];
+            port.latch = bytes[3];
+            port.c1 = bytes[4] & 0x01 != 0;
+            port.c2_input = bytes[4] & 0x02 != 0;
+            port.c2_output = bytes[4] & 0x04 != 0;
+            port.c2_pulse = bytes[4] & 0x08 != 0;
+        }
+        let word = |index: usize| u16::from_le_bytes([state[index], state[index + 1]]);
+        self.t1_counter = word(10);
+        self.t1_latch = word(12);
+        self.t1_armed = state[14] & 0x01 != 0;
+        self.t1_reload = state[14] & 0x02 != 0;
+        self.t1_pb7 = state[14] & 0x04 != 0;
+        self.t2_armed = state[14] & 0x08 != 0;
+        self.shift_clock = state[14] & 0x10 != 0;
+        self.t2_counter = word(15);
+        self.t2_latch_low = state[17];
+        self.shift = state[18];
+        self.shift_count = state[19];
+        self.shift_timer = word(20);
+        self.acr = state[22];
+        self.pcr = state[23];
+        self.ifr = state[24];
+        self.ier = state[25];
+        Ok(())
+    }
+}
+
+impl Device for Via {
+    fn tick(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.cycle();
+        }
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        let t1 = (self.t1_armed && !self.t1_reload).then_some(self.t1_counter as u64 + 1);
+        let t2 = (self.t2_armed && self.acr & 0x20 == 0).then_some(self.t2_counter as u64 + 1);
+        let shifting =
+            self.shift_mode() != 0 && (self.shift_count < 8 || self.shift_mode() == 0b100);
+        let shift = shifting.then_some(self.shift_timer as u64 + 1);
+        let pulse = (self.a.c2_pulse || self.b.c2_pulse || self.t1_reload).then_some(1);
+        [t1, t2, shift, pulse].into_iter().flatten().min()
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_active()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timer1() {
+        let mut via = Via::new();
+        via.write(IER, 0x80 | IRQ_T1);
+        via.write(T1CL, 10);
+        via.write(T1CH, 0);
+        assert_eq!(via.next_event(), Some(11));
+
+        via.tick(10);
+        assert!(!via.irq());
+        assert_eq!(via.read(T1CL), 0);
+        via.tick(1);
+        assert!(via.irq());
+        assert_eq!(via.peek(IFR), 0x80 | IRQ_T1);
+        via.read(T1CL);
+        assert!(!via.irq());
+
+        // One-shot doesn't interrupt again
+        via.tick(0x10010);
+        assert!(!via.irq());
+
+        // Free-running with PB7 toggling every N + 2 cycles
+        via.write(ACR, 0xC0);
+        via.write(T1CH, 0);
+        assert_eq!(via.port_b() & 0x80, 0);
+        via.tick(11);
+        assert!(via.irq());
+        assert_eq!(via.port_b() & 0x80, 0x80);
+        via.write(IFR, IRQ_T1);
+        via.tick(12);
+        assert!(via.irq());
+        assert_eq!(via.port_b() & 0x80, 0);
+    }
+
+    #[test]
+    fn test_timer2() {
+        let mut via = Via::new();
+        via.write(IER, 0x80 | IRQ_T2);
+        via.write(T2CL, 5);
+        via.write(T2CH, 0);
+        via.tick(6);
+        assert!(via.irq());
+        assert_eq!(via.read(T2CL), 0xFF);
+        assert!(!via.irq());
+
+        // Pulse counting on PB6
+        via.write(ACR, 0x20);
+        via.write(T2CL, 2);
+        via.write(T2CH, 0);
+        via.tick(100);
+        for _ in 0..2 {
+            via.set_port_b(0xBF);
+            via.set_port_b(0xFF);
+        }
+        assert!(!via.irq());
+        via.set_port_b(0xBF);
+        assert!(via.irq());
+    }
+
+    #[test]
+    fn test_ports_and_handshake() {
+        let mut via = Via::new();
+        via.write(DDRA, 0x0F);
+        via.write(ORA, 0x55);
+        via.set_port_a(0xA0);
+        assert_eq!(via.port_a(), 0xA5);
+        assert_eq!(via.read(ORA), 0xA5);
+
+        via.write(DDRB, 0xF0);
+        via.write(ORB, 0x3C);
+        via.set_port_b(0x0A);
+        assert_eq!(via.read(ORB), 0x3A);
+
+        // CA1 positive edge, CA2 read handshake output
+        via.write(PCR, 0x09);
+        via.write(IER, 0x80 | IRQ_CA1);
+        via.set_ca1(false);
+        assert!(!via.irq());
+        via.set_ca1(true);
+        assert!(via.irq());
+        assert!(via.ca2());
+        via.read(ORA);
+        assert!(!via.irq());
+        assert!(!via.ca2());
+        via.set_ca1(false);
+        via.set_ca1(true);
+        assert!(via.ca2());
+
+        // No handshake through register F
+        via.read(ORA_NO_HANDSHAKE);
+        assert!(via.irq());
+
+        // CB2 pulse output on writes to ORB
+        via.write(PCR, 0xA0);
+        via.write(ORB, 0);
+        assert!(!via.cb2());
+        via.tick(1);
+        assert!(via.cb2());
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut via = Via::new();
+        via.write(IER, 0x80 | IRQ_SR);
+        // Shift out under phi2
+        via.write(ACR, 0x18);
+        via.write(SR, 0b1010_0110);
+        let mut bits = 0u8;
+        while !via.irq() {
+            let clock = via.cb1();
+            via.tick(1);
+            if !clock && via.cb1() {
+                bits = (bits << 1) | via.cb2() as u8;
+            }
+        }
+        assert_eq!(bits, 0b1010_0110);
+
+        // Shift in under external clock
+        via.write(ACR, 0x0C);
+        via.read(SR);
+        for bit in [1, 0, 0, 1, 1, 1, 0, 1] {
+            via.set_cb2(bit == 1);
+            via.set_cb1(false);
+            via.set_cb1(true);
+        }
+        assert!(via.irq());
+        assert_eq!(via.read(SR), 0b1001_1101);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut via = Via::new();
+        via.write(T1CL, 0x34);
+        via.write(T1CH, 0x12);
+        via.write(ACR, 0x40);
+        via.tick(3);
+        let state = via.save_state();
+
+        let mut restored = Via::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.peek(T1CL), 0x31);
+        assert_eq!(restored.peek(T1CH), 0x12);
+        assert_eq!(restored.peek(ACR), 0x40);
+    }
+}